@@ -0,0 +1,55 @@
+//! Rough per-sample costs of the DSP primitives and output calibration on
+//! the host, against the plain f32 equivalents.
+//!
+//! cargo run --release --example bench
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use common::calibration::OutputCalibration;
+use common::dsp::{exp2, sine, volts_to_dac, Phase, ONE_VOLT};
+
+const SAMPLES: u32 = 10_000_000;
+
+fn bench(name: &str, mut f: impl FnMut(u32) -> i64) {
+    let start = Instant::now();
+    let mut sum = 0i64;
+    for i in 0..SAMPLES {
+        sum = sum.wrapping_add(f(black_box(i)));
+    }
+    let elapsed = start.elapsed();
+    black_box(sum);
+    println!(
+        "{:<16} {:>6.2} ns/sample",
+        name,
+        elapsed.as_nanos() as f64 / SAMPLES as f64
+    );
+}
+
+fn main() {
+    let mut phase = Phase::new(440.0, 48_000);
+    bench("phase + sine", |_| {
+        phase.tick();
+        sine(phase.phase()) as i64
+    });
+
+    let mut t = 0.0f32;
+    bench("f32 sin", |_| {
+        t += 440.0 / 48_000.0;
+        (t * core::f32::consts::TAU).sin() as i64
+    });
+
+    bench("exp2", |i| exp2((i % (8 * ONE_VOLT as u32)) as i32) as i64);
+    bench("f32 exp2", |i| (i as f32 / 1_000_000.0).exp2() as i64);
+
+    bench("volts_to_dac", |i| volts_to_dac(i as i32 - i32::MAX / 2) as i64);
+    let calibration = OutputCalibration::default();
+    bench("volts_to_code", |i| {
+        let volts = i as f32 / SAMPLES as f32 * 20.0 - 10.0;
+        calibration.volts_to_code(volts) as i64
+    });
+    bench("f32 to code", |i| {
+        let volts = i as f32 / SAMPLES as f32 * 20.0 - 10.0;
+        ((volts + 10.0) / 20.0 * 65535.0).clamp(0.0, 65535.0) as i64
+    });
+}
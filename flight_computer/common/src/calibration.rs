@@ -0,0 +1,561 @@
+//! Output calibration for the two DAC8565s and the CV input ADC.
+//!
+//! Every output is trimmed against a meter at a handful of reference
+//! voltages. The trimmed DAC codes form a piecewise-linear table which
+//! `volts_to_code` interpolates between, so modes only ever deal in volts.
+//!
+//! Inputs are sampled at two known voltages, which fixes a gain and offset
+//! for converting ADC codes back to volts.
+
+use crate::dsp::{self, Q16};
+
+pub const NUM_OUTPUTS: usize = 8;
+
+/// Voltages each output is trimmed at, lowest first.
+pub const REFERENCE_VOLTS: [f32; 5] = [-8.0, -4.0, 0.0, 4.0, 8.0];
+pub const NUM_POINTS: usize = REFERENCE_VOLTS.len();
+
+/// Nominal output range of an uncalibrated channel.
+pub const MIN_VOLTS: f32 = -10.0;
+pub const MAX_VOLTS: f32 = 10.0;
+pub const MAX_CODE: u16 = u16::MAX;
+
+pub const NUM_INPUTS: usize = 3;
+
+/// Voltages patched into each input while calibrating it.
+pub const INPUT_REFERENCE_VOLTS: [f32; 2] = [1.0, 3.0];
+pub const MAX_ADC: u16 = 4095;
+
+/// Smallest spread of ADC codes between the two reference voltages that is
+/// accepted, anything closer means the input was not patched.
+const MIN_INPUT_SPAN: f32 = 64.0;
+
+const DAC_MAGIC: [u8; 4] = *b"DCAL";
+const CV_MAGIC: [u8; 4] = *b"ICAL";
+const VERSION: u16 = 1;
+// Magic and version ahead of the payload, checksum behind it
+const HEADER_SIZE: usize = 6;
+const STORAGE_OVERHEAD: usize = HEADER_SIZE + 2;
+
+/// Code an ideal DAC and output stage would need for `volts`.
+pub fn nominal_code(volts: f32) -> u16 {
+    dsp::volts_to_dac(dsp::to_q16(volts))
+}
+
+fn reference_q16(point: usize) -> Q16 {
+    REFERENCE_VOLTS[point] as Q16 * dsp::ONE_VOLT
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputCalibration {
+    codes: [u16; NUM_POINTS],
+}
+
+impl Default for OutputCalibration {
+    fn default() -> Self {
+        OutputCalibration {
+            codes: REFERENCE_VOLTS.map(nominal_code),
+        }
+    }
+}
+
+impl OutputCalibration {
+    /// Trimmed code for `REFERENCE_VOLTS[point]`.
+    pub fn code(&self, point: usize) -> u16 {
+        self.codes[point]
+    }
+
+    pub fn trim(&mut self, point: usize, delta: i32) {
+        let code = self.codes[point] as i32 + delta;
+        self.codes[point] = code.clamp(0, MAX_CODE as i32) as u16;
+    }
+
+    pub fn volts_to_code(&self, volts: f32) -> u16 {
+        let volts = dsp::to_q16(volts);
+        // Pick the segment containing `volts`, the end segments extrapolate
+        let mut seg = 0;
+        while seg < NUM_POINTS - 2 && volts > reference_q16(seg + 1) {
+            seg += 1;
+        }
+
+        // Interpolate the volts to ask a nominal DAC for, so the code is
+        // only rounded once, by `volts_to_dac`
+        let (v0, v1) = (reference_q16(seg) as i64, reference_q16(seg + 1) as i64);
+        let d0 = dsp::dac_to_volts(self.codes[seg]) as i64;
+        let d1 = dsp::dac_to_volts(self.codes[seg + 1]) as i64;
+        let span = v1 - v0;
+        let asked = d0 + ((volts as i64 - v0) * (d1 - d0) * 2 + span).div_euclid(2 * span);
+        dsp::volts_to_dac(asked.clamp(Q16::MIN as i64, Q16::MAX as i64) as Q16)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DacCalibration {
+    outputs: [OutputCalibration; NUM_OUTPUTS],
+}
+
+impl DacCalibration {
+    pub const STORED_SIZE: usize = STORAGE_OVERHEAD + NUM_OUTPUTS * NUM_POINTS * 2;
+
+    pub fn output(&self, channel: usize) -> &OutputCalibration {
+        &self.outputs[channel]
+    }
+
+    pub fn output_mut(&mut self, channel: usize) -> &mut OutputCalibration {
+        &mut self.outputs[channel]
+    }
+
+    pub fn volts_to_code(&self, channel: usize, volts: f32) -> u16 {
+        self.outputs[channel].volts_to_code(volts)
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::STORED_SIZE] {
+        let mut bytes = [0u8; Self::STORED_SIZE];
+        let codes = self.outputs.iter().flat_map(|out| out.codes.iter());
+        for (chunk, code) in payload_mut(&mut bytes).chunks_exact_mut(2).zip(codes) {
+            chunk.copy_from_slice(&code.to_le_bytes());
+        }
+        seal(&mut bytes, DAC_MAGIC);
+        bytes
+    }
+
+    /// Parse a table written by `to_bytes`, `None` if it is missing or corrupt.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let payload = unseal(bytes.get(..Self::STORED_SIZE)?, DAC_MAGIC)?;
+
+        let mut cal = DacCalibration::default();
+        let codes = cal.outputs.iter_mut().flat_map(|out| out.codes.iter_mut());
+        for (code, chunk) in codes.zip(payload.chunks_exact(2)) {
+            *code = u16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+        Some(cal)
+    }
+}
+
+/// Code an ideal input stage and ADC would read for `volts`.
+pub fn nominal_adc_code(volts: f32) -> u16 {
+    let code = (volts - MIN_VOLTS) / (MAX_VOLTS - MIN_VOLTS) * MAX_ADC as f32;
+    if code <= 0.0 {
+        0
+    } else if code >= MAX_ADC as f32 {
+        MAX_ADC
+    } else {
+        (code + 0.5) as u16
+    }
+}
+
+/// Running mean of the ADC codes captured at one reference voltage.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SampleSet {
+    sum: u32,
+    count: u32,
+}
+
+impl SampleSet {
+    pub fn push(&mut self, code: u16) {
+        self.sum += code as u32;
+        self.count += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn mean(&self) -> Option<f32> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.sum as f32 / self.count as f32)
+        }
+    }
+}
+
+impl FromIterator<u16> for SampleSet {
+    fn from_iter<I: IntoIterator<Item = u16>>(codes: I) -> Self {
+        let mut set = SampleSet::default();
+        codes.into_iter().for_each(|code| set.push(code));
+        set
+    }
+}
+
+/// Linear map from ADC code to volts, `volts = code * gain + offset`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputCalibration {
+    gain: f32,
+    offset: f32,
+}
+
+impl Default for InputCalibration {
+    fn default() -> Self {
+        InputCalibration {
+            gain: (MAX_VOLTS - MIN_VOLTS) / MAX_ADC as f32,
+            offset: MIN_VOLTS,
+        }
+    }
+}
+
+impl InputCalibration {
+    /// Fit from the codes read with `INPUT_REFERENCE_VOLTS` patched in.
+    pub fn from_samples(low: &SampleSet, high: &SampleSet) -> Option<Self> {
+        let (low, high) = (low.mean()?, high.mean()?);
+        let span = high - low;
+        if span < MIN_INPUT_SPAN && span > -MIN_INPUT_SPAN {
+            return None;
+        }
+
+        let [v0, v1] = INPUT_REFERENCE_VOLTS;
+        let gain = (v1 - v0) / span;
+        Some(InputCalibration {
+            gain,
+            offset: v0 - low * gain,
+        })
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    pub fn offset(&self) -> f32 {
+        self.offset
+    }
+
+    pub fn volts(&self, code: u16) -> f32 {
+        code as f32 * self.gain + self.offset
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CvCalibration {
+    inputs: [InputCalibration; NUM_INPUTS],
+}
+
+impl CvCalibration {
+    pub const STORED_SIZE: usize = STORAGE_OVERHEAD + NUM_INPUTS * 8;
+
+    pub fn input(&self, input: usize) -> &InputCalibration {
+        &self.inputs[input]
+    }
+
+    pub fn set_input(&mut self, input: usize, cal: InputCalibration) {
+        self.inputs[input] = cal;
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::STORED_SIZE] {
+        let mut bytes = [0u8; Self::STORED_SIZE];
+        for (chunk, cal) in payload_mut(&mut bytes)
+            .chunks_exact_mut(8)
+            .zip(&self.inputs)
+        {
+            chunk[..4].copy_from_slice(&cal.gain.to_le_bytes());
+            chunk[4..].copy_from_slice(&cal.offset.to_le_bytes());
+        }
+        seal(&mut bytes, CV_MAGIC);
+        bytes
+    }
+
+    /// Parse a table written by `to_bytes`, `None` if it is missing or corrupt.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let payload = unseal(bytes.get(..Self::STORED_SIZE)?, CV_MAGIC)?;
+
+        let mut cal = CvCalibration::default();
+        for (input, chunk) in cal.inputs.iter_mut().zip(payload.chunks_exact(8)) {
+            input.gain = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            input.offset = f32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        }
+        Some(cal)
+    }
+}
+
+fn payload_mut(bytes: &mut [u8]) -> &mut [u8] {
+    let end = bytes.len() - 2;
+    &mut bytes[HEADER_SIZE..end]
+}
+
+/// Fill in the header and checksum around an already written payload.
+fn seal(bytes: &mut [u8], magic: [u8; 4]) {
+    bytes[0..4].copy_from_slice(&magic);
+    bytes[4..6].copy_from_slice(&VERSION.to_le_bytes());
+
+    let end = bytes.len() - 2;
+    let sum = checksum(&bytes[..end]);
+    bytes[end..].copy_from_slice(&sum.to_le_bytes());
+}
+
+/// Check the header and checksum, returning the payload.
+fn unseal(bytes: &[u8], magic: [u8; 4]) -> Option<&[u8]> {
+    let end = bytes.len() - 2;
+    if bytes[0..4] != magic
+        || u16::from_le_bytes([bytes[4], bytes[5]]) != VERSION
+        || u16::from_le_bytes([bytes[end], bytes[end + 1]]) != checksum(&bytes[..end])
+    {
+        return None;
+    }
+    Some(&bytes[HEADER_SIZE..end])
+}
+
+/// Fletcher-16 over the stored table.
+fn checksum(data: &[u8]) -> u16 {
+    let (mut a, mut b) = (0u16, 0u16);
+    for byte in data {
+        a = (a + *byte as u16) % 255;
+        b = (b + a) % 255;
+    }
+    (b << 8) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Synthetic readings, a few codes of noise around the nominal codes for
+    // 1V, 2V and 3V, 32 samples per reference voltage
+    const IN1_1V: [u16; 32] = [
+        2246, 2246, 2246, 2245, 2245, 2246, 2247, 2246, 2247, 2246, 2246, 2246, 2244, 2247, 2246,
+        2246, 2244, 2244, 2245, 2245, 2246, 2246, 2246, 2245, 2246, 2246, 2245, 2248, 2247, 2247,
+        2245, 2245,
+    ];
+    const IN1_2V: [u16; 32] = [
+        2454, 2454, 2455, 2454, 2454, 2453, 2454, 2456, 2453, 2454, 2455, 2452, 2454, 2456, 2452,
+        2454, 2454, 2453, 2455, 2454, 2452, 2455, 2455, 2455, 2456, 2455, 2454, 2453, 2455, 2453,
+        2454, 2453,
+    ];
+    const IN1_3V: [u16; 32] = [
+        2661, 2662, 2664, 2660, 2661, 2663, 2664, 2663, 2660, 2659, 2663, 2662, 2661, 2664, 2664,
+        2663, 2663, 2663, 2664, 2663, 2663, 2663, 2661, 2664, 2664, 2663, 2660, 2662, 2664, 2660,
+        2662, 2664,
+    ];
+
+    // The same for an input with an inverting stage
+    const IN2_1V: [u16; 32] = [
+        1856, 1859, 1858, 1857, 1858, 1858, 1858, 1859, 1857, 1857, 1859, 1857, 1856, 1859, 1859,
+        1857, 1856, 1857, 1857, 1857, 1859, 1856, 1859, 1856, 1856, 1858, 1859, 1858, 1858, 1858,
+        1858, 1858,
+    ];
+    const IN2_2V: [u16; 32] = [
+        1654, 1654, 1654, 1654, 1655, 1654, 1656, 1654, 1653, 1653, 1654, 1655, 1653, 1654, 1656,
+        1651, 1652, 1654, 1654, 1654, 1653, 1655, 1654, 1653, 1657, 1654, 1653, 1654, 1653, 1654,
+        1650, 1653,
+    ];
+    const IN2_3V: [u16; 32] = [
+        1451, 1449, 1450, 1451, 1451, 1452, 1448, 1450, 1450, 1451, 1451, 1447, 1451, 1448, 1451,
+        1448, 1450, 1452, 1450, 1450, 1451, 1450, 1450, 1452, 1451, 1450, 1453, 1449, 1451, 1450,
+        1450, 1451,
+    ];
+
+    // A twelfth of a volt, one semitone at 1V/oct
+    const SEMITONE: f32 = 1.0 / 12.0;
+
+    fn fit(low: &[u16], high: &[u16]) -> Option<InputCalibration> {
+        let low: SampleSet = low.iter().copied().collect();
+        let high: SampleSet = high.iter().copied().collect();
+        InputCalibration::from_samples(&low, &high)
+    }
+
+    fn mean_volts(cal: &InputCalibration, codes: &[u16]) -> f32 {
+        codes.iter().map(|code| cal.volts(*code)).sum::<f32>() / codes.len() as f32
+    }
+
+    fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn sample_set_mean() {
+        let set: SampleSet = [10, 11, 12, 13].into_iter().collect();
+        assert_eq!(set.len(), 4);
+        assert_eq!(set.mean(), Some(11.5));
+        assert_eq!(SampleSet::default().mean(), None);
+    }
+
+    #[test]
+    fn fit_hits_reference_points() {
+        let cal = fit(&IN1_1V, &IN1_3V).unwrap();
+        assert_near(mean_volts(&cal, &IN1_1V), 1.0, 1e-4);
+        assert_near(mean_volts(&cal, &IN1_3V), 3.0, 1e-4);
+    }
+
+    #[test]
+    fn fit_tracks_between_reference_points() {
+        let cal = fit(&IN1_1V, &IN1_3V).unwrap();
+        assert_near(mean_volts(&cal, &IN1_2V), 2.0, 0.05 * SEMITONE);
+        assert_near(cal.gain(), 0.0048, 0.0001);
+    }
+
+    #[test]
+    fn fit_single_samples_within_a_quarter_semitone() {
+        let cal = fit(&IN1_1V, &IN1_3V).unwrap();
+        for code in IN1_2V {
+            assert_near(cal.volts(code), 2.0, 0.25 * SEMITONE);
+        }
+    }
+
+    #[test]
+    fn fit_inverting_input() {
+        let cal = fit(&IN2_1V, &IN2_3V).unwrap();
+        assert!(cal.gain() < 0.0);
+        assert_near(mean_volts(&cal, &IN2_2V), 2.0, 0.05 * SEMITONE);
+    }
+
+    #[test]
+    fn fit_rejects_unpatched_input() {
+        assert_eq!(fit(&IN1_1V, &IN1_1V), None);
+        assert_eq!(fit(&IN1_1V, &[2250, 2260]), None);
+    }
+
+    #[test]
+    fn fit_rejects_missing_samples() {
+        assert_eq!(fit(&[], &IN1_3V), None);
+        assert_eq!(fit(&IN1_1V, &[]), None);
+    }
+
+    #[test]
+    fn uncalibrated_input_is_nominal() {
+        let cal = InputCalibration::default();
+        for volts in [-8.0, -1.0, 0.0, 1.0, 5.0] {
+            assert_near(cal.volts(nominal_adc_code(volts)), volts, 0.005);
+        }
+    }
+
+    /// A channel a little hot and offset from nominal, as if trimmed.
+    fn trimmed() -> OutputCalibration {
+        let mut cal = OutputCalibration::default();
+        for (point, delta) in [-120, -40, 25, 90, 180].into_iter().enumerate() {
+            cal.trim(point, delta);
+        }
+        cal
+    }
+
+    #[test]
+    fn output_hits_reference_points() {
+        let cal = trimmed();
+        for (point, volts) in REFERENCE_VOLTS.into_iter().enumerate() {
+            assert_eq!(cal.volts_to_code(volts), cal.code(point));
+        }
+    }
+
+    #[test]
+    fn output_interpolates_between_reference_points() {
+        let cal = trimmed();
+        for point in 0..NUM_POINTS - 1 {
+            let (low, high) = (cal.code(point) as f32, cal.code(point + 1) as f32);
+            let volts = (REFERENCE_VOLTS[point] + REFERENCE_VOLTS[point + 1]) / 2.0;
+            assert_near(cal.volts_to_code(volts) as f32, (low + high) / 2.0, 0.5);
+            let volts = REFERENCE_VOLTS[point] + 1.0;
+            assert_near(
+                cal.volts_to_code(volts) as f32,
+                low + (high - low) / 4.0,
+                0.5,
+            );
+        }
+    }
+
+    #[test]
+    fn output_extrapolates_and_clamps_outside_reference_points() {
+        let cal = trimmed();
+        // The end segments carry on past the outer reference voltages
+        let slope = (cal.code(4) - cal.code(3)) as f32 / 4.0;
+        assert_near(
+            cal.volts_to_code(9.0) as f32,
+            cal.code(4) as f32 + slope,
+            0.5,
+        );
+        assert_eq!(cal.volts_to_code(-12.0), 0);
+        assert_eq!(cal.volts_to_code(12.0), MAX_CODE);
+        assert_eq!(cal.volts_to_code(-1000.0), 0);
+        assert_eq!(cal.volts_to_code(1000.0), MAX_CODE);
+    }
+
+    #[test]
+    fn uncalibrated_output_is_nominal() {
+        let cal = OutputCalibration::default();
+        for volts in [-10.0, -8.0, -1.0, 0.0, 1.0, 5.0, 10.0] {
+            assert_near(
+                cal.volts_to_code(volts) as f32,
+                nominal_code(volts) as f32,
+                1.0,
+            );
+        }
+    }
+
+    #[test]
+    fn trim_steps_and_clamps() {
+        let mut cal = OutputCalibration::default();
+        let start = cal.code(2);
+        cal.trim(2, 1);
+        assert_eq!(cal.code(2), start + 1);
+        cal.trim(2, -11);
+        assert_eq!(cal.code(2), start - 10);
+        // Other points are left alone
+        assert_eq!(cal.code(1), OutputCalibration::default().code(1));
+
+        cal.trim(0, -100_000);
+        assert_eq!(cal.code(0), 0);
+        cal.trim(4, 100_000);
+        assert_eq!(cal.code(4), MAX_CODE);
+    }
+
+    #[test]
+    fn dac_calibration_round_trip() {
+        let mut cal = DacCalibration::default();
+        *cal.output_mut(0) = trimmed();
+        cal.output_mut(7).trim(4, -300);
+
+        let bytes = cal.to_bytes();
+        assert_eq!(bytes.len(), DacCalibration::STORED_SIZE);
+        assert_eq!(DacCalibration::from_bytes(&bytes), Some(cal));
+        assert_eq!(cal.volts_to_code(0, 4.0), trimmed().code(3));
+    }
+
+    #[test]
+    fn dac_calibration_rejects_corruption() {
+        let mut bytes = DacCalibration::default().to_bytes();
+        bytes[20] ^= 0x01;
+        assert_eq!(DacCalibration::from_bytes(&bytes), None);
+
+        // A good payload under a bad checksum
+        let mut bytes = DacCalibration::default().to_bytes();
+        bytes[DacCalibration::STORED_SIZE - 1] ^= 0x80;
+        assert_eq!(DacCalibration::from_bytes(&bytes), None);
+
+        assert_eq!(DacCalibration::from_bytes(&bytes[..4]), None);
+        // An erased flash sector
+        assert_eq!(
+            DacCalibration::from_bytes(&[0xFF; DacCalibration::STORED_SIZE]),
+            None
+        );
+        // Nor is a CV table taken for a DAC one
+        assert_eq!(
+            DacCalibration::from_bytes(&CvCalibration::default().to_bytes()),
+            None
+        );
+    }
+
+    #[test]
+    fn cv_calibration_round_trip() {
+        let mut cal = CvCalibration::default();
+        cal.set_input(0, fit(&IN1_1V, &IN1_3V).unwrap());
+        cal.set_input(1, fit(&IN2_1V, &IN2_3V).unwrap());
+
+        let bytes = cal.to_bytes();
+        assert_eq!(CvCalibration::from_bytes(&bytes), Some(cal));
+    }
+
+    #[test]
+    fn cv_calibration_rejects_corruption() {
+        let mut bytes = CvCalibration::default().to_bytes();
+        bytes[10] ^= 0x01;
+        assert_eq!(CvCalibration::from_bytes(&bytes), None);
+        assert_eq!(CvCalibration::from_bytes(&bytes[..4]), None);
+        assert_eq!(CvCalibration::from_bytes(&[0xFF; 64]), None);
+    }
+}
@@ -0,0 +1,58 @@
+use crate::calibration::InputCalibration;
+
+/// Latest reading of one CV input along with its calibration.
+#[derive(Clone, Copy, Debug)]
+pub struct CvInput {
+    calibration: InputCalibration,
+    raw: u16,
+    patched: bool,
+    /// Volts read while nothing is patched
+    normal: f32,
+}
+
+impl Default for CvInput {
+    fn default() -> Self {
+        Self::new(InputCalibration::default())
+    }
+}
+
+impl CvInput {
+    pub fn new(calibration: InputCalibration) -> Self {
+        CvInput {
+            calibration,
+            raw: 0,
+            patched: true,
+            normal: 0.0,
+        }
+    }
+
+    pub fn set_calibration(&mut self, calibration: InputCalibration) {
+        self.calibration = calibration;
+    }
+
+    pub fn set_raw(&mut self, raw: u16) {
+        self.raw = raw;
+    }
+
+    pub fn raw(&self) -> u16 {
+        self.raw
+    }
+
+    pub fn set_patched(&mut self, patched: bool) {
+        self.patched = patched;
+    }
+
+    pub fn set_normal(&mut self, volts: f32) {
+        self.normal = volts;
+    }
+
+    /// Calibrated reading, or the normal value when unpatched rather than
+    /// whatever the floating input picks up.
+    pub fn volts(&self) -> f32 {
+        if self.patched {
+            self.calibration.volts(self.raw)
+        } else {
+            self.normal
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! Fixed-point building blocks for generating outputs at the sample rate.
+//!
+//! The output core's Cortex-M33 has a single precision FPU but integer
+//! math is cheaper, exact and behaves identically on the host. Formats:
+//!
+//! - `Q15`, unit range values such as a sine, -1 to just under 1
+//! - `Q16`, volts with 16 fractional bits, about 15uV per step, as the
+//!   slew stage and output calibration run in
+//! - phases are a full `u32` turn, wrapping for free, as the LFOs run on
+//!
+//! Tables are built at compile time, so nothing here needs floats at run
+//! time apart from the conversions used when settings change.
+
+pub type Q15 = i16;
+pub type Q16 = i32;
+
+pub const Q15_ONE: i32 = 1 << 15;
+pub const ONE_VOLT: Q16 = 1 << 16;
+
+/// Nominal DAC span, matching `calibration::MIN_VOLTS` and `MAX_VOLTS`.
+const DAC_MIN: Q16 = -10 * ONE_VOLT;
+const DAC_SPAN: i64 = 20 * ONE_VOLT as i64;
+
+const SINE_BITS: u32 = 10;
+const SINE_LEN: usize = 1 << SINE_BITS;
+const EXP2_BITS: u32 = 8;
+const EXP2_LEN: usize = 1 << EXP2_BITS;
+/// Fractional bits of the exp2 table, whose entries run from 1.0 to 2.0
+const EXP2_FRAC: u32 = 30;
+
+pub fn to_q16(volts: f32) -> Q16 {
+    (volts * ONE_VOLT as f32) as Q16
+}
+
+pub fn from_q16(value: Q16) -> f32 {
+    value as f32 / ONE_VOLT as f32
+}
+
+pub fn to_q15(value: f32) -> Q15 {
+    (value * Q15_ONE as f32).clamp(i16::MIN as f32, i16::MAX as f32) as Q15
+}
+
+pub fn from_q15(value: Q15) -> f32 {
+    value as f32 / Q15_ONE as f32
+}
+
+/// Product of two `Q15`s, rounded and saturated.
+pub fn mul_q15(a: Q15, b: Q15) -> Q15 {
+    let product = (a as i32 * b as i32 + (1 << 14)) >> 15;
+    product.clamp(i16::MIN as i32, i16::MAX as i32) as Q15
+}
+
+/// Scale volts by a `Q15` gain, e.g. an LFO depth.
+pub fn scale_q16(volts: Q16, gain: Q15) -> Q16 {
+    ((volts as i64 * gain as i64) >> 15) as Q16
+}
+
+/// Product of two `Q16`s, saturated.
+pub fn mul_q16(a: Q16, b: Q16) -> Q16 {
+    ((a as i64 * b as i64) >> 16).clamp(i32::MIN as i64, i32::MAX as i64) as Q16
+}
+
+/// Linear interpolation `frac` of the way from `a` to `b`, a fraction of
+/// 0x1_0000.
+pub fn lerp(a: i32, b: i32, frac: u16) -> i32 {
+    a + (((b as i64 - a as i64) * frac as i64) >> 16) as i32
+}
+
+/// Nominal 16 bit DAC code for `volts`, saturating at the rails.
+pub fn volts_to_dac(volts: Q16) -> u16 {
+    let offset = volts as i64 - DAC_MIN as i64;
+    let code = (offset * u16::MAX as i64 + DAC_SPAN / 2) / DAC_SPAN;
+    code.clamp(0, u16::MAX as i64) as u16
+}
+
+/// Volts a nominal DAC puts out for `code`, the inverse of `volts_to_dac`.
+pub fn dac_to_volts(code: u16) -> Q16 {
+    let offset = (code as i64 * DAC_SPAN + u16::MAX as i64 / 2) / u16::MAX as i64;
+    (DAC_MIN as i64 + offset) as Q16
+}
+
+/// Taylor series for sine, good to 1e-12 over a quarter turn.
+const fn sin_quadrant(x: f64) -> f64 {
+    let x2 = x * x;
+    let mut term = x;
+    let mut sum = x;
+    let mut n = 1;
+    while n < 12 {
+        term = -term * x2 / ((2 * n) * (2 * n + 1)) as f64;
+        sum += term;
+        n += 1;
+    }
+    sum
+}
+
+const fn round(x: f64) -> i64 {
+    if x >= 0.0 {
+        (x + 0.5) as i64
+    } else {
+        (x - 0.5) as i64
+    }
+}
+
+/// One turn of sine in `Q15`, plus a guard entry for interpolation.
+static SINE: [Q15; SINE_LEN + 1] = {
+    const HALF_PI: f64 = core::f64::consts::FRAC_PI_2;
+    let mut table = [0; SINE_LEN + 1];
+    let mut i = 0;
+    while i <= SINE_LEN {
+        let turn = (i % SINE_LEN) as f64 / SINE_LEN as f64;
+        // Fold onto the first quadrant where the series converges fastest
+        let (x, sign) = match (turn * 4.0) as u32 {
+            0 => (turn * 4.0, 1.0),
+            1 => (2.0 - turn * 4.0, 1.0),
+            2 => (turn * 4.0 - 2.0, -1.0),
+            _ => (4.0 - turn * 4.0, -1.0),
+        };
+        let value = round(sign * sin_quadrant(x * HALF_PI) * i16::MAX as f64);
+        table[i] = value as Q15;
+        i += 1;
+    }
+    table
+};
+
+/// Taylor series for 2^x over 0 to 1.
+const fn exp2_unit(x: f64) -> f64 {
+    let y = x * core::f64::consts::LN_2;
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    let mut n = 1;
+    while n < 16 {
+        term = term * y / n as f64;
+        sum += term;
+        n += 1;
+    }
+    sum
+}
+
+/// 2^(i/256) with 30 fractional bits, plus a guard entry.
+static EXP2: [u32; EXP2_LEN + 1] = {
+    let mut table = [0; EXP2_LEN + 1];
+    let mut i = 0;
+    while i <= EXP2_LEN {
+        let value = exp2_unit(i as f64 / EXP2_LEN as f64) * (1u64 << EXP2_FRAC) as f64;
+        table[i] = round(value) as u32;
+        i += 1;
+    }
+    table
+};
+
+/// Sine of a phase, interpolated between table entries.
+pub fn sine(phase: u32) -> Q15 {
+    let index = (phase >> (32 - SINE_BITS)) as usize;
+    let frac = (phase >> (16 - SINE_BITS)) as u16;
+    lerp(SINE[index] as i32, SINE[index + 1] as i32, frac) as Q15
+}
+
+/// 2^x for `Q16` x, as an unsigned Q16.16, saturating. With x in volts
+/// this is the frequency ratio of a 1V/octave pitch.
+pub fn exp2(x: Q16) -> u32 {
+    let octave = x >> 16;
+    let frac = x as u32 & 0xFFFF;
+    let index = (frac >> (16 - EXP2_BITS)) as usize;
+    let between = (frac << EXP2_BITS) as u16;
+    let (a, b) = (EXP2[index] as i64, EXP2[index + 1] as i64);
+    let mantissa = (a + (((b - a) * between as i64) >> 16)) as u64;
+
+    // Mantissa has 30 fractional bits, the result 16
+    let shift = octave - (EXP2_FRAC as i32 - 16);
+    let value = if shift >= 0 {
+        if shift >= 32 {
+            u64::MAX
+        } else {
+            mantissa << shift
+        }
+    } else if shift <= -64 {
+        0
+    } else {
+        mantissa >> -shift
+    };
+    value.min(u32::MAX as u64) as u32
+}
+
+/// Oscillator phase, a whole turn being 2^32.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Phase {
+    phase: u32,
+    increment: u32,
+}
+
+impl Phase {
+    pub fn new(hz: f32, sample_rate: u32) -> Self {
+        let mut phase = Self::default();
+        phase.set_hz(hz, sample_rate);
+        phase
+    }
+
+    pub fn set_hz(&mut self, hz: f32, sample_rate: u32) {
+        let turns = hz as f64 / sample_rate as f64;
+        self.increment = round(turns * (1u64 << 32) as f64).clamp(0, u32::MAX as i64) as u32;
+    }
+
+    /// Frequency as an unsigned Q16.16, e.g. straight from `exp2`.
+    pub fn set_hz_q16(&mut self, hz: u32, sample_rate: u32) {
+        let rate = sample_rate as u64;
+        self.increment = ((((hz as u64) << 16) + rate / 2) / rate).min(u32::MAX as u64) as u32;
+    }
+
+    pub fn phase(&self) -> u32 {
+        self.phase
+    }
+
+    pub fn reset(&mut self, phase: u32) {
+        self.phase = phase;
+    }
+
+    /// Advance one sample, returning true when a turn completes.
+    pub fn tick(&mut self) -> bool {
+        let (phase, wrapped) = self.phase.overflowing_add(self.increment);
+        self.phase = phase;
+        wrapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TURN: f64 = 4_294_967_296.0;
+
+    #[test]
+    fn sine_matches_f64() {
+        let mut worst = 0.0f64;
+        for i in 0..100_000u64 {
+            let phase = (i * 42_949) as u32;
+            let expected = (phase as f64 / TURN * core::f64::consts::TAU).sin();
+            let error = (sine(phase) as f64 / i16::MAX as f64 - expected).abs();
+            worst = worst.max(error);
+        }
+        // Linear interpolation over 1024 points, plus Q15 rounding
+        assert!(worst < 1e-4, "{}", worst);
+    }
+
+    #[test]
+    fn sine_quadrants_exact() {
+        assert_eq!(sine(0), 0);
+        assert_eq!(sine(1 << 30), i16::MAX);
+        assert_eq!(sine(1 << 31), 0);
+        assert_eq!(sine(3 << 30), -i16::MAX);
+    }
+
+    #[test]
+    fn exp2_matches_f64() {
+        for x in (-8 * ONE_VOLT..8 * ONE_VOLT).step_by(997) {
+            let expected = 2f64.powf(x as f64 / ONE_VOLT as f64);
+            let actual = exp2(x) as f64 / ONE_VOLT as f64;
+            // Relative, until the result is down to a handful of Q16 steps
+            let tolerance = (expected * 2e-6).max(2.0 / ONE_VOLT as f64);
+            assert!(
+                (actual - expected).abs() <= tolerance,
+                "{} {} {}",
+                x,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn exp2_octaves_exact() {
+        assert_eq!(exp2(0), 1 << 16);
+        assert_eq!(exp2(ONE_VOLT), 2 << 16);
+        assert_eq!(exp2(-ONE_VOLT), 1 << 15);
+        assert_eq!(exp2(10 * ONE_VOLT), 1024 << 16);
+    }
+
+    #[test]
+    fn exp2_saturates() {
+        assert_eq!(exp2(16 * ONE_VOLT), u32::MAX);
+        assert_eq!(exp2(i32::MAX), u32::MAX);
+        assert_eq!(exp2(-17 * ONE_VOLT), 0);
+        assert_eq!(exp2(i32::MIN), 0);
+    }
+
+    #[test]
+    fn phase_runs_at_frequency() {
+        let mut phase = Phase::new(440.0, 48_000);
+        let wraps = (0..48_000).filter(|_| phase.tick()).count();
+        assert_eq!(wraps, 440);
+
+        let mut phase = Phase::default();
+        phase.set_hz_q16(exp2(0) * 100, 48_000);
+        let wraps = (0..480_000).filter(|_| phase.tick()).count();
+        assert_eq!(wraps, 1000);
+    }
+
+    #[test]
+    fn lerp_matches_f64() {
+        for (a, b) in [(0, 1000), (-32768, 32767), (i32::MIN / 2, i32::MAX / 2)] {
+            for frac in (0..=u16::MAX).step_by(4099) {
+                let expected = a as f64 + (b as f64 - a as f64) * frac as f64 / 65536.0;
+                assert!((lerp(a, b, frac) as f64 - expected).abs() <= 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn q15_multiply() {
+        assert_eq!(mul_q15(to_q15(0.5), to_q15(0.5)), to_q15(0.25));
+        assert_eq!(mul_q15(i16::MIN, i16::MIN), i16::MAX);
+        assert_eq!(mul_q15(to_q15(-0.5), i16::MAX), to_q15(-0.5) + 1);
+        assert_eq!(scale_q16(4 * ONE_VOLT, to_q15(0.5)), 2 * ONE_VOLT);
+        assert_eq!(mul_q16(3 * ONE_VOLT, ONE_VOLT / 2), 3 * ONE_VOLT / 2);
+        assert_eq!(mul_q16(i32::MAX, i32::MAX), i32::MAX);
+    }
+
+    #[test]
+    fn volts_to_dac_matches_f64() {
+        for millivolts in -10_000..=10_000 {
+            let volts = millivolts as f64 / 1000.0;
+            let expected = ((volts + 10.0) / 20.0 * 65535.0).round();
+            let actual = volts_to_dac(to_q16(volts as f32)) as f64;
+            assert!((actual - expected).abs() <= 1.0, "{} {}", volts, actual);
+        }
+    }
+
+    #[test]
+    fn volts_to_dac_saturates() {
+        assert_eq!(volts_to_dac(-10 * ONE_VOLT), 0);
+        assert_eq!(volts_to_dac(10 * ONE_VOLT), u16::MAX);
+        assert_eq!(volts_to_dac(i32::MIN), 0);
+        assert_eq!(volts_to_dac(i32::MAX), u16::MAX);
+        assert_eq!(volts_to_dac(0), 32768);
+    }
+
+    #[test]
+    fn dac_to_volts_round_trips() {
+        for code in 0..=u16::MAX {
+            assert_eq!(volts_to_dac(dac_to_volts(code)), code);
+        }
+        assert_eq!(dac_to_volts(0), -10 * ONE_VOLT);
+        assert_eq!(dac_to_volts(u16::MAX), 10 * ONE_VOLT);
+    }
+}
@@ -0,0 +1,165 @@
+//! Which front panel jacks have a cable in them.
+//!
+//! The jacks are `AudioJack2_SwitchT`s, whose tip normal (TN) contact rests
+//! against the tip until a plug pushes it away. Reading each TN contact
+//! tells whether the jack is patched; the readings bounce as a plug goes
+//! in, so `JackDetect` only takes them once they have settled.
+//!
+//! The current board leaves the output TN pins unconnected and ties the
+//! input ones to ground, so the firmware reports every jack as patched.
+
+use crate::calibration::{NUM_INPUTS, NUM_OUTPUTS};
+
+/// Identical scans needed before a change is believed.
+pub const DEBOUNCE_SCANS: u8 = 8;
+
+/// Patched state of every jack, a set bit is a patched jack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Jacks {
+    inputs: u8,
+    outputs: u8,
+}
+
+impl Default for Jacks {
+    /// Everything patched, what a board that can't sense its jacks reports.
+    fn default() -> Self {
+        Jacks {
+            inputs: (1 << NUM_INPUTS) - 1,
+            outputs: ((1u16 << NUM_OUTPUTS) - 1) as u8,
+        }
+    }
+}
+
+impl Jacks {
+    /// Build from TN switch readings, true while the contact is closed.
+    pub fn from_switches(inputs: [bool; NUM_INPUTS], outputs: [bool; NUM_OUTPUTS]) -> Self {
+        let patched = |closed: &[bool]| {
+            closed
+                .iter()
+                .enumerate()
+                .filter(|(_, closed)| !**closed)
+                .fold(0, |bits, (i, _)| bits | 1 << i)
+        };
+        Jacks {
+            inputs: patched(&inputs),
+            outputs: patched(&outputs),
+        }
+    }
+
+    pub fn input(&self, input: usize) -> bool {
+        self.inputs & 1 << input != 0
+    }
+
+    pub fn output(&self, ch: usize) -> bool {
+        self.outputs & 1 << ch != 0
+    }
+
+    pub fn set_input(&mut self, input: usize, patched: bool) {
+        self.inputs = set_bit(self.inputs, input, patched);
+    }
+
+    pub fn set_output(&mut self, ch: usize, patched: bool) {
+        self.outputs = set_bit(self.outputs, ch, patched);
+    }
+}
+
+fn set_bit(bits: u8, bit: usize, set: bool) -> u8 {
+    if set {
+        bits | 1 << bit
+    } else {
+        bits & !(1 << bit)
+    }
+}
+
+/// Debounces scans of the TN switches into a settled `Jacks`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JackDetect {
+    jacks: Jacks,
+    candidate: Jacks,
+    count: u8,
+}
+
+impl JackDetect {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn jacks(&self) -> Jacks {
+        self.jacks
+    }
+
+    /// Feed one scan of the switches, returning the settled state.
+    pub fn scan(&mut self, inputs: [bool; NUM_INPUTS], outputs: [bool; NUM_OUTPUTS]) -> Jacks {
+        let reading = Jacks::from_switches(inputs, outputs);
+        if reading != self.candidate {
+            self.candidate = reading;
+            self.count = 0;
+        } else if self.count < DEBOUNCE_SCANS {
+            self.count += 1;
+            if self.count == DEBOUNCE_SCANS {
+                self.jacks = reading;
+            }
+        }
+        self.jacks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPEN: [bool; NUM_OUTPUTS] = [false; NUM_OUTPUTS];
+
+    #[test]
+    fn closed_switch_is_unpatched() {
+        let mut outputs = OPEN;
+        outputs[2] = true;
+        let jacks = Jacks::from_switches([true, false, false], outputs);
+        assert!(!jacks.input(0));
+        assert!(jacks.input(1));
+        assert!(!jacks.output(2));
+        assert!(jacks.output(7));
+    }
+
+    #[test]
+    fn default_is_all_patched() {
+        assert_eq!(Jacks::default(), Jacks::from_switches([false; 3], OPEN));
+    }
+
+    #[test]
+    fn set_and_clear() {
+        let mut jacks = Jacks::default();
+        jacks.set_output(7, false);
+        jacks.set_input(1, false);
+        assert!(!jacks.output(7));
+        assert!(!jacks.input(1));
+        jacks.set_output(7, true);
+        jacks.set_input(1, true);
+        assert_eq!(jacks, Jacks::default());
+    }
+
+    #[test]
+    fn change_waits_for_switch_to_settle() {
+        let mut detect = JackDetect::new();
+        let unplugged = [true; NUM_OUTPUTS];
+        for _ in 0..DEBOUNCE_SCANS {
+            assert!(detect.scan([false; 3], unplugged).output(0));
+        }
+        assert!(!detect.scan([false; 3], unplugged).output(0));
+    }
+
+    #[test]
+    fn bounce_restarts_the_wait() {
+        let mut detect = JackDetect::new();
+        let unplugged = [true; 3];
+        for _ in 0..DEBOUNCE_SCANS {
+            detect.scan(unplugged, OPEN);
+        }
+        // Contact bounces closed for a scan before settling
+        detect.scan([false; 3], OPEN);
+        for _ in 0..DEBOUNCE_SCANS {
+            assert!(detect.scan(unplugged, OPEN).input(0));
+        }
+        assert!(!detect.scan(unplugged, OPEN).input(0));
+    }
+}
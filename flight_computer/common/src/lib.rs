@@ -0,0 +1,9 @@
+#![no_std]
+
+pub mod calibration;
+pub mod cv_input;
+pub mod dsp;
+pub mod jacks;
+pub mod midi;
+pub mod slew;
+pub mod w25x40;
@@ -0,0 +1,1182 @@
+//! MIDI: a byte-at-a-time parser, the state it builds up and the queue
+//! and filter used to send MIDI back out.
+//!
+//! The parser follows the MIDI 1.0 wire rules: running status, realtime
+//! bytes that may land anywhere (even between a message's data bytes) and
+//! SysEx, which is buffered up to `SYSEX_LEN` bytes. `MidiState` folds
+//! parsed messages into what output modes need, the held notes, controllers
+//! and pitch bend of each channel and the MIDI clock.
+
+pub const NUM_CHANNELS: usize = 16;
+pub const BAUD_RATE: u32 = 31_250;
+
+/// SysEx bytes kept between F0 and F7, longer messages are truncated.
+pub const SYSEX_LEN: usize = 64;
+
+/// Notes remembered per channel, later presses push out the oldest.
+pub const MAX_HELD: usize = 16;
+
+/// MIDI clock ticks per quarter note.
+pub const TICKS_PER_BEAT: u32 = 24;
+
+/// Bytes queued for the UART before further messages are dropped.
+pub const OUT_LEN: usize = 256;
+
+// Controllers with channel mode meanings
+const ALL_SOUND_OFF: u8 = 120;
+const RESET_CONTROLLERS: u8 = 121;
+const ALL_NOTES_OFF: u8 = 123;
+
+/// Channels are 0-15, pitch bend is centred on 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MidiMessage<'a> {
+    NoteOff { channel: u8, note: u8, velocity: u8 },
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    PolyPressure { channel: u8, note: u8, pressure: u8 },
+    ControlChange { channel: u8, control: u8, value: u8 },
+    ProgramChange { channel: u8, program: u8 },
+    ChannelPressure { channel: u8, pressure: u8 },
+    PitchBend { channel: u8, value: i16 },
+    SysEx(&'a [u8]),
+    TimeCode(u8),
+    SongPosition(u16),
+    SongSelect(u8),
+    TuneRequest,
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    Reset,
+}
+
+impl MidiMessage<'_> {
+    /// Encode onto the wire without running status, returns the bytes used.
+    /// SysEx is not encoded and returns 0.
+    pub fn encode(&self, buf: &mut [u8; 3]) -> usize {
+        let (status, data): (u8, &[u8]) = match *self {
+            MidiMessage::NoteOff {
+                channel,
+                note,
+                velocity,
+            } => (0x80 | channel, &[note, velocity]),
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => (0x90 | channel, &[note, velocity]),
+            MidiMessage::PolyPressure {
+                channel,
+                note,
+                pressure,
+            } => (0xA0 | channel, &[note, pressure]),
+            MidiMessage::ControlChange {
+                channel,
+                control,
+                value,
+            } => (0xB0 | channel, &[control, value]),
+            MidiMessage::ProgramChange { channel, program } => (0xC0 | channel, &[program]),
+            MidiMessage::ChannelPressure { channel, pressure } => (0xD0 | channel, &[pressure]),
+            MidiMessage::PitchBend { channel, value } => {
+                let value = (value as i32 + 8192).clamp(0, 0x3FFF) as u16;
+                buf[1] = (value & 0x7F) as u8;
+                buf[2] = (value >> 7) as u8;
+                buf[0] = 0xE0 | channel;
+                return 3;
+            }
+            MidiMessage::SysEx(_) => return 0,
+            MidiMessage::TimeCode(value) => (0xF1, &[value]),
+            MidiMessage::SongPosition(position) => {
+                buf[1] = (position & 0x7F) as u8;
+                buf[2] = (position >> 7 & 0x7F) as u8;
+                buf[0] = 0xF2;
+                return 3;
+            }
+            MidiMessage::SongSelect(song) => (0xF3, &[song]),
+            MidiMessage::TuneRequest => (0xF6, &[]),
+            MidiMessage::Clock => (0xF8, &[]),
+            MidiMessage::Start => (0xFA, &[]),
+            MidiMessage::Continue => (0xFB, &[]),
+            MidiMessage::Stop => (0xFC, &[]),
+            MidiMessage::ActiveSensing => (0xFE, &[]),
+            MidiMessage::Reset => (0xFF, &[]),
+        };
+        buf[0] = status;
+        buf[1..1 + data.len()].copy_from_slice(data);
+        1 + data.len()
+    }
+
+    /// Channel of a channel voice message.
+    pub fn channel(&self) -> Option<u8> {
+        match *self {
+            MidiMessage::NoteOff { channel, .. }
+            | MidiMessage::NoteOn { channel, .. }
+            | MidiMessage::PolyPressure { channel, .. }
+            | MidiMessage::ControlChange { channel, .. }
+            | MidiMessage::ProgramChange { channel, .. }
+            | MidiMessage::ChannelPressure { channel, .. }
+            | MidiMessage::PitchBend { channel, .. } => Some(channel),
+            _ => None,
+        }
+    }
+}
+
+/// Data bytes that follow a status byte.
+fn data_len(status: u8) -> usize {
+    match status & 0xF0 {
+        0xC0 | 0xD0 => 1,
+        0xF0 => match status {
+            0xF1 | 0xF3 => 1,
+            0xF2 => 2,
+            _ => 0,
+        },
+        _ => 2,
+    }
+}
+
+pub struct MidiParser {
+    /// Status of the message being received, kept as running status
+    status: Option<u8>,
+    data: [u8; 2],
+    received: usize,
+    in_sysex: bool,
+    sysex: [u8; SYSEX_LEN],
+    sysex_len: usize,
+}
+
+impl Default for MidiParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiParser {
+    pub const fn new() -> Self {
+        MidiParser {
+            status: None,
+            data: [0; 2],
+            received: 0,
+            in_sysex: false,
+            sysex: [0; SYSEX_LEN],
+            sysex_len: 0,
+        }
+    }
+
+    /// Feed one byte from the UART, returning a message once it is complete.
+    pub fn push(&mut self, byte: u8) -> Option<MidiMessage<'_>> {
+        match byte {
+            // Realtime, may interrupt anything without disturbing it
+            0xF8..=0xFF => match byte {
+                0xF8 => Some(MidiMessage::Clock),
+                0xFA => Some(MidiMessage::Start),
+                0xFB => Some(MidiMessage::Continue),
+                0xFC => Some(MidiMessage::Stop),
+                0xFE => Some(MidiMessage::ActiveSensing),
+                0xFF => {
+                    self.status = None;
+                    self.in_sysex = false;
+                    Some(MidiMessage::Reset)
+                }
+                _ => None,
+            },
+            0xF7 => {
+                self.status = None;
+                if core::mem::take(&mut self.in_sysex) {
+                    Some(MidiMessage::SysEx(&self.sysex[..self.sysex_len]))
+                } else {
+                    None
+                }
+            }
+            0x80..=0xF6 => {
+                // Any other status abandons an unterminated SysEx
+                self.in_sysex = byte == 0xF0;
+                self.sysex_len = 0;
+                self.received = 0;
+                // System common messages cancel running status
+                self.status = match byte {
+                    0xF0 | 0xF4 | 0xF5 => None,
+                    _ => Some(byte),
+                };
+                if byte == 0xF6 {
+                    self.status = None;
+                    return Some(MidiMessage::TuneRequest);
+                }
+                None
+            }
+            data if self.in_sysex => {
+                if self.sysex_len < SYSEX_LEN {
+                    self.sysex[self.sysex_len] = data;
+                    self.sysex_len += 1;
+                }
+                None
+            }
+            data => {
+                // Stray data with no status to run on is dropped
+                let status = self.status?;
+                self.data[self.received] = data;
+                self.received += 1;
+                if self.received < data_len(status) {
+                    return None;
+                }
+                self.received = 0;
+                if status >= 0xF0 {
+                    self.status = None;
+                }
+                Some(self.message(status))
+            }
+        }
+    }
+
+    fn message(&self, status: u8) -> MidiMessage<'_> {
+        let channel = status & 0x0F;
+        let [first, second] = self.data;
+        match status & 0xF0 {
+            0x80 => MidiMessage::NoteOff {
+                channel,
+                note: first,
+                velocity: second,
+            },
+            // Note on with zero velocity is the usual running status note off
+            0x90 if second == 0 => MidiMessage::NoteOff {
+                channel,
+                note: first,
+                velocity: 0,
+            },
+            0x90 => MidiMessage::NoteOn {
+                channel,
+                note: first,
+                velocity: second,
+            },
+            0xA0 => MidiMessage::PolyPressure {
+                channel,
+                note: first,
+                pressure: second,
+            },
+            0xB0 => MidiMessage::ControlChange {
+                channel,
+                control: first,
+                value: second,
+            },
+            0xC0 => MidiMessage::ProgramChange {
+                channel,
+                program: first,
+            },
+            0xD0 => MidiMessage::ChannelPressure {
+                channel,
+                pressure: first,
+            },
+            0xE0 => MidiMessage::PitchBend {
+                channel,
+                value: (((second as u16) << 7 | first as u16) as i16) - 8192,
+            },
+            _ => match status {
+                0xF1 => MidiMessage::TimeCode(first),
+                0xF2 => MidiMessage::SongPosition((second as u16) << 7 | first as u16),
+                _ => MidiMessage::SongSelect(first),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeldNote {
+    pub note: u8,
+    pub velocity: u8,
+}
+
+/// Notes held on one channel, oldest first.
+#[derive(Clone, Copy, Debug)]
+pub struct HeldNotes {
+    notes: [HeldNote; MAX_HELD],
+    len: usize,
+}
+
+impl HeldNotes {
+    const fn new() -> Self {
+        HeldNotes {
+            notes: [HeldNote {
+                note: 0,
+                velocity: 0,
+            }; MAX_HELD],
+            len: 0,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[HeldNote] {
+        &self.notes[..self.len]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn last(&self) -> Option<HeldNote> {
+        self.as_slice().last().copied()
+    }
+
+    pub fn highest(&self) -> Option<HeldNote> {
+        self.as_slice().iter().copied().max_by_key(|held| held.note)
+    }
+
+    pub fn lowest(&self) -> Option<HeldNote> {
+        self.as_slice().iter().copied().min_by_key(|held| held.note)
+    }
+
+    fn press(&mut self, note: u8, velocity: u8) {
+        // A repeated note moves to the top rather than being held twice
+        self.release(note);
+        if self.len == MAX_HELD {
+            self.notes.copy_within(1.., 0);
+            self.len -= 1;
+        }
+        self.notes[self.len] = HeldNote { note, velocity };
+        self.len += 1;
+    }
+
+    fn release(&mut self, note: u8) {
+        if let Some(i) = self.as_slice().iter().position(|held| held.note == note) {
+            self.notes.copy_within(i + 1..self.len, i);
+            self.len -= 1;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ChannelState {
+    pub notes: HeldNotes,
+    pub controllers: [u8; 128],
+    /// -8192 to 8191, centred on 0
+    pub pitch_bend: i16,
+    /// Channel aftertouch
+    pub pressure: u8,
+    /// Polyphonic aftertouch by note, zeroed when the note is struck
+    pub key_pressure: [u8; 128],
+}
+
+impl ChannelState {
+    const fn new() -> Self {
+        ChannelState {
+            notes: HeldNotes::new(),
+            controllers: [0; 128],
+            pitch_bend: 0,
+            pressure: 0,
+            key_pressure: [0; 128],
+        }
+    }
+}
+
+/// Everything received over MIDI that output modes follow.
+#[derive(Clone, Copy, Debug)]
+pub struct MidiState {
+    pub channels: [ChannelState; NUM_CHANNELS],
+    /// Clock ticks since Start or the last song position
+    pub clock_ticks: u32,
+    /// Between Start or Continue and Stop
+    pub running: bool,
+}
+
+impl Default for MidiState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiState {
+    pub const fn new() -> Self {
+        MidiState {
+            channels: [ChannelState::new(); NUM_CHANNELS],
+            clock_ticks: 0,
+            running: false,
+        }
+    }
+
+    pub fn channel(&self, channel: u8) -> &ChannelState {
+        &self.channels[channel as usize & 0x0F]
+    }
+
+    /// True while any note is held on any channel.
+    pub fn any_held(&self) -> bool {
+        self.channels.iter().any(|ch| !ch.notes.is_empty())
+    }
+
+    pub fn apply(&mut self, message: &MidiMessage) {
+        match *message {
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => {
+                let state = &mut self.channels[channel as usize];
+                state.notes.press(note, velocity);
+                state.key_pressure[note as usize & 0x7F] = 0;
+            }
+            MidiMessage::NoteOff { channel, note, .. } => {
+                self.channels[channel as usize].notes.release(note)
+            }
+            MidiMessage::ControlChange {
+                channel,
+                control,
+                value,
+            } => {
+                let state = &mut self.channels[channel as usize];
+                match control {
+                    ALL_SOUND_OFF | ALL_NOTES_OFF => state.notes.clear(),
+                    RESET_CONTROLLERS => {
+                        state.controllers = [0; 128];
+                        state.pitch_bend = 0;
+                        state.pressure = 0;
+                        state.key_pressure = [0; 128];
+                    }
+                    _ => {}
+                }
+                state.controllers[control as usize & 0x7F] = value;
+            }
+            MidiMessage::PitchBend { channel, value } => {
+                self.channels[channel as usize].pitch_bend = value
+            }
+            MidiMessage::ChannelPressure { channel, pressure } => {
+                self.channels[channel as usize].pressure = pressure
+            }
+            MidiMessage::PolyPressure {
+                channel,
+                note,
+                pressure,
+            } => self.channels[channel as usize].key_pressure[note as usize & 0x7F] = pressure,
+            MidiMessage::Clock if self.running => self.clock_ticks += 1,
+            MidiMessage::Start => {
+                self.clock_ticks = 0;
+                self.running = true;
+            }
+            MidiMessage::Continue => self.running = true,
+            MidiMessage::Stop => self.running = false,
+            // Song position counts sixteenths, six clocks each
+            MidiMessage::SongPosition(position) => self.clock_ticks = position as u32 * 6,
+            MidiMessage::Reset => *self = Self::new(),
+            _ => {}
+        }
+    }
+}
+
+/// Bytes waiting to go out of the MIDI port.
+pub struct MidiOut {
+    buf: [u8; OUT_LEN],
+    start: usize,
+    len: usize,
+}
+
+impl Default for MidiOut {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiOut {
+    pub const fn new() -> Self {
+        MidiOut {
+            buf: [0; OUT_LEN],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Queue a whole message, or drop it and return false when there is no room.
+    pub fn send(&mut self, message: &MidiMessage) -> bool {
+        if let MidiMessage::SysEx(data) = message {
+            if self.len + data.len() + 2 > OUT_LEN {
+                return false;
+            }
+            self.push(&[0xF0]);
+            self.push(data);
+            self.push(&[0xF7]);
+            return true;
+        }
+
+        let mut bytes = [0; 3];
+        let len = message.encode(&mut bytes);
+        if self.len + len > OUT_LEN {
+            return false;
+        }
+        self.push(&bytes[..len]);
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buf[self.start];
+        self.start = (self.start + 1) % OUT_LEN;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    /// The byte `pop` would return next, left queued.
+    pub fn peek(&self) -> Option<u8> {
+        (self.len > 0).then_some(self.buf[self.start])
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.buf[(self.start + self.len) % OUT_LEN] = *byte;
+            self.len += 1;
+        }
+    }
+}
+
+/// Channels passed from MIDI in to MIDI out, system messages always pass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ThruFilter {
+    channels: u16,
+}
+
+impl Default for ThruFilter {
+    fn default() -> Self {
+        ThruFilter { channels: u16::MAX }
+    }
+}
+
+impl ThruFilter {
+    pub fn enabled(&self, channel: u8) -> bool {
+        self.channels & 1 << (channel & 0x0F) != 0
+    }
+
+    pub fn toggle(&mut self, channel: u8) {
+        self.channels ^= 1 << (channel & 0x0F);
+    }
+
+    pub fn passes(&self, message: &MidiMessage) -> bool {
+        message
+            .channel()
+            .is_none_or(|channel| self.enabled(channel))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::MidiMessage::*;
+    use super::*;
+
+    fn owned(message: MidiMessage) -> MidiMessage<'static> {
+        match message {
+            NoteOff {
+                channel,
+                note,
+                velocity,
+            } => NoteOff {
+                channel,
+                note,
+                velocity,
+            },
+            NoteOn {
+                channel,
+                note,
+                velocity,
+            } => NoteOn {
+                channel,
+                note,
+                velocity,
+            },
+            PolyPressure {
+                channel,
+                note,
+                pressure,
+            } => PolyPressure {
+                channel,
+                note,
+                pressure,
+            },
+            ControlChange {
+                channel,
+                control,
+                value,
+            } => ControlChange {
+                channel,
+                control,
+                value,
+            },
+            ProgramChange { channel, program } => ProgramChange { channel, program },
+            ChannelPressure { channel, pressure } => ChannelPressure { channel, pressure },
+            PitchBend { channel, value } => PitchBend { channel, value },
+            SysEx(data) => SysEx(Vec::leak(data.to_vec())),
+            TimeCode(value) => TimeCode(value),
+            SongPosition(position) => SongPosition(position),
+            SongSelect(song) => SongSelect(song),
+            TuneRequest => TuneRequest,
+            Clock => Clock,
+            Start => Start,
+            Continue => Continue,
+            Stop => Stop,
+            ActiveSensing => ActiveSensing,
+            Reset => Reset,
+        }
+    }
+
+    fn parse(bytes: &[u8]) -> Vec<MidiMessage<'static>> {
+        let mut parser = MidiParser::new();
+        bytes
+            .iter()
+            .filter_map(|byte| parser.push(*byte).map(owned))
+            .collect()
+    }
+
+    fn apply(state: &mut MidiState, bytes: &[u8]) {
+        for message in parse(bytes) {
+            state.apply(&message);
+        }
+    }
+
+    fn held(state: &MidiState, channel: u8) -> Vec<u8> {
+        let notes = state.channel(channel).notes;
+        notes.as_slice().iter().map(|held| held.note).collect()
+    }
+
+    #[test]
+    fn channel_messages_on_every_channel() {
+        for channel in 0..16 {
+            let messages = [
+                NoteOff {
+                    channel,
+                    note: 60,
+                    velocity: 64,
+                },
+                NoteOn {
+                    channel,
+                    note: 61,
+                    velocity: 100,
+                },
+                PolyPressure {
+                    channel,
+                    note: 62,
+                    pressure: 5,
+                },
+                ControlChange {
+                    channel,
+                    control: 1,
+                    value: 127,
+                },
+                ProgramChange {
+                    channel,
+                    program: 9,
+                },
+                ChannelPressure {
+                    channel,
+                    pressure: 77,
+                },
+                PitchBend {
+                    channel,
+                    value: -8192,
+                },
+                PitchBend { channel, value: 0 },
+                PitchBend {
+                    channel,
+                    value: 8191,
+                },
+            ];
+            for message in messages {
+                let mut buf = [0; 3];
+                let len = message.encode(&mut buf);
+                assert_eq!(parse(&buf[..len]), [message], "{:02X?}", &buf[..len]);
+                assert_eq!(message.channel(), Some(channel));
+            }
+        }
+    }
+
+    #[test]
+    fn system_messages_round_trip() {
+        let messages = [
+            TimeCode(0x35),
+            SongPosition(0),
+            SongPosition(0x3FFF),
+            SongSelect(3),
+            TuneRequest,
+            Clock,
+            Start,
+            Continue,
+            Stop,
+            ActiveSensing,
+            Reset,
+        ];
+        for message in messages {
+            let mut buf = [0; 3];
+            let len = message.encode(&mut buf);
+            assert_eq!(parse(&buf[..len]), [message]);
+            assert_eq!(message.channel(), None);
+        }
+    }
+
+    #[test]
+    fn pitch_bend_is_centred() {
+        assert_eq!(
+            parse(&[0xE3, 0x00, 0x40]),
+            [PitchBend {
+                channel: 3,
+                value: 0
+            }]
+        );
+        assert_eq!(
+            parse(&[0xE3, 0x7F, 0x7F]),
+            [PitchBend {
+                channel: 3,
+                value: 8191
+            }]
+        );
+    }
+
+    #[test]
+    fn running_status() {
+        assert_eq!(
+            parse(&[0x92, 60, 100, 62, 90, 64, 80]),
+            [
+                NoteOn {
+                    channel: 2,
+                    note: 60,
+                    velocity: 100
+                },
+                NoteOn {
+                    channel: 2,
+                    note: 62,
+                    velocity: 90
+                },
+                NoteOn {
+                    channel: 2,
+                    note: 64,
+                    velocity: 80
+                },
+            ]
+        );
+        assert_eq!(
+            parse(&[0xC0, 1, 2, 3]),
+            [
+                ProgramChange {
+                    channel: 0,
+                    program: 1
+                },
+                ProgramChange {
+                    channel: 0,
+                    program: 2
+                },
+                ProgramChange {
+                    channel: 0,
+                    program: 3
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn zero_velocity_note_on_is_note_off() {
+        assert_eq!(
+            parse(&[0x90, 60, 100, 60, 0]),
+            [
+                NoteOn {
+                    channel: 0,
+                    note: 60,
+                    velocity: 100
+                },
+                NoteOff {
+                    channel: 0,
+                    note: 60,
+                    velocity: 0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn realtime_between_data_bytes() {
+        assert_eq!(
+            parse(&[0xB1, 0xF8, 7, 0xFE, 100, 0xFA, 8, 0xFC, 50]),
+            [
+                Clock,
+                ActiveSensing,
+                ControlChange {
+                    channel: 1,
+                    control: 7,
+                    value: 100
+                },
+                Start,
+                Stop,
+                ControlChange {
+                    channel: 1,
+                    control: 8,
+                    value: 50
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn undefined_realtime_ignored() {
+        assert_eq!(
+            parse(&[0x90, 60, 0xF9, 0xFD, 100]),
+            [NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100
+            }]
+        );
+    }
+
+    #[test]
+    fn system_common_cancels_running_status() {
+        assert_eq!(
+            parse(&[0x90, 60, 100, 0xF3, 2, 62, 100]),
+            [
+                NoteOn {
+                    channel: 0,
+                    note: 60,
+                    velocity: 100
+                },
+                SongSelect(2),
+            ]
+        );
+        assert_eq!(
+            parse(&[0x90, 60, 100, 0xF6, 62, 100]),
+            [
+                NoteOn {
+                    channel: 0,
+                    note: 60,
+                    velocity: 100
+                },
+                TuneRequest,
+            ]
+        );
+        assert_eq!(
+            parse(&[0x90, 60, 100, 0xF4, 62, 100]),
+            [NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100
+            }]
+        );
+    }
+
+    #[test]
+    fn stray_data_ignored() {
+        assert_eq!(
+            parse(&[1, 2, 3, 0x80, 60, 0]),
+            [NoteOff {
+                channel: 0,
+                note: 60,
+                velocity: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn new_status_abandons_partial_message() {
+        assert_eq!(
+            parse(&[0x90, 60, 0x80, 61, 0]),
+            [NoteOff {
+                channel: 0,
+                note: 61,
+                velocity: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn sysex_buffered() {
+        assert_eq!(
+            parse(&[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]),
+            [SysEx(&[0x7E, 0x7F, 0x06, 0x01])]
+        );
+        assert_eq!(parse(&[0xF0, 0xF7]), [SysEx(&[])]);
+    }
+
+    #[test]
+    fn realtime_inside_sysex() {
+        assert_eq!(
+            parse(&[0xF0, 0x41, 0xF8, 0x10, 0xF7]),
+            [Clock, SysEx(&[0x41, 0x10])]
+        );
+    }
+
+    #[test]
+    fn long_sysex_truncated() {
+        let mut bytes = std::vec![0xF0];
+        bytes.extend((0..SYSEX_LEN + 10).map(|i| (i % 128) as u8));
+        bytes.push(0xF7);
+        let expected: Vec<u8> = (0..SYSEX_LEN).map(|i| (i % 128) as u8).collect();
+        assert_eq!(parse(&bytes), [SysEx(Vec::leak(expected))]);
+    }
+
+    #[test]
+    fn unterminated_sysex_dropped() {
+        assert_eq!(
+            parse(&[0xF0, 1, 2, 0x90, 60, 100, 0xF7]),
+            [NoteOn {
+                channel: 0,
+                note: 60,
+                velocity: 100
+            }]
+        );
+    }
+
+    #[test]
+    fn sysex_does_not_leave_running_status() {
+        assert_eq!(
+            parse(&[0x90, 60, 100, 0xF0, 1, 0xF7, 62, 100]),
+            [
+                NoteOn {
+                    channel: 0,
+                    note: 60,
+                    velocity: 100
+                },
+                SysEx(&[1])
+            ]
+        );
+    }
+
+    #[test]
+    fn held_notes_in_order() {
+        let mut state = MidiState::new();
+        apply(&mut state, &[0x90, 60, 100, 64, 90, 67, 80]);
+        assert_eq!(held(&state, 0), [60, 64, 67]);
+        assert_eq!(
+            state.channel(0).notes.last(),
+            Some(HeldNote {
+                note: 67,
+                velocity: 80
+            })
+        );
+        assert_eq!(state.channel(0).notes.highest().map(|h| h.note), Some(67));
+        assert_eq!(state.channel(0).notes.lowest().map(|h| h.note), Some(60));
+
+        apply(&mut state, &[0x80, 64, 0]);
+        assert_eq!(held(&state, 0), [60, 67]);
+        assert!(state.channel(1).notes.is_empty());
+    }
+
+    #[test]
+    fn repeated_note_moves_to_top() {
+        let mut state = MidiState::new();
+        apply(&mut state, &[0x95, 60, 100, 62, 100, 60, 50]);
+        assert_eq!(held(&state, 5), [62, 60]);
+        assert_eq!(state.channel(5).notes.last().map(|h| h.velocity), Some(50));
+    }
+
+    #[test]
+    fn oldest_note_dropped_when_full() {
+        let mut state = MidiState::new();
+        let mut bytes = std::vec![0x90];
+        for note in 0..MAX_HELD as u8 + 2 {
+            bytes.extend([note, 100]);
+        }
+        apply(&mut state, &bytes);
+        let expected: Vec<u8> = (2..MAX_HELD as u8 + 2).collect();
+        assert_eq!(held(&state, 0), expected);
+    }
+
+    #[test]
+    fn releasing_unheld_note_is_harmless() {
+        let mut state = MidiState::new();
+        apply(&mut state, &[0x90, 60, 100, 0x80, 61, 0]);
+        assert_eq!(held(&state, 0), [60]);
+    }
+
+    #[test]
+    fn all_notes_off() {
+        let mut state = MidiState::new();
+        apply(&mut state, &[0x90, 60, 100, 62, 100, 0x91, 60, 100]);
+        assert!(state.any_held());
+        apply(&mut state, &[0xB0, ALL_NOTES_OFF, 0]);
+        assert!(held(&state, 0).is_empty());
+        assert_eq!(held(&state, 1), [60]);
+        apply(&mut state, &[0xB1, ALL_SOUND_OFF, 0]);
+        assert!(!state.any_held());
+    }
+
+    #[test]
+    fn controllers_and_pitch_bend() {
+        let mut state = MidiState::new();
+        apply(&mut state, &[0xB2, 1, 64, 74, 127, 0xE2, 0, 0]);
+        assert_eq!(state.channel(2).controllers[1], 64);
+        assert_eq!(state.channel(2).controllers[74], 127);
+        assert_eq!(state.channel(2).pitch_bend, -8192);
+        assert_eq!(state.channel(3).controllers[1], 0);
+
+        apply(&mut state, &[0xB2, RESET_CONTROLLERS, 0]);
+        assert_eq!(state.channel(2).controllers[1], 0);
+        assert_eq!(state.channel(2).pitch_bend, 0);
+    }
+
+    #[test]
+    fn aftertouch() {
+        let mut state = MidiState::new();
+        apply(&mut state, &[0x90, 60, 100, 0xA0, 60, 90, 0xD0, 40]);
+        assert_eq!(state.channel(0).key_pressure[60], 90);
+        assert_eq!(state.channel(0).pressure, 40);
+
+        // Striking the note again starts it from no pressure
+        apply(&mut state, &[0x90, 60, 100]);
+        assert_eq!(state.channel(0).key_pressure[60], 0);
+
+        apply(&mut state, &[0xA0, 62, 30, 0xB0, RESET_CONTROLLERS, 0]);
+        assert_eq!(state.channel(0).key_pressure[62], 0);
+        assert_eq!(state.channel(0).pressure, 0);
+    }
+
+    #[test]
+    fn clock_counts_while_running() {
+        let mut state = MidiState::new();
+        apply(&mut state, &[0xF8, 0xF8]);
+        assert_eq!(state.clock_ticks, 0);
+
+        apply(&mut state, &[0xFA, 0xF8, 0xF8, 0xF8]);
+        assert!(state.running);
+        assert_eq!(state.clock_ticks, 3);
+
+        apply(&mut state, &[0xFC, 0xF8]);
+        assert!(!state.running);
+        assert_eq!(state.clock_ticks, 3);
+
+        apply(&mut state, &[0xFB, 0xF8]);
+        assert_eq!(state.clock_ticks, 4);
+
+        apply(&mut state, &[0xFA]);
+        assert_eq!(state.clock_ticks, 0);
+    }
+
+    #[test]
+    fn song_position_sets_ticks() {
+        let mut state = MidiState::new();
+        apply(&mut state, &[0xF2, 4, 0]);
+        assert_eq!(state.clock_ticks, 24);
+        assert_eq!(state.clock_ticks / TICKS_PER_BEAT, 1);
+    }
+
+    #[test]
+    fn reset_clears_everything() {
+        let mut state = MidiState::new();
+        apply(&mut state, &[0xFA, 0xF8, 0x90, 60, 100, 0xE0, 0, 0, 0xFF]);
+        assert!(!state.any_held());
+        assert!(!state.running);
+        assert_eq!(state.clock_ticks, 0);
+        assert_eq!(state.channel(0).pitch_bend, 0);
+    }
+
+    fn drain(out: &mut MidiOut) -> Vec<u8> {
+        core::iter::from_fn(|| out.pop()).collect()
+    }
+
+    #[test]
+    fn out_queue_encodes_messages() {
+        let mut out = MidiOut::new();
+        assert!(out.send(&NoteOn {
+            channel: 1,
+            note: 60,
+            velocity: 100
+        }));
+        assert!(out.send(&Clock));
+        assert!(out.send(&SysEx(&[0x7D, 1])));
+        assert!(out.send(&PitchBend {
+            channel: 0,
+            value: 0
+        }));
+        assert_eq!(out.peek(), Some(0x91));
+        assert_eq!(out.len(), 11);
+        assert_eq!(
+            drain(&mut out),
+            [0x91, 60, 100, 0xF8, 0xF0, 0x7D, 1, 0xF7, 0xE0, 0x00, 0x40]
+        );
+        assert!(out.is_empty());
+        assert_eq!(out.peek(), None);
+    }
+
+    #[test]
+    fn out_queue_drops_whole_messages_when_full() {
+        let mut out = MidiOut::new();
+        for _ in 0..OUT_LEN / 3 {
+            assert!(out.send(&NoteOff {
+                channel: 0,
+                note: 1,
+                velocity: 0
+            }));
+        }
+        assert_eq!(out.len(), OUT_LEN - OUT_LEN % 3);
+        assert!(!out.send(&NoteOff {
+            channel: 0,
+            note: 1,
+            velocity: 0
+        }));
+        assert!(out.send(&Clock));
+
+        // Wraps round once there is room again
+        out.pop();
+        out.pop();
+        out.pop();
+        assert!(out.send(&ControlChange {
+            channel: 2,
+            control: 3,
+            value: 4
+        }));
+        assert!(drain(&mut out).ends_with(&[1, 0, 0xF8, 0xB2, 3, 4]));
+    }
+
+    #[test]
+    fn out_queue_round_trips_through_parser() {
+        let mut out = MidiOut::new();
+        let messages = [
+            NoteOn {
+                channel: 9,
+                note: 36,
+                velocity: 127,
+            },
+            ControlChange {
+                channel: 9,
+                control: 74,
+                value: 12,
+            },
+            SysEx(&[1, 2, 3]),
+            Start,
+        ];
+        for message in &messages {
+            out.send(message);
+        }
+        assert_eq!(parse(&drain(&mut out)), messages);
+    }
+
+    #[test]
+    fn thru_filter_blocks_disabled_channels() {
+        let mut thru = ThruFilter::default();
+        thru.toggle(3);
+        assert!(!thru.enabled(3));
+        assert!(!thru.passes(&NoteOn {
+            channel: 3,
+            note: 60,
+            velocity: 1
+        }));
+        assert!(thru.passes(&NoteOn {
+            channel: 4,
+            note: 60,
+            velocity: 1
+        }));
+        assert!(thru.passes(&Clock));
+        assert!(thru.passes(&SysEx(&[])));
+
+        thru.toggle(3);
+        assert_eq!(thru, ThruFilter::default());
+    }
+
+    #[test]
+    fn reset_cancels_running_status() {
+        assert_eq!(parse(&[0x90, 60, 0xFF, 100, 62, 100]), [Reset]);
+    }
+}
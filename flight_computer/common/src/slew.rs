@@ -0,0 +1,256 @@
+//! Glide and bandwidth limiting applied to a channel after its mode.
+//!
+//! Everything runs per sample in Q16.16 volts so the output core never
+//! touches floats at audio rate; only changing a setting does any floating
+//! point work, to turn a time or cutoff into a step or coefficient.
+
+use crate::dsp::{ONE_VOLT, Q16};
+
+/// Coefficients are fractions of a sample's remaining distance in Q1.31.
+const COEFF_ONE: f32 = (1u32 << 31) as f32;
+
+fn samples(millis: f32, sample_rate: u32) -> u32 {
+    ((millis * sample_rate as f32 / 1000.0) as u32).max(1)
+}
+
+/// Move `coeff` of the way from `value` to `target`, always by at least one
+/// step so the target is actually reached.
+fn approach(value: Q16, target: Q16, coeff: i32) -> Q16 {
+    let distance = target - value;
+    let step = ((distance as i64 * coeff as i64) >> 31) as Q16;
+    match step {
+        0 => value + distance.signum(),
+        step => value + step,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GlideShape {
+    Off,
+    /// Fixed rate, the time is per volt
+    Linear,
+    /// RC style, the time is the time constant
+    Exponential,
+    /// Every change takes the same time, however far it goes
+    ConstantTime,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Glide {
+    shape: GlideShape,
+    /// Linear step per sample, or exponential coefficient
+    rate: i32,
+    /// Length of a constant time glide
+    glide_samples: u32,
+    target: Q16,
+    /// Constant time step and position, with 16 more fractional bits
+    step: i64,
+    position: i64,
+    remaining: u32,
+    value: Q16,
+}
+
+impl Default for Glide {
+    fn default() -> Self {
+        Glide {
+            shape: GlideShape::Off,
+            rate: 0,
+            glide_samples: 1,
+            target: 0,
+            step: 0,
+            position: 0,
+            remaining: 0,
+            value: 0,
+        }
+    }
+}
+
+impl Glide {
+    pub fn new(shape: GlideShape, millis: f32, sample_rate: u32) -> Self {
+        let mut glide = Self::default();
+        glide.configure(shape, millis, sample_rate);
+        glide
+    }
+
+    /// Change the glide without disturbing where the output currently is.
+    pub fn configure(&mut self, shape: GlideShape, millis: f32, sample_rate: u32) {
+        let samples = samples(millis, sample_rate);
+        self.shape = shape;
+        self.glide_samples = samples;
+        self.rate = match shape {
+            GlideShape::Linear => ((ONE_VOLT as u32 + samples / 2) / samples).max(1) as i32,
+            GlideShape::Exponential => exp_coeff(samples as f32),
+            GlideShape::Off | GlideShape::ConstantTime => 0,
+        };
+        self.remaining = self.remaining.min(samples);
+    }
+
+    pub fn value(&self) -> Q16 {
+        self.value
+    }
+
+    pub fn process(&mut self, target: Q16) -> Q16 {
+        self.value = match self.shape {
+            GlideShape::Off => target,
+            GlideShape::Linear => {
+                let distance = target - self.value;
+                self.value + distance.clamp(-self.rate, self.rate)
+            }
+            GlideShape::Exponential => approach(self.value, target, self.rate),
+            GlideShape::ConstantTime => {
+                if target != self.target {
+                    self.remaining = self.glide_samples;
+                    self.position = (self.value as i64) << 16;
+                    self.step = ((target - self.value) as i64) << 16;
+                    self.step /= self.glide_samples as i64;
+                }
+                if self.remaining > 1 {
+                    self.remaining -= 1;
+                    self.position += self.step;
+                    (self.position >> 16) as Q16
+                } else {
+                    // Land exactly, whatever the division left over
+                    self.remaining = 0;
+                    target
+                }
+            }
+        };
+        self.target = target;
+        self.value
+    }
+}
+
+/// Coefficient that covers 1 - 1/e of the distance in `samples`.
+fn exp_coeff(samples: f32) -> i32 {
+    coeff(one_minus_exp(1.0 / samples))
+}
+
+fn coeff(fraction: f32) -> i32 {
+    (fraction * COEFF_ONE).clamp(1.0, COEFF_ONE - 1.0) as i32
+}
+
+/// 1 - e^-x, by series while x is small enough that the subtraction would
+/// lose everything.
+fn one_minus_exp(x: f32) -> f32 {
+    if x < 0.5 {
+        x * (1.0 - x / 2.0 * (1.0 - x / 3.0 * (1.0 - x / 4.0 * (1.0 - x / 5.0))))
+    } else {
+        1.0 - micromath::F32Ext::exp(-x)
+    }
+}
+
+/// One-pole low-pass, limits how fast a channel can move.
+#[derive(Clone, Copy, Debug)]
+pub struct OnePole {
+    coeff: i32,
+    value: Q16,
+}
+
+impl OnePole {
+    pub fn new(cutoff_hz: f32, sample_rate: u32) -> Self {
+        let mut filter = OnePole { coeff: 0, value: 0 };
+        filter.set_cutoff(cutoff_hz, sample_rate);
+        filter
+    }
+
+    pub fn set_cutoff(&mut self, cutoff_hz: f32, sample_rate: u32) {
+        let x = 2.0 * core::f32::consts::PI * cutoff_hz / sample_rate as f32;
+        self.coeff = coeff(one_minus_exp(x));
+    }
+
+    /// Jump straight to `value`, as if it had been held there forever.
+    pub fn reset(&mut self, value: Q16) {
+        self.value = value;
+    }
+
+    pub fn process(&mut self, input: Q16) -> Q16 {
+        self.value = approach(self.value, input, self.coeff);
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::{from_q16, to_q16};
+
+    const RATE: u32 = 48_000;
+
+    fn run(glide: &mut Glide, target: f32, samples: u32) -> f32 {
+        let target = to_q16(target);
+        for _ in 0..samples - 1 {
+            glide.process(target);
+        }
+        from_q16(glide.process(target))
+    }
+
+    #[test]
+    fn off_follows_immediately() {
+        let mut glide = Glide::new(GlideShape::Off, 100.0, RATE);
+        assert_eq!(run(&mut glide, 3.0, 1), 3.0);
+    }
+
+    #[test]
+    fn linear_takes_time_per_volt() {
+        // 10ms per volt
+        let mut glide = Glide::new(GlideShape::Linear, 10.0, RATE);
+        assert!((run(&mut glide, 2.0, 480) - 1.0).abs() < 0.005);
+        assert!((run(&mut glide, 2.0, 480) - 2.0).abs() < 0.005);
+        assert_eq!(run(&mut glide, 2.0, 10), 2.0);
+        assert!((run(&mut glide, -2.0, 480) - 1.0).abs() < 0.005);
+    }
+
+    #[test]
+    fn exponential_covers_63_percent_in_one_time_constant() {
+        let mut glide = Glide::new(GlideShape::Exponential, 5.0, RATE);
+        let after = run(&mut glide, 1.0, 240);
+        assert!((after - (1.0 - (-1.0f32).exp())).abs() < 0.002, "{}", after);
+        assert_eq!(run(&mut glide, 1.0, 48_000), 1.0);
+    }
+
+    #[test]
+    fn constant_time_lands_on_time_for_any_distance() {
+        for distance in [0.1, 1.0, 7.3, -9.5] {
+            let mut glide = Glide::new(GlideShape::ConstantTime, 20.0, RATE);
+            let halfway = run(&mut glide, distance, 480);
+            assert!((halfway - distance / 2.0).abs() < 0.001);
+            assert_eq!(run(&mut glide, distance, 480), from_q16(to_q16(distance)));
+        }
+    }
+
+    #[test]
+    fn constant_time_restarts_on_new_target() {
+        let mut glide = Glide::new(GlideShape::ConstantTime, 10.0, RATE);
+        run(&mut glide, 4.0, 240);
+        assert!((run(&mut glide, 0.0, 240) - 1.0).abs() < 0.001);
+        assert_eq!(run(&mut glide, 0.0, 240), 0.0);
+    }
+
+    #[test]
+    fn reconfigure_keeps_position() {
+        let mut glide = Glide::new(GlideShape::Linear, 10.0, RATE);
+        let before = run(&mut glide, 5.0, 100);
+        glide.configure(GlideShape::Exponential, 10.0, RATE);
+        assert_eq!(from_q16(glide.value()), before);
+    }
+
+    #[test]
+    fn one_pole_passes_dc_and_cuts_highs() {
+        let mut filter = OnePole::new(100.0, RATE);
+        for _ in 0..RATE {
+            filter.process(ONE_VOLT);
+        }
+        assert_eq!(filter.process(ONE_VOLT), ONE_VOLT);
+
+        // 4kHz square, far above the cutoff, is heavily attenuated
+        let mut peak = 0;
+        for i in 0..RATE {
+            let input = if i / 6 % 2 == 0 { ONE_VOLT } else { -ONE_VOLT };
+            let out = filter.process(input);
+            if i > RATE / 2 {
+                peak = peak.max(out.abs());
+            }
+        }
+        assert!(from_q16(peak) < 0.05, "{}", from_q16(peak));
+    }
+}
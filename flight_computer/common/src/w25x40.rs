@@ -0,0 +1,381 @@
+//! Driver for the W25X40CL 4Mbit SPI NOR flash.
+//!
+//! On the flight computer the chip sits on SPI0 (MEM_CLK GPIO2, MEM_MOSI
+//! GPIO3, MEM_MISO GPIO4, MEM_CS GPIO5) and holds data too large for the
+//! calibration sector, such as sequences and wavetables.
+//!
+//! The firmware doesn't start the driver yet. It still drives the display
+//! on SPI0 with its chip select on GPIO5, the flash's pins, rather than
+//! on the board's display pins on SPI1 (GPIO10 and 11, CS GPIO13).
+//!
+//! Like any NOR flash, programming can only clear bits, so a region has to
+//! be erased back to 0xFF before it is written. Every operation that starts
+//! an internal program or erase cycle waits for it to finish before
+//! returning.
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{Operation, SpiDevice};
+
+/// Manufacturer, memory type and capacity as returned by JEDEC ID.
+pub const JEDEC_ID: [u8; 3] = [0xEF, 0x30, 0x13];
+
+pub const CAPACITY: u32 = 512 * 1024;
+pub const PAGE_SIZE: u32 = 256;
+pub const SECTOR_SIZE: u32 = 4 * 1024;
+pub const BLOCK_32K_SIZE: u32 = 32 * 1024;
+pub const BLOCK_64K_SIZE: u32 = 64 * 1024;
+
+/// Time from release of deep power-down to accepting commands, tRES1.
+const RELEASE_NS: u32 = 3_000;
+
+const WRITE_ENABLE: u8 = 0x06;
+const READ_STATUS: u8 = 0x05;
+const READ_DATA: u8 = 0x03;
+const PAGE_PROGRAM: u8 = 0x02;
+const SECTOR_ERASE: u8 = 0x20;
+const BLOCK_32K_ERASE: u8 = 0x52;
+const BLOCK_64K_ERASE: u8 = 0xD8;
+const CHIP_ERASE: u8 = 0xC7;
+const POWER_DOWN: u8 = 0xB9;
+const RELEASE_POWER_DOWN: u8 = 0xAB;
+const READ_JEDEC_ID: u8 = 0x9F;
+
+const STATUS_BUSY: u8 = 1 << 0;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error<E> {
+    Spi(E),
+    /// The ID read back wasn't a W25X40, usually a wiring fault.
+    UnexpectedId([u8; 3]),
+    /// The range runs past the end of the chip.
+    OutOfBounds,
+    /// An erase address isn't on its region's boundary, or a page program
+    /// would wrap within its page.
+    NotAligned,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Self {
+        Error::Spi(err)
+    }
+}
+
+pub struct W25x40<SPI> {
+    spi: SPI,
+}
+
+impl<SPI: SpiDevice> W25x40<SPI> {
+    pub fn new(spi: SPI) -> Self {
+        W25x40 { spi }
+    }
+
+    pub fn release(self) -> SPI {
+        self.spi
+    }
+
+    pub fn jedec_id(&mut self) -> Result<[u8; 3], Error<SPI::Error>> {
+        let mut id = [0; 3];
+        self.spi
+            .transaction(&mut [Operation::Write(&[READ_JEDEC_ID]), Operation::Read(&mut id)])?;
+        Ok(id)
+    }
+
+    /// Confirm a W25X40 is answering before trusting anything read from it.
+    pub fn verify_id(&mut self) -> Result<(), Error<SPI::Error>> {
+        match self.jedec_id()? {
+            JEDEC_ID => Ok(()),
+            id => Err(Error::UnexpectedId(id)),
+        }
+    }
+
+    pub fn read_status(&mut self) -> Result<u8, Error<SPI::Error>> {
+        let mut status = [0];
+        self.spi.transaction(&mut [
+            Operation::Write(&[READ_STATUS]),
+            Operation::Read(&mut status),
+        ])?;
+        Ok(status[0])
+    }
+
+    pub fn is_busy(&mut self) -> Result<bool, Error<SPI::Error>> {
+        Ok(self.read_status()? & STATUS_BUSY != 0)
+    }
+
+    /// Poll the status register until a program or erase cycle finishes.
+    pub fn wait_ready(&mut self) -> Result<(), Error<SPI::Error>> {
+        while self.is_busy()? {}
+        Ok(())
+    }
+
+    pub fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        check_bounds(address, buf.len())?;
+        self.spi.transaction(&mut [
+            Operation::Write(&command(READ_DATA, address)),
+            Operation::Read(buf),
+        ])?;
+        Ok(())
+    }
+
+    /// Program up to one page, which must not wrap past the end of its page.
+    pub fn write_page(&mut self, address: u32, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        check_bounds(address, data.len())?;
+        if address % PAGE_SIZE + data.len() as u32 > PAGE_SIZE {
+            return Err(Error::NotAligned);
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        self.write_enable()?;
+        self.spi.transaction(&mut [
+            Operation::Write(&command(PAGE_PROGRAM, address)),
+            Operation::Write(data),
+        ])?;
+        self.wait_ready()
+    }
+
+    /// Program any already erased range, a page at a time.
+    pub fn write(&mut self, mut address: u32, mut data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        check_bounds(address, data.len())?;
+        while !data.is_empty() {
+            let room = (PAGE_SIZE - address % PAGE_SIZE) as usize;
+            let (page, rest) = data.split_at(room.min(data.len()));
+            self.write_page(address, page)?;
+            address += page.len() as u32;
+            data = rest;
+        }
+        Ok(())
+    }
+
+    pub fn erase_sector(&mut self, address: u32) -> Result<(), Error<SPI::Error>> {
+        self.erase(SECTOR_ERASE, address, SECTOR_SIZE)
+    }
+
+    pub fn erase_block_32k(&mut self, address: u32) -> Result<(), Error<SPI::Error>> {
+        self.erase(BLOCK_32K_ERASE, address, BLOCK_32K_SIZE)
+    }
+
+    pub fn erase_block_64k(&mut self, address: u32) -> Result<(), Error<SPI::Error>> {
+        self.erase(BLOCK_64K_ERASE, address, BLOCK_64K_SIZE)
+    }
+
+    pub fn erase_chip(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.write_enable()?;
+        self.spi.write(&[CHIP_ERASE])?;
+        self.wait_ready()
+    }
+
+    /// Drop to about 1uA, ignoring everything but `power_up` until woken.
+    pub fn power_down(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.spi.write(&[POWER_DOWN])?;
+        Ok(())
+    }
+
+    pub fn power_up(&mut self, delay: &mut impl DelayNs) -> Result<(), Error<SPI::Error>> {
+        self.spi.write(&[RELEASE_POWER_DOWN])?;
+        delay.delay_ns(RELEASE_NS);
+        Ok(())
+    }
+
+    fn write_enable(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.spi.write(&[WRITE_ENABLE])?;
+        Ok(())
+    }
+
+    fn erase(&mut self, opcode: u8, address: u32, size: u32) -> Result<(), Error<SPI::Error>> {
+        check_bounds(address, size as usize)?;
+        if !address.is_multiple_of(size) {
+            return Err(Error::NotAligned);
+        }
+
+        self.write_enable()?;
+        self.spi.write(&command(opcode, address))?;
+        self.wait_ready()
+    }
+}
+
+/// Opcode followed by a 24 bit address, most significant byte first.
+fn command(opcode: u8, address: u32) -> [u8; 4] {
+    let [_, high, mid, low] = address.to_be_bytes();
+    [opcode, high, mid, low]
+}
+
+fn check_bounds<E>(address: u32, len: usize) -> Result<(), Error<E>> {
+    match address.checked_add(len as u32) {
+        Some(end) if end <= CAPACITY => Ok(()),
+        _ => Err(Error::OutOfBounds),
+    }
+}
+
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockFlash;
+    use super::*;
+    use embedded_hal::spi::ErrorKind;
+
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    fn flash() -> W25x40<MockFlash> {
+        W25x40::new(MockFlash::new())
+    }
+
+    #[test]
+    fn reads_jedec_id() {
+        let mut flash = flash();
+        assert_eq!(flash.jedec_id(), Ok(JEDEC_ID));
+        assert_eq!(flash.verify_id(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_wrong_id() {
+        let mut flash = W25x40::new(MockFlash::with_id([0xC2, 0x20, 0x13]));
+        assert_eq!(
+            flash.verify_id(),
+            Err(Error::UnexpectedId([0xC2, 0x20, 0x13]))
+        );
+    }
+
+    #[test]
+    fn blank_chip_reads_erased() {
+        let mut flash = flash();
+        let mut buf = [0; 16];
+        flash.read(0x1000, &mut buf).unwrap();
+        assert_eq!(buf, [0xFF; 16]);
+    }
+
+    #[test]
+    fn programs_and_reads_back_a_page() {
+        let mut flash = flash();
+        let data: [u8; 256] = core::array::from_fn(|i| i as u8);
+        flash.write_page(0x200, &data).unwrap();
+
+        let mut buf = [0; 256];
+        flash.read(0x200, &mut buf).unwrap();
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn write_splits_across_pages() {
+        let mut flash = flash();
+        let data: [u8; 600] = core::array::from_fn(|i| (i * 7) as u8);
+        flash.write(0xF0, &data).unwrap();
+
+        let mut buf = [0; 600];
+        flash.read(0xF0, &mut buf).unwrap();
+        assert_eq!(buf, data);
+        // One program for the partial first page, then two full and one partial
+        assert_eq!(flash.release().programs(), 4);
+    }
+
+    #[test]
+    fn page_program_must_not_wrap() {
+        let mut flash = flash();
+        assert_eq!(flash.write_page(0x1F0, &[0; 32]), Err(Error::NotAligned));
+    }
+
+    #[test]
+    fn programming_only_clears_bits() {
+        let mut flash = flash();
+        flash.write(0, &[0xF0]).unwrap();
+        flash.write(0, &[0x3C]).unwrap();
+
+        let mut buf = [0];
+        flash.read(0, &mut buf).unwrap();
+        assert_eq!(buf, [0x30]);
+    }
+
+    #[test]
+    fn sector_erase_clears_only_its_sector() {
+        let mut flash = flash();
+        flash.write(SECTOR_SIZE - 2, &[0; 4]).unwrap();
+        flash.erase_sector(0).unwrap();
+
+        let mut buf = [0; 4];
+        flash.read(SECTOR_SIZE - 2, &mut buf).unwrap();
+        assert_eq!(buf, [0xFF, 0xFF, 0, 0]);
+    }
+
+    #[test]
+    fn block_erases() {
+        let mut flash = flash();
+        flash.write(BLOCK_64K_SIZE - 1, &[0; 2]).unwrap();
+        flash.write(BLOCK_32K_SIZE, &[0]).unwrap();
+
+        flash.erase_block_32k(BLOCK_32K_SIZE).unwrap();
+        let mut buf = [0; 2];
+        flash.read(BLOCK_64K_SIZE - 1, &mut buf).unwrap();
+        assert_eq!(buf, [0xFF, 0]);
+
+        flash.erase_block_64k(BLOCK_64K_SIZE).unwrap();
+        flash.read(BLOCK_64K_SIZE - 1, &mut buf).unwrap();
+        assert_eq!(buf, [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn chip_erase() {
+        let mut flash = flash();
+        flash.write(0, &[0]).unwrap();
+        flash.write(CAPACITY - 1, &[0]).unwrap();
+        flash.erase_chip().unwrap();
+
+        let mut buf = [0];
+        flash.read(0, &mut buf).unwrap();
+        assert_eq!(buf, [0xFF]);
+        flash.read(CAPACITY - 1, &mut buf).unwrap();
+        assert_eq!(buf, [0xFF]);
+    }
+
+    #[test]
+    fn erase_must_be_aligned() {
+        let mut flash = flash();
+        assert_eq!(flash.erase_sector(0x100), Err(Error::NotAligned));
+        assert_eq!(flash.erase_block_32k(SECTOR_SIZE), Err(Error::NotAligned));
+        assert_eq!(
+            flash.erase_block_64k(BLOCK_32K_SIZE),
+            Err(Error::NotAligned)
+        );
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let mut flash = flash();
+        let mut buf = [0; 2];
+        assert_eq!(flash.read(CAPACITY - 1, &mut buf), Err(Error::OutOfBounds));
+        assert_eq!(flash.write(CAPACITY, &[0]), Err(Error::OutOfBounds));
+        assert_eq!(flash.erase_sector(CAPACITY), Err(Error::OutOfBounds));
+    }
+
+    #[test]
+    fn waits_out_busy_cycles() {
+        // The mock rejects any command other than a status read while busy
+        let mut flash = W25x40::new(MockFlash::new().busy_for(5));
+        flash.erase_sector(0).unwrap();
+        flash.write(0, &[1, 2, 3]).unwrap();
+        assert!(!flash.is_busy().unwrap());
+    }
+
+    #[test]
+    fn deep_power_down_ignores_commands_until_woken() {
+        let mut flash = flash();
+        flash.power_down().unwrap();
+        assert_eq!(flash.jedec_id(), Ok([0xFF; 3]));
+
+        flash.power_up(&mut NoDelay).unwrap();
+        assert_eq!(flash.jedec_id(), Ok(JEDEC_ID));
+    }
+
+    #[test]
+    fn mock_rejects_unused_operations() {
+        let mut spi = MockFlash::new();
+        let mut buf = [0; 1];
+        let mut ops = [Operation::Transfer(&mut buf, &[READ_STATUS])];
+        assert_eq!(spi.transaction(&mut ops), Err(ErrorKind::Other));
+    }
+}
@@ -0,0 +1,162 @@
+//! In-memory W25X40 that answers the SPI command set, for host tests.
+//! Built with the `mock` feature.
+
+extern crate std;
+
+use std::{vec, vec::Vec};
+
+use embedded_hal::spi::{ErrorKind, ErrorType, Operation, SpiDevice};
+
+use super::*;
+
+const STATUS_WEL: u8 = 1 << 1;
+
+pub struct MockFlash {
+    memory: Vec<u8>,
+    id: [u8; 3],
+    write_enabled: bool,
+    powered_down: bool,
+    /// Status reads a program or erase stays busy for
+    busy_cycles: u32,
+    busy: u32,
+    programs: usize,
+}
+
+impl Default for MockFlash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockFlash {
+    pub fn new() -> Self {
+        Self::with_id(JEDEC_ID)
+    }
+
+    pub fn with_id(id: [u8; 3]) -> Self {
+        MockFlash {
+            memory: vec![0xFF; CAPACITY as usize],
+            id,
+            write_enabled: false,
+            powered_down: false,
+            busy_cycles: 1,
+            busy: 0,
+            programs: 0,
+        }
+    }
+
+    pub fn busy_for(mut self, status_reads: u32) -> Self {
+        self.busy_cycles = status_reads;
+        self
+    }
+
+    /// Page program commands accepted so far.
+    pub fn programs(&self) -> usize {
+        self.programs
+    }
+
+    fn status(&self) -> u8 {
+        let busy = if self.busy > 0 { STATUS_BUSY } else { 0 };
+        let wel = if self.write_enabled { STATUS_WEL } else { 0 };
+        busy | wel
+    }
+
+    fn respond(&mut self, command: &[u8], buf: &mut [u8], offset: usize) {
+        if self.powered_down {
+            buf.fill(0xFF);
+            return;
+        }
+        match command[0] {
+            READ_STATUS => buf.fill(self.status()),
+            READ_JEDEC_ID => {
+                for (i, byte) in buf.iter_mut().enumerate() {
+                    *byte = self.id.get(offset + i).copied().unwrap_or(0xFF);
+                }
+            }
+            READ_DATA => {
+                let start = address(command) as usize + offset;
+                for (i, byte) in buf.iter_mut().enumerate() {
+                    *byte = self.memory[(start + i) % CAPACITY as usize];
+                }
+            }
+            opcode => panic!("read after unexpected command {:#04x}", opcode),
+        }
+    }
+
+    /// Act on a command once chip select goes high.
+    fn execute(&mut self, command: &[u8]) {
+        if self.powered_down {
+            self.powered_down = command[0] != RELEASE_POWER_DOWN;
+            return;
+        }
+        match command[0] {
+            WRITE_ENABLE => self.write_enabled = true,
+            POWER_DOWN => self.powered_down = true,
+            PAGE_PROGRAM if self.write_enabled => {
+                let address = address(command);
+                let page = address & !(PAGE_SIZE - 1);
+                for (i, byte) in command[4..].iter().enumerate() {
+                    // Data past the end of the page wraps round to its start
+                    let offset = (address + i as u32) % PAGE_SIZE;
+                    self.memory[(page + offset) as usize] &= byte;
+                }
+                self.programs += 1;
+                self.start_cycle();
+            }
+            SECTOR_ERASE if self.write_enabled => self.erase(command, SECTOR_SIZE),
+            BLOCK_32K_ERASE if self.write_enabled => self.erase(command, BLOCK_32K_SIZE),
+            BLOCK_64K_ERASE if self.write_enabled => self.erase(command, BLOCK_64K_SIZE),
+            CHIP_ERASE if self.write_enabled => {
+                self.memory.fill(0xFF);
+                self.start_cycle();
+            }
+            READ_STATUS => self.busy = self.busy.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    fn erase(&mut self, command: &[u8], size: u32) {
+        // The chip ignores the low address bits
+        let start = (address(command) & !(size - 1)) as usize;
+        self.memory[start..start + size as usize].fill(0xFF);
+        self.start_cycle();
+    }
+
+    fn start_cycle(&mut self) {
+        self.write_enabled = false;
+        self.busy = self.busy_cycles;
+    }
+}
+
+fn address(command: &[u8]) -> u32 {
+    u32::from_be_bytes([0, command[1], command[2], command[3]])
+}
+
+impl ErrorType for MockFlash {
+    type Error = ErrorKind;
+}
+
+impl SpiDevice for MockFlash {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+        let mut command = Vec::new();
+        let mut read = 0;
+        for op in operations {
+            match op {
+                Operation::Write(bytes) => command.extend_from_slice(bytes),
+                Operation::Read(buf) => {
+                    self.respond(&command, buf, read);
+                    read += buf.len();
+                }
+                Operation::DelayNs(_) => {}
+                // The driver only writes then reads
+                _ => return Err(ErrorKind::Other),
+            }
+        }
+
+        if self.busy > 0 && !self.powered_down && command[0] != READ_STATUS {
+            panic!("command {:#04x} sent while busy", command[0]);
+        }
+        self.execute(&command);
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+//! Render a timeline script to CSV and WAV, see `disp::render`.
+//!
+//! cargo run --bin render -- script.txt [--csv out.csv] [--wav out.wav] [--every N]
+//!
+//! Without `--csv` or `--wav` both are written beside the script.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use disp::render::Timeline;
+
+const USAGE: &str = "usage: render <script> [--csv <file>] [--wav <file>] [--every <samples>]";
+
+struct Args {
+    script: PathBuf,
+    csv: Option<PathBuf>,
+    wav: Option<PathBuf>,
+    every: usize,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = std::env::args().skip(1);
+    let mut script = None;
+    let (mut csv, mut wav, mut every) = (None, None, 1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--csv" => csv = Some(value()?.into()),
+            "--wav" => wav = Some(value()?.into()),
+            "--every" => {
+                let samples = value()?;
+                every = samples
+                    .parse()
+                    .map_err(|_| format!("bad sample count '{}'", samples))?;
+            }
+            _ if script.is_none() && !arg.starts_with("--") => script = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected '{}'", arg)),
+        }
+    }
+    let script = script.ok_or("missing script")?;
+    if csv.is_none() && wav.is_none() {
+        csv = Some(script.with_extension("csv"));
+        wav = Some(script.with_extension("wav"));
+    }
+    Ok(Args {
+        script,
+        csv,
+        wav,
+        every,
+    })
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let script = std::fs::read_to_string(&args.script)
+        .map_err(|e| format!("{}: {}", args.script.display(), e))?;
+    let recording = Timeline::parse(&script)
+        .and_then(|timeline| timeline.render())
+        .map_err(|e| format!("{}: {}", args.script.display(), e))?;
+
+    let create = |path: &PathBuf| {
+        File::create(path)
+            .map(BufWriter::new)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    };
+    if let Some(path) = &args.csv {
+        recording
+            .write_csv(&mut create(path)?, args.every)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    if let Some(path) = &args.wav {
+        recording
+            .write_wav(&mut create(path)?)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match parse_args().and_then(run) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("ERR: {}", message);
+            eprintln!("{}", USAGE);
+            ExitCode::FAILURE
+        }
+    }
+}
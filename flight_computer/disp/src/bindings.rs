@@ -0,0 +1,196 @@
+//! Keys the simulator maps to the panel's controls.
+//!
+//! Bindings are read from a plain text file, one `<key> = <action>` per
+//! line with `#` starting a comment. Keys go by their SDL names (`Right`,
+//! `Return`, `Space`, `A`, `1`, `F1`, ...), actions are an `InputEvent`
+//! name, `In <n>`/`Out <n>` to toggle a jack or `Quit`. A file only needs
+//! the keys it changes; `<key> = Unbound` drops a default.
+//!
+//! ```text
+//! # Encoder on the home row
+//! J = EncDec
+//! K = EncPush
+//! L = EncInc
+//! Space = BtnDn
+//! Return = Unbound
+//! ```
+
+use std::path::Path;
+
+use common::calibration::{NUM_INPUTS, NUM_OUTPUTS};
+use embedded_graphics_simulator::sdl2::Keycode;
+
+use crate::gui::{InputEvent, Jack};
+
+/// Looked for in the working directory, like calibration.
+pub const KEY_BINDINGS_FILE: &str = "keys.cfg";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Input(InputEvent),
+    Jack(Jack),
+    Quit,
+}
+
+impl Action {
+    fn parse(text: &str) -> Option<Self> {
+        match text.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["Quit"] => Some(Action::Quit),
+            [event] => InputEvent::from_name(event).map(Action::Input),
+            [kind, n] => Jack::from_words(kind, n).map(Action::Jack),
+            _ => None,
+        }
+    }
+}
+
+pub struct KeyBindings {
+    keys: Vec<(Keycode, Action)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        const OUTPUT_KEYS: [Keycode; NUM_OUTPUTS] = [
+            Keycode::Num1,
+            Keycode::Num2,
+            Keycode::Num3,
+            Keycode::Num4,
+            Keycode::Num5,
+            Keycode::Num6,
+            Keycode::Num7,
+            Keycode::Num8,
+        ];
+        const INPUT_KEYS: [Keycode; NUM_INPUTS] = [Keycode::F1, Keycode::F2, Keycode::F3];
+
+        let mut keys = vec![
+            (Keycode::Right, Action::Input(InputEvent::EncInc)),
+            (Keycode::Left, Action::Input(InputEvent::EncDec)),
+            (Keycode::Down, Action::Input(InputEvent::EncPush)),
+            (Keycode::Space, Action::Input(InputEvent::EncHold)),
+            (Keycode::Up, Action::Input(InputEvent::BtnUp)),
+            (Keycode::Return, Action::Input(InputEvent::BtnDn)),
+            (Keycode::Q, Action::Quit),
+        ];
+        // Pulling a cable out of, or plugging one into, a jack
+        for (ch, key) in OUTPUT_KEYS.into_iter().enumerate() {
+            keys.push((key, Action::Jack(Jack::Output(ch))));
+        }
+        for (i, key) in INPUT_KEYS.into_iter().enumerate() {
+            keys.push((key, Action::Jack(Jack::Input(i))));
+        }
+        KeyBindings { keys }
+    }
+}
+
+impl KeyBindings {
+    /// The defaults with `text`'s bindings applied on top.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut bindings = KeyBindings::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", i + 1, message);
+            let (name, action) = line
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected <key> = <action>, got '{}'", line)))?;
+            let (name, action) = (name.trim(), action.trim());
+            let key =
+                Keycode::from_name(name).ok_or_else(|| error(format!("unknown key '{}'", name)))?;
+
+            bindings.keys.retain(|(bound, _)| *bound != key);
+            if action != "Unbound" {
+                let action = Action::parse(action)
+                    .ok_or_else(|| error(format!("unknown action '{}'", action)))?;
+                bindings.keys.push((key, action));
+            }
+        }
+        Ok(bindings)
+    }
+
+    /// Bindings from `path`, or the defaults if there's no such file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn action(&self, key: Keycode) -> Option<Action> {
+        self.keys
+            .iter()
+            .find(|(bound, _)| *bound == key)
+            .map(|(_, action)| *action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let bindings = KeyBindings::default();
+        assert_eq!(
+            bindings.action(Keycode::Right),
+            Some(Action::Input(InputEvent::EncInc))
+        );
+        assert_eq!(bindings.action(Keycode::Q), Some(Action::Quit));
+        assert_eq!(
+            bindings.action(Keycode::Num8),
+            Some(Action::Jack(Jack::Output(7)))
+        );
+        assert_eq!(
+            bindings.action(Keycode::F3),
+            Some(Action::Jack(Jack::Input(2)))
+        );
+        assert_eq!(bindings.action(Keycode::Z), None);
+    }
+
+    #[test]
+    fn overrides() {
+        let text = "# home row\nK = EncPush\nSpace = BtnDn  # trailing\n\nReturn = Unbound\nRight = Out 3\nF12 = Quit\n";
+        let bindings = KeyBindings::parse(text).unwrap();
+        assert_eq!(
+            bindings.action(Keycode::K),
+            Some(Action::Input(InputEvent::EncPush))
+        );
+        assert_eq!(
+            bindings.action(Keycode::Space),
+            Some(Action::Input(InputEvent::BtnDn))
+        );
+        assert_eq!(bindings.action(Keycode::Return), None);
+        assert_eq!(
+            bindings.action(Keycode::Right),
+            Some(Action::Jack(Jack::Output(2)))
+        );
+        assert_eq!(bindings.action(Keycode::F12), Some(Action::Quit));
+        // Untouched defaults stay
+        assert_eq!(
+            bindings.action(Keycode::Left),
+            Some(Action::Input(InputEvent::EncDec))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = |text| KeyBindings::parse(text).err();
+        assert_eq!(
+            error("K EncPush"),
+            Some("line 1: expected <key> = <action>, got 'K EncPush'".into())
+        );
+        assert_eq!(
+            error("\nNoSuchKey = EncPush"),
+            Some("line 2: unknown key 'NoSuchKey'".into())
+        );
+        assert_eq!(
+            error("K = Spin"),
+            Some("line 1: unknown action 'Spin'".into())
+        );
+        assert_eq!(
+            error("K = Out 9"),
+            Some("line 1: unknown action 'Out 9'".into())
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use std::path::Path;
+
+use common::calibration::{CvCalibration, DacCalibration, INPUT_REFERENCE_VOLTS, REFERENCE_VOLTS};
+use embedded_graphics::prelude::Point;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle, StyledDrawable};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use crate::display::{BLUE, BRIGHT, FONT_10, FONT_16, TAN};
+use crate::Display;
+
+// Stand in for the calibration sector of the flight computer's flash
+const DAC_CALIBRATION_FILE: &str = "dac_calibration.bin";
+const CV_CALIBRATION_FILE: &str = "cv_calibration.bin";
+
+/// ADC readings averaged for each input reference point.
+pub const INPUT_SAMPLES: usize = 32;
+
+pub const FINE_TRIM: i32 = 1;
+pub const COARSE_TRIM: i32 = 32;
+
+pub fn load_dac(dir: &Path) -> DacCalibration {
+    std::fs::read(dir.join(DAC_CALIBRATION_FILE))
+        .ok()
+        .and_then(|bytes| DacCalibration::from_bytes(&bytes))
+        .unwrap_or_default()
+}
+
+pub fn store_dac(dir: &Path, cal: &DacCalibration) {
+    store(&dir.join(DAC_CALIBRATION_FILE), &cal.to_bytes());
+}
+
+pub fn load_cv(dir: &Path) -> CvCalibration {
+    std::fs::read(dir.join(CV_CALIBRATION_FILE))
+        .ok()
+        .and_then(|bytes| CvCalibration::from_bytes(&bytes))
+        .unwrap_or_default()
+}
+
+pub fn store_cv(dir: &Path, cal: &CvCalibration) {
+    store(&dir.join(CV_CALIBRATION_FILE), &cal.to_bytes());
+}
+
+fn store(path: &Path, bytes: &[u8]) {
+    if let Err(err) = std::fs::write(path, bytes) {
+        eprintln!("ERR: storing {}: {}", path.display(), err);
+    }
+}
+
+pub fn draw_calibrate_output(
+    disp: &mut Display,
+    window: Rectangle,
+    cal: &DacCalibration,
+    ch: u8,
+    point: u8,
+    trim: i32,
+) {
+    let style = PrimitiveStyle::with_stroke(BLUE, 1);
+    window.offset(-2).draw_styled(&style, disp).ok();
+
+    let anchor = window.top_left + Point::new(5, 5);
+    FONT_16
+        .render_aligned(
+            format_args!("Out {}", ch + 1),
+            anchor,
+            VerticalPosition::Top,
+            HorizontalAlignment::Left,
+            FontColor::Transparent(TAN),
+            disp,
+        )
+        .ok();
+
+    let code = cal.output(ch as usize).code(point as usize);
+    let lines = [
+        format!("Set {:+.1}V", REFERENCE_VOLTS[point as usize]),
+        format!("Code {}", code),
+        format!("Trim x{}", trim),
+    ];
+    for (i, line) in lines.iter().enumerate() {
+        FONT_10
+            .render_aligned(
+                line.as_str(),
+                anchor + Point::new(0, 30 + 14 * i as i32),
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(BRIGHT),
+                disp,
+            )
+            .ok();
+    }
+}
+
+pub fn draw_calibrate_input(disp: &mut Display, window: Rectangle, input: u8, point: u8, raw: u16) {
+    let style = PrimitiveStyle::with_stroke(BLUE, 1);
+    window.offset(-2).draw_styled(&style, disp).ok();
+
+    let anchor = window.top_left + Point::new(5, 5);
+    FONT_16
+        .render_aligned(
+            format_args!("In {}", input + 1),
+            anchor,
+            VerticalPosition::Top,
+            HorizontalAlignment::Left,
+            FontColor::Transparent(TAN),
+            disp,
+        )
+        .ok();
+
+    let lines = [
+        "Patch Out 1".to_string(),
+        format!("Out 1 {:+.1}V", INPUT_REFERENCE_VOLTS[point as usize]),
+        format!("Raw {}", raw),
+    ];
+    for (i, line) in lines.iter().enumerate() {
+        FONT_10
+            .render_aligned(
+                line.as_str(),
+                anchor + Point::new(0, 30 + 14 * i as i32),
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(BRIGHT),
+                disp,
+            )
+            .ok();
+    }
+}
@@ -11,7 +11,7 @@ use crate::{
     output::OutSignal,
 };
 use crate::{
-    output::{NoOutput, OutputChannel},
+    output::{InputState, GATE_VOLTS},
     parameters::Parameter,
 };
 
@@ -36,16 +36,25 @@ impl Default for ClockOut {
 }
 
 impl OutSignal for ClockOut {
-    fn next(&self) -> OutputChannel {
-        NoOutput::new().into()
+    fn num_parameters(&self) -> usize {
+        2
     }
 
-    fn prev(&self) -> OutputChannel {
-        NoOutput::new().into()
-    }
+    fn generate(&mut self, input: &InputState) -> f32 {
+        // Positive values multiply the beat, negative ones divide it
+        let period = match *self.multiplier {
+            m if m > 1 => input.beat_period / m as u32,
+            m if m < -1 => input.beat_period * m.unsigned_abs(),
+            _ => input.beat_period,
+        }
+        .max(2);
 
-    fn num_parameters(&self) -> usize {
-        2
+        let high = (period as f32 * *self.duty_cycle) as u32;
+        if input.cycle % period < high {
+            GATE_VOLTS
+        } else {
+            0.0
+        }
     }
 
     fn parameter(
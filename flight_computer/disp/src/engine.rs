@@ -0,0 +1,293 @@
+use common::calibration::{DacCalibration, NUM_INPUTS, NUM_OUTPUTS};
+use common::midi::{MidiMessage, MidiOut, MidiState, TICKS_PER_BEAT};
+
+use crate::modes;
+use crate::output::{InputState, NoOutput, OutSignal, OutputChannel, SAMPLE_RATE};
+use crate::parameters::ConfigParameter;
+use crate::slew::{SlewStage, SLEW_PARAMETERS};
+
+/// Default tempo of the master clock.
+pub const DEFAULT_BPM: u32 = 120;
+pub const MIN_BPM: u32 = 20;
+pub const MAX_BPM: u32 = 300;
+
+/// A channel's mode and slew stage, everything its configure screens set.
+#[derive(Clone)]
+pub struct ChannelConfig {
+    pub output: OutputChannel,
+    pub slew: SlewStage,
+}
+
+/// Every channel's mode and slew stage, run one sample at a time the way
+/// the output core does.
+pub struct Engine {
+    pub outputs: [OutputChannel; NUM_OUTPUTS],
+    pub slews: [SlewStage; NUM_OUTPUTS],
+    /// Calibrated CV inputs, held until changed
+    pub cv: [f32; NUM_INPUTS],
+    pub midi: MidiState,
+    pub midi_out: MidiOut,
+    /// Send MIDI clock on `midi_out`
+    pub midi_clock: bool,
+    /// Turns every channel's volts into the codes its DAC is sent
+    pub calibration: DacCalibration,
+    /// Channel held at a voltage in place of its mode while it's calibrated
+    pub reference: Option<(usize, f32)>,
+    beat_period: u32,
+    cycle: u32,
+    levels: [f32; NUM_OUTPUTS],
+    codes: [u16; NUM_OUTPUTS],
+    gates: [bool; NUM_OUTPUTS],
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine {
+            outputs: core::array::from_fn(|_| NoOutput::new().into()),
+            slews: Default::default(),
+            cv: [0.0; NUM_INPUTS],
+            midi: MidiState::new(),
+            midi_out: MidiOut::new(),
+            midi_clock: true,
+            calibration: DacCalibration::default(),
+            reference: None,
+            beat_period: SAMPLE_RATE * 60 / DEFAULT_BPM,
+            cycle: 0,
+            levels: [0.0; NUM_OUTPUTS],
+            codes: [0; NUM_OUTPUTS],
+            gates: [false; NUM_OUTPUTS],
+        }
+    }
+}
+
+impl Engine {
+    /// Set the master clock, clamped to what the patch bay can dial in.
+    pub fn set_bpm(&mut self, bpm: u32) {
+        self.beat_period = SAMPLE_RATE * 60 / bpm.clamp(MIN_BPM, MAX_BPM);
+    }
+
+    pub fn cycle(&self) -> u32 {
+        self.cycle
+    }
+
+    /// Every channel's last sample, in volts.
+    pub fn levels(&self) -> &[f32; NUM_OUTPUTS] {
+        &self.levels
+    }
+
+    /// Every channel's last sample as the DAC code the output core would
+    /// send, through that channel's calibration.
+    pub fn codes(&self) -> &[u16; NUM_OUTPUTS] {
+        &self.codes
+    }
+
+    /// Update the MIDI state with a received message.
+    pub fn receive(&mut self, message: &MidiMessage) {
+        self.midi.apply(message);
+    }
+
+    /// A channel's mode parameters followed by its slew stage's.
+    pub fn parameter(
+        &mut self,
+        ch: usize,
+        param: usize,
+    ) -> Option<(&'static str, &mut dyn ConfigParameter)> {
+        let num_params = self.outputs[ch].num_parameters();
+        if param < num_params {
+            self.outputs[ch].parameter(param)
+        } else {
+            self.slews[ch].parameter(param - num_params)
+        }
+    }
+
+    pub fn num_parameters(&self, ch: usize) -> usize {
+        self.outputs[ch].num_parameters() + SLEW_PARAMETERS
+    }
+
+    /// Channels in the group `ch` leads, 1 if it isn't linked to any.
+    fn width(&self, ch: usize) -> usize {
+        self.outputs[ch].width().clamp(1, NUM_OUTPUTS - ch)
+    }
+
+    /// The channel whose mode drives `ch`: the first of its group if it's
+    /// linked, otherwise `ch` itself. A linked channel's own mode is kept,
+    /// but doesn't run until the group no longer covers it.
+    pub fn primary(&self, ch: usize) -> usize {
+        let mut primary = 0;
+        while primary < ch {
+            let width = self.width(primary);
+            if ch < primary + width {
+                return primary;
+            }
+            primary += width;
+        }
+        ch
+    }
+
+    pub fn config(&self, ch: usize) -> ChannelConfig {
+        ChannelConfig {
+            output: self.outputs[ch].clone(),
+            slew: self.slews[ch].clone(),
+        }
+    }
+
+    /// Replace a channel's mode, ending any notes the old one left sounding.
+    pub fn set_mode(&mut self, ch: usize, output: OutputChannel) {
+        self.outputs[ch].release(&mut self.midi_out);
+        self.outputs[ch] = output;
+    }
+
+    pub fn set_config(&mut self, ch: usize, config: ChannelConfig) {
+        self.set_mode(ch, config.output);
+        self.slews[ch] = config.slew;
+    }
+
+    pub fn swap_channels(&mut self, a: usize, b: usize) {
+        self.outputs.swap(a, b);
+        self.slews.swap(a, b);
+    }
+
+    /// Put a channel back to its mode's defaults, keeping the mode.
+    pub fn reset_channel(&mut self, ch: usize) {
+        let mode = &modes::MODES[modes::index_of(&self.outputs[ch])];
+        self.set_mode(ch, (mode.new)());
+        self.slews[ch] = SlewStage::default();
+    }
+
+    /// Generate one sample on every channel.
+    pub fn tick(&mut self) {
+        if self.midi_clock && self.cycle.is_multiple_of(self.beat_period / TICKS_PER_BEAT) {
+            self.midi_out.send(&MidiMessage::Clock);
+        }
+        let input = InputState {
+            cycle: self.cycle,
+            beat_period: self.beat_period,
+            cv: self.cv,
+            outputs: self.levels,
+            gates: self.gates,
+            midi: &self.midi,
+        };
+        let mut ch = 0;
+        while ch < NUM_OUTPUTS {
+            self.outputs[ch].fit(NUM_OUTPUTS - ch);
+            let width = self.width(ch);
+            let output = &mut self.outputs[ch];
+            self.levels[ch] = self.slews[ch].process(output.generate(&input));
+            // Linked channels keep their own slew
+            for link in 1..width {
+                self.levels[ch + link] = self.slews[ch + link].process(output.linked(link));
+            }
+            self.gates[ch] = output.gate();
+            self.gates[ch + 1..ch + width].fill(false);
+            output.midi(&mut self.midi_out);
+            ch += width;
+        }
+        if let Some((ch, volts)) = self.reference {
+            self.levels[ch] = volts;
+            self.gates[ch] = false;
+        }
+        for (ch, code) in self.codes.iter_mut().enumerate() {
+            *code = self.calibration.volts_to_code(ch, self.levels[ch]);
+        }
+        self.cycle = self.cycle.wrapping_add(1);
+    }
+
+    pub fn run(&mut self, samples: u32) {
+        self.run_with(samples, |_| {});
+    }
+
+    /// Run for `samples` samples, passing every sample's levels to `each`.
+    pub fn run_with(&mut self, samples: u32, mut each: impl FnMut(&[f32; NUM_OUTPUTS])) {
+        for _ in 0..samples {
+            self.tick();
+            each(&self.levels);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::calibration::REFERENCE_VOLTS;
+
+    #[test]
+    fn codes_follow_each_channels_calibration() {
+        let mut engine = Engine::default();
+        engine.calibration.output_mut(3).trim(2, 40);
+        engine.tick();
+
+        // Every channel is at 0V, the middle reference point
+        let nominal = DacCalibration::default().output(0).code(2);
+        for (ch, code) in engine.codes().iter().enumerate() {
+            let expected = if ch == 3 { nominal + 40 } else { nominal };
+            assert_eq!(*code, expected, "channel {}", ch);
+        }
+    }
+
+    #[test]
+    fn reference_holds_a_channel() {
+        let mut engine = Engine::default();
+        engine.set_mode(1, (modes::find("ClockOut").unwrap().new)());
+        engine.calibration.output_mut(1).trim(3, -25);
+        engine.reference = Some((1, REFERENCE_VOLTS[3]));
+        for _ in 0..SAMPLE_RATE {
+            engine.tick();
+            assert_eq!(engine.levels()[1], REFERENCE_VOLTS[3]);
+            assert_eq!(engine.codes()[1], engine.calibration.output(1).code(3));
+        }
+
+        // The mode runs again once calibration is done
+        engine.reference = None;
+        engine.run(SAMPLE_RATE);
+        assert_ne!(engine.levels()[1], REFERENCE_VOLTS[3]);
+    }
+
+    #[test]
+    fn tempo_is_clamped() {
+        let mut engine = Engine::default();
+        engine.set_bpm(0);
+        assert_eq!(engine.beat_period, SAMPLE_RATE * 60 / MIN_BPM);
+        // Fast enough to round the clock's period down to nothing
+        engine.set_bpm(3_000_000);
+        assert_eq!(engine.beat_period, SAMPLE_RATE * 60 / MAX_BPM);
+        engine.set_mode(0, (modes::find("ClockOut").unwrap().new)());
+        engine.run(SAMPLE_RATE);
+    }
+
+    /// A sequencer sending on MIDI channel 1, run until its first note is on.
+    fn sounding_sequencer() -> Engine {
+        let mut engine = Engine {
+            midi_clock: false,
+            ..Default::default()
+        };
+        engine.set_mode(0, (modes::find("Sequencer").unwrap().new)());
+        let (name, channel) = engine.parameter(0, 5).unwrap();
+        assert_eq!(name, "MIDI Out");
+        channel.next();
+        engine.run(10);
+        assert_eq!(drain(&mut engine.midi_out), [0x90, 60, 100]);
+        engine
+    }
+
+    fn drain(out: &mut MidiOut) -> Vec<u8> {
+        core::iter::from_fn(|| out.pop()).collect()
+    }
+
+    #[test]
+    fn replaced_modes_end_their_notes() {
+        let replace: [fn(&mut Engine); 3] = [
+            |engine| engine.set_mode(0, NoOutput::new().into()),
+            |engine| engine.reset_channel(0),
+            |engine| {
+                let config = engine.config(1);
+                engine.set_config(0, config)
+            },
+        ];
+        for replace in replace {
+            let mut engine = sounding_sequencer();
+            replace(&mut engine);
+            assert_eq!(drain(&mut engine.midi_out), [0x80, 60, 0]);
+            engine.run(10);
+        }
+    }
+}
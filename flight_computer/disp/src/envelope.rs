@@ -0,0 +1,440 @@
+use embedded_graphics::{
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, Rectangle, StyledDrawable},
+};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use common::calibration::NUM_INPUTS;
+use common::dsp::{exp2, from_q16, scale_q16, to_q15, to_q16, ONE_VOLT};
+
+use crate::display::{BRIGHT, DARK, FONT_10, FONT_16, TAN};
+use crate::output::{InputState, OutSignal, SAMPLE_RATE};
+use crate::parameters::{Choice, ConfigParameter, LogTime, Parameter};
+use crate::Display;
+
+const TRIGGERS: [&str; 13] = [
+    "Clock", "MIDI", "CV 1", "CV 2", "CV 3", "Out 1", "Out 2", "Out 3", "Out 4", "Out 5", "Out 6",
+    "Out 7", "Out 8",
+];
+const CLOCK: usize = 0;
+const MIDI: usize = 1;
+
+const SHAPES: [&str; 2] = ["ADSR", "AR"];
+const AR: usize = 1;
+
+const GATE_MODES: [&str; 2] = ["Retrigger", "Legato"];
+const LEGATO: usize = 1;
+
+const LOOP: [&str; 2] = ["Off", "On"];
+
+// Schmitt trigger thresholds for CV and output triggers
+const GATE_HIGH: f32 = 1.0;
+const GATE_LOW: f32 = 0.5;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Clone)]
+pub struct Envelope {
+    trigger: Choice,
+    shape: Choice,
+    attack: LogTime,
+    decay: LogTime,
+    sustain: Parameter<f32>,
+    release: LogTime,
+    curve: Parameter<i32>,
+    gate_mode: Choice,
+    looping: Choice,
+    level: Parameter<f32>,
+    gate: bool,
+    stage: Stage,
+    from: f32,
+    progress: f32,
+    value: f32,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Envelope {
+            trigger: Choice::new(&TRIGGERS, CLOCK),
+            shape: Choice::new(&SHAPES, 0),
+            attack: LogTime::new(10.0),
+            decay: LogTime::new(200.0),
+            sustain: Parameter::new_saturating(0.0, 1.0, 0.05, 0.6),
+            release: LogTime::new(300.0),
+            curve: Parameter::new_saturating(-4, 4, 1, 2),
+            gate_mode: Choice::new(&GATE_MODES, 0),
+            looping: Choice::new(&LOOP, 0),
+            level: Parameter::new_saturating(1.0, 10.0, 0.5, 8.0),
+            gate: false,
+            stage: Stage::Idle,
+            from: 0.0,
+            progress: 0.0,
+            value: 0.0,
+        }
+    }
+}
+
+impl Envelope {
+    fn is_ar(&self) -> bool {
+        *self.shape == AR
+    }
+
+    fn gate_in(&self, input: &InputState) -> bool {
+        let volts = match *self.trigger {
+            CLOCK => return input.cycle % input.beat_period < input.beat_period / 2,
+            MIDI => return input.midi.any_held(),
+            cv if cv < 2 + NUM_INPUTS => input.cv[cv - 2],
+            out => input.outputs[out - 2 - NUM_INPUTS],
+        };
+        volts > if self.gate { GATE_LOW } else { GATE_HIGH }
+    }
+
+    fn millis(&self, stage: Stage) -> f32 {
+        match stage {
+            Stage::Attack => self.attack.millis(),
+            Stage::Decay => self.decay.millis(),
+            Stage::Release => self.release.millis(),
+            Stage::Idle | Stage::Sustain => f32::INFINITY,
+        }
+    }
+
+    fn target(&self, stage: Stage) -> f32 {
+        match stage {
+            Stage::Attack => 1.0,
+            Stage::Decay | Stage::Sustain => *self.sustain,
+            Stage::Idle | Stage::Release => 0.0,
+        }
+    }
+
+    /// Fraction of the way to a stage's target after `progress` of its time.
+    fn curve(&self, progress: f32) -> f32 {
+        // Positive curves move quickly at first, like an analog envelope
+        let exponent = exp2(*self.curve * ONE_VOLT / 2) as f32 / ONE_VOLT as f32;
+        1.0 - (1.0 - progress.clamp(0.0, 1.0)).powf(exponent)
+    }
+
+    fn enter(&mut self, stage: Stage) {
+        self.from = self.value;
+        self.progress = 0.0;
+        self.stage = stage;
+    }
+
+    /// Stage to move on to once `stage` reaches its target.
+    fn after(&self, stage: Stage) -> Stage {
+        let looping = *self.looping != 0 && self.gate;
+        match stage {
+            Stage::Attack if self.is_ar() => Stage::Release,
+            Stage::Attack => Stage::Decay,
+            Stage::Decay if looping => Stage::Release,
+            Stage::Decay | Stage::Sustain => Stage::Sustain,
+            Stage::Release if looping => Stage::Attack,
+            Stage::Release | Stage::Idle => Stage::Idle,
+        }
+    }
+}
+
+impl OutSignal for Envelope {
+    fn num_parameters(&self) -> usize {
+        if self.is_ar() {
+            8
+        } else {
+            10
+        }
+    }
+
+    fn parameter(&mut self, param: usize) -> Option<(&'static str, &mut dyn ConfigParameter)> {
+        // AR skips decay and sustain
+        let param = if self.is_ar() && param >= 3 {
+            param + 2
+        } else {
+            param
+        };
+        match param {
+            0 => Some(("Trigger", &mut self.trigger)),
+            1 => Some(("Shape", &mut self.shape)),
+            2 => Some(("Attack", &mut self.attack)),
+            3 => Some(("Decay", &mut self.decay)),
+            4 => Some(("Sustain", &mut self.sustain)),
+            5 => Some(("Release", &mut self.release)),
+            6 => Some(("Curve", &mut self.curve)),
+            7 => Some(("Gate Mode", &mut self.gate_mode)),
+            8 => Some(("Loop", &mut self.looping)),
+            9 => Some(("Level", &mut self.level)),
+            _ => None,
+        }
+    }
+
+    fn generate(&mut self, input: &InputState) -> f32 {
+        let gate = self.gate_in(input);
+        let rising = gate && !self.gate;
+        let falling = !gate && self.gate;
+        self.gate = gate;
+
+        if rising {
+            match (*self.gate_mode, self.stage) {
+                (LEGATO, Stage::Release) if !self.is_ar() => self.enter(Stage::Decay),
+                (LEGATO, stage) if stage != Stage::Idle => {}
+                _ => self.enter(Stage::Attack),
+            }
+        } else if falling && !self.is_ar() {
+            // AR runs its course however short the trigger
+            if !matches!(self.stage, Stage::Idle | Stage::Release) {
+                self.enter(Stage::Release);
+            }
+        }
+
+        let samples = self.millis(self.stage) * SAMPLE_RATE as f32 / 1000.0;
+        self.progress += 1.0 / samples.max(1.0);
+        let target = self.target(self.stage);
+        if self.stage == Stage::Sustain {
+            // Follow the sustain level as it's edited
+            self.from = target;
+        }
+        self.value = self.from + (target - self.from) * self.curve(self.progress);
+        if self.progress >= 1.0 {
+            let next = self.after(self.stage);
+            if next != self.stage {
+                self.enter(next);
+            }
+        }
+
+        from_q16(scale_q16(to_q16(*self.level), to_q15(self.value)))
+    }
+
+    fn draw_output(&self, disp: &mut Display, window: Rectangle) {
+        let center = window.center().y;
+        let width = (26.0 * self.value) as i32;
+        let start = Point::new(window.top_left.x + 2, center);
+        let end = Point::new(window.top_left.x + 28, center);
+        Line::new(start, end)
+            .draw_styled(&PrimitiveStyle::with_stroke(DARK, 3), disp)
+            .ok();
+        if width > 0 {
+            Line::new(start, start + Point::new(width, 0))
+                .draw_styled(&PrimitiveStyle::with_stroke(BRIGHT, 3), disp)
+                .ok();
+        }
+    }
+
+    fn draw_configure(&self, disp: &mut Display, window: Rectangle) {
+        let anchor = window.top_left + Point::new(5, 5);
+        FONT_16
+            .render_aligned(
+                "Envelope",
+                anchor,
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(TAN),
+                disp,
+            )
+            .ok();
+
+        FONT_10
+            .render_aligned(
+                format_args!("{} {}", self.shape.label(), self.trigger.label()),
+                anchor + Point::new(0, 26),
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(BRIGHT),
+                disp,
+            )
+            .ok();
+
+        let plot = Rectangle::new(
+            window.top_left + Point::new(5, 44),
+            Size::new(window.size.width - 10, window.size.height - 49),
+        );
+        self.draw_shape(disp, plot);
+    }
+}
+
+impl Envelope {
+    /// Plot a gate held long enough to reach sustain, to scale in time.
+    fn draw_shape(&self, disp: &mut Display, window: Rectangle) {
+        let (attack, decay, release) = (
+            self.attack.millis(),
+            self.decay.millis(),
+            self.release.millis(),
+        );
+        let (decay, hold, sustain) = if self.is_ar() {
+            (0.0, 0.0, 1.0)
+        } else {
+            (decay, (attack + decay + release) / 4.0, *self.sustain)
+        };
+        let total = attack + decay + hold + release;
+
+        let level_at = |t: f32| {
+            if t < attack {
+                self.curve(t / attack)
+            } else if t < attack + decay {
+                1.0 + (sustain - 1.0) * self.curve((t - attack) / decay)
+            } else if t < attack + decay + hold {
+                sustain
+            } else {
+                sustain * (1.0 - self.curve((t - attack - decay - hold) / release))
+            }
+        };
+
+        let height = window.size.height as i32 - 1;
+        let point = |x: i32| {
+            let t = total * x as f32 / window.size.width as f32;
+            let y = height - (level_at(t) * height as f32) as i32;
+            window.top_left + Point::new(x, y)
+        };
+
+        let style = PrimitiveStyle::with_stroke(BRIGHT, 1);
+        for x in 0..window.size.width as i32 - 1 {
+            Line::new(point(x), point(x + 1))
+                .draw_styled(&style, disp)
+                .ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::midi::MidiState;
+
+    const MS: u32 = SAMPLE_RATE / 1000;
+    const CV_1: usize = 2;
+
+    /// Triggered from CV 1, with stages short enough to run through quickly.
+    fn envelope() -> Envelope {
+        Envelope {
+            trigger: Choice::new(&TRIGGERS, CV_1),
+            attack: LogTime::new(1.0),
+            decay: LogTime::new(2.0),
+            release: LogTime::new(4.0),
+            ..Default::default()
+        }
+    }
+
+    /// Run `samples` with `volts` at CV 1, returning the stage each sample
+    /// ended in.
+    fn run(env: &mut Envelope, volts: f32, samples: u32) -> Vec<Stage> {
+        let midi = MidiState::new();
+        let input = InputState {
+            cv: [volts, 0.0, 0.0],
+            ..InputState::at(0, &midi)
+        };
+        (0..samples)
+            .map(|_| {
+                env.generate(&input);
+                env.stage
+            })
+            .collect()
+    }
+
+    /// Stages in the order they were entered.
+    fn stages(samples: &[Stage]) -> Vec<Stage> {
+        let mut stages = samples.to_vec();
+        stages.dedup();
+        stages
+    }
+
+    #[test]
+    fn schmitt_thresholds() {
+        let mut env = envelope();
+        run(&mut env, 0.9, 1);
+        assert_eq!(env.stage, Stage::Idle);
+        run(&mut env, 1.1, 1);
+        assert_eq!(env.stage, Stage::Attack);
+        // Held between the thresholds, the gate stays high
+        run(&mut env, 0.6, 20 * MS);
+        assert!(env.gate);
+        assert_eq!(env.stage, Stage::Sustain);
+        run(&mut env, 0.4, 1);
+        assert!(!env.gate);
+        assert_eq!(env.stage, Stage::Release);
+        // And stays low until the upper threshold again
+        run(&mut env, 0.9, 1);
+        assert!(!env.gate);
+    }
+
+    #[test]
+    fn sustain_follows_edits() {
+        let mut env = envelope();
+        assert_eq!(
+            stages(&run(&mut env, 5.0, 20 * MS)),
+            [Stage::Attack, Stage::Decay, Stage::Sustain]
+        );
+        assert!((env.value - 0.6).abs() < 1e-4);
+        env.sustain.next();
+        run(&mut env, 5.0, 1);
+        assert!((env.value - 0.65).abs() < 1e-4);
+    }
+
+    #[test]
+    fn legato_reenters_decay_from_release() {
+        let mut env = Envelope {
+            gate_mode: Choice::new(&GATE_MODES, LEGATO),
+            ..envelope()
+        };
+        run(&mut env, 5.0, 20 * MS);
+        run(&mut env, 0.0, MS);
+        assert_eq!(env.stage, Stage::Release);
+        let released = env.value;
+        assert!(released > 0.0 && released < 0.6);
+
+        // Picks up from where release got to, without a new attack
+        run(&mut env, 5.0, 1);
+        assert_eq!(env.stage, Stage::Decay);
+        assert!((env.value - released).abs() < 0.01);
+        assert_eq!(
+            stages(&run(&mut env, 5.0, 20 * MS)),
+            [Stage::Decay, Stage::Sustain]
+        );
+
+        // Retrigger starts over from the attack
+        let mut env = envelope();
+        run(&mut env, 5.0, 20 * MS);
+        run(&mut env, 0.0, MS);
+        run(&mut env, 5.0, 1);
+        assert_eq!(env.stage, Stage::Attack);
+    }
+
+    #[test]
+    fn ar_ignores_gate_off() {
+        let mut env = Envelope {
+            shape: Choice::new(&SHAPES, AR),
+            ..envelope()
+        };
+        run(&mut env, 5.0, 1);
+        let stages = stages(&run(&mut env, 0.0, 20 * MS));
+        assert_eq!(stages, [Stage::Attack, Stage::Release, Stage::Idle]);
+        assert_eq!(env.value, 0.0);
+    }
+
+    #[test]
+    fn loops_while_gate_held() {
+        let mut env = Envelope {
+            looping: Choice::new(&LOOP, 1),
+            ..envelope()
+        };
+        let looped = stages(&run(&mut env, 5.0, 30 * MS));
+        assert_eq!(
+            looped[..6],
+            [
+                Stage::Attack,
+                Stage::Decay,
+                Stage::Release,
+                Stage::Attack,
+                Stage::Decay,
+                Stage::Release
+            ]
+        );
+        assert!(!looped.contains(&Stage::Sustain));
+
+        // Let go and the release runs out
+        run(&mut env, 0.0, 20 * MS);
+        assert_eq!(env.stage, Stage::Idle);
+    }
+}
@@ -0,0 +1,93 @@
+use embedded_graphics::{
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, Rectangle, StyledDrawable},
+};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use crate::display::{BRIGHT, DARK, FONT_10, FONT_16, TAN};
+use crate::output::{InputState, OutSignal, GATE_VOLTS};
+use crate::parameters::{Choice, ConfigParameter};
+use crate::Display;
+
+const SOURCES: [&str; 8] = [
+    "Out 1", "Out 2", "Out 3", "Out 4", "Out 5", "Out 6", "Out 7", "Out 8",
+];
+
+/// Outputs the gate of another channel, pairing it with a pitch mode.
+#[derive(Clone)]
+pub struct GateOut {
+    source: Choice,
+    high: bool,
+}
+
+impl Default for GateOut {
+    fn default() -> Self {
+        GateOut {
+            source: Choice::new(&SOURCES, 0),
+            high: false,
+        }
+    }
+}
+
+impl OutSignal for GateOut {
+    fn num_parameters(&self) -> usize {
+        1
+    }
+
+    fn parameter(&mut self, param: usize) -> Option<(&'static str, &mut dyn ConfigParameter)> {
+        match param {
+            0 => Some(("Source", &mut self.source)),
+            _ => None,
+        }
+    }
+
+    fn gate(&self) -> bool {
+        self.high
+    }
+
+    fn generate(&mut self, input: &InputState) -> f32 {
+        self.high = input.gates[*self.source];
+        if self.high {
+            GATE_VOLTS
+        } else {
+            0.0
+        }
+    }
+
+    fn draw_output(&self, disp: &mut Display, window: Rectangle) {
+        let style = PrimitiveStyle::with_stroke(if self.high { BRIGHT } else { DARK }, 1);
+        let y = if self.high {
+            window.top_left.y + 4
+        } else {
+            window.center().y + 4
+        };
+        let start = Point::new(window.top_left.x + 2, y);
+        let end = Point::new(window.top_left.x + 28, y);
+        Line::new(start, end).draw_styled(&style, disp).ok();
+    }
+
+    fn draw_configure(&self, disp: &mut Display, window: Rectangle) {
+        let anchor = window.top_left + Point::new(5, 5);
+        FONT_16
+            .render_aligned(
+                "Gate",
+                anchor,
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(TAN),
+                disp,
+            )
+            .ok();
+
+        FONT_10
+            .render_aligned(
+                format_args!("Follows {}", self.source.label()),
+                anchor + Point::new(0, 26),
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(BRIGHT),
+                disp,
+            )
+            .ok();
+    }
+}
@@ -0,0 +1,1221 @@
+use std::path::PathBuf;
+
+use embedded_graphics::draw_target::DrawTarget;
+use embedded_graphics::geometry::AnchorPoint;
+use embedded_graphics::pixelcolor::Bgr565;
+use embedded_graphics::prelude::{Dimensions, Pixel, Point, PointsIter, Size};
+use embedded_graphics::primitives::{
+    CornerRadii, PrimitiveStyle, Rectangle, RoundedRectangle, StyledDrawable,
+};
+
+use common::calibration::{
+    nominal_adc_code, CvCalibration, DacCalibration, InputCalibration, SampleSet,
+    INPUT_REFERENCE_VOLTS, MAX_VOLTS, MIN_VOLTS, NUM_INPUTS, NUM_OUTPUTS, NUM_POINTS,
+    REFERENCE_VOLTS,
+};
+use common::cv_input::CvInput;
+use common::jacks::Jacks;
+use common::midi::{ThruFilter, NUM_CHANNELS};
+
+use crate::display::{BG, BLUE, BRIGHT, DARK, TAN};
+use crate::display::{FONT_10, FONT_16};
+use crate::engine::{ChannelConfig, Engine};
+use crate::output::OutSignal;
+use crate::parameters::ConfigParameter;
+use crate::{calibrate, midi, modes, Display};
+
+/// Side of the square OLED, in pixels.
+pub const SCREEN_SIZE: u32 = 128;
+
+/// Tempo of the simulated master clock.
+const SIM_BPM: u32 = 120;
+
+const SETTINGS: [&str; 5] = [
+    "Calibrate Out",
+    "Calibrate In",
+    "MIDI Thru",
+    "MIDI Clock",
+    "In Normals",
+];
+
+/// Volts an input normal moves per encoder step.
+const NORMAL_STEP: f32 = 0.1;
+
+/// What a long press on a channel offers.
+const CHANNEL_ACTIONS: [&str; 4] = ["Copy", "Paste", "Swap", "Reset"];
+
+/// Step `a` by `b` within `0..max`, wrapping at either end.
+pub fn add_wrap(a: u8, b: i8, max: u8) -> u8 {
+    if max == 0 {
+        0
+    } else {
+        (a as i16 + b as i16).rem_euclid(max as i16) as u8
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+    EncInc,
+    EncDec,
+    EncPush,
+    /// The encoder held down, a long press
+    EncHold,
+    BtnUp,
+    BtnDn,
+    None,
+}
+
+impl InputEvent {
+    /// The event named as in its variant, e.g. `EncPush`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "EncInc" => Some(InputEvent::EncInc),
+            "EncDec" => Some(InputEvent::EncDec),
+            "EncPush" => Some(InputEvent::EncPush),
+            "EncHold" => Some(InputEvent::EncHold),
+            "BtnUp" => Some(InputEvent::BtnUp),
+            "BtnDn" => Some(InputEvent::BtnDn),
+            "None" => Some(InputEvent::None),
+            _ => Option::None,
+        }
+    }
+}
+
+/// A cable pulled out of, or plugged into, a jack.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Jack {
+    Input(usize),
+    Output(usize),
+}
+
+impl Jack {
+    /// `In <n>` or `Out <n>`, counting from 1 like the panel.
+    pub fn from_words(kind: &str, number: &str) -> Option<Self> {
+        let (jack, count): (fn(usize) -> Jack, usize) = match kind {
+            "In" => (Jack::Input, NUM_INPUTS),
+            "Out" => (Jack::Output, NUM_OUTPUTS),
+            _ => return Option::None,
+        };
+        match number.parse::<usize>() {
+            Ok(n) if (1..=count).contains(&n) => Some(jack(n - 1)),
+            _ => Option::None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GuiState {
+    Idle,
+    Settings(u8),
+    CalibrateOutput(u8, u8),
+    CalibrateInput(u8, u8),
+    MidiThru(u8),
+    InputNormal(u8),
+    ChannelSelect(u8),
+    ChannelAction(u8, u8),
+    ModeSelect(u8),
+    ParameterSelect(u8, u8),
+    ParameterEdit(u8, u8),
+}
+
+/// The panel: the output engine, the screen it's configured through and
+/// the settings behind it.
+pub struct Gui {
+    pub engine: Engine,
+    state: GuiState,
+    /// Directory calibration is kept in, or `None` to keep it in memory
+    storage: Option<PathBuf>,
+    trim: i32,
+    cv_calibration: CvCalibration,
+    low_samples: SampleSet,
+    midi_thru: ThruFilter,
+    jacks: Jacks,
+    cv_in: [CvInput; NUM_INPUTS],
+    /// Volts at each input's jack, from whatever is patched into it
+    cv_sources: [f32; NUM_INPUTS],
+    /// Volts each input reads while unpatched
+    cv_normals: [f32; NUM_INPUTS],
+    /// Channel last copied from, and its configuration when copied
+    clipboard: Option<(u8, ChannelConfig)>,
+    // Screens draw here first, then the whole frame goes out at once
+    frame: Display,
+}
+
+impl Gui {
+    pub fn new(storage: Option<PathBuf>) -> Self {
+        let cv_calibration = storage
+            .as_deref()
+            .map(calibrate::load_cv)
+            .unwrap_or_default();
+        let mut engine = Engine::default();
+        engine.set_bpm(SIM_BPM);
+        engine.calibration = storage
+            .as_deref()
+            .map(calibrate::load_dac)
+            .unwrap_or_default();
+        Gui {
+            engine,
+            state: GuiState::Idle,
+            storage,
+            trim: calibrate::FINE_TRIM,
+            cv_calibration,
+            low_samples: SampleSet::default(),
+            midi_thru: ThruFilter::default(),
+            jacks: Jacks::default(),
+            cv_in: core::array::from_fn(|i| CvInput::new(*cv_calibration.input(i))),
+            cv_sources: [0.0; NUM_INPUTS],
+            cv_normals: [0.0; NUM_INPUTS],
+            clipboard: None,
+            frame: Display::new(Size::new_equal(SCREEN_SIZE)),
+        }
+    }
+
+    pub fn state(&self) -> GuiState {
+        self.state
+    }
+
+    pub fn jacks(&self) -> &Jacks {
+        &self.jacks
+    }
+
+    pub fn toggle_jack(&mut self, jack: Jack) {
+        match jack {
+            Jack::Input(i) => self.jacks.set_input(i, !self.jacks.input(i)),
+            Jack::Output(ch) => self.jacks.set_output(ch, !self.jacks.output(ch)),
+        }
+    }
+
+    pub fn set_cv(&mut self, input: usize, volts: f32) {
+        self.cv_sources[input] = volts;
+    }
+
+    fn load_dac(&self) -> DacCalibration {
+        self.storage
+            .as_deref()
+            .map(calibrate::load_dac)
+            .unwrap_or_default()
+    }
+
+    fn load_cv(&self) -> CvCalibration {
+        self.storage
+            .as_deref()
+            .map(calibrate::load_cv)
+            .unwrap_or_default()
+    }
+
+    pub fn handle(&mut self, input: InputEvent) {
+        self.state = match self.state {
+            GuiState::Settings(item) => match input {
+                InputEvent::EncInc => GuiState::Settings(add_wrap(item, 1, SETTINGS.len() as u8)),
+                InputEvent::EncDec => GuiState::Settings(add_wrap(item, -1, SETTINGS.len() as u8)),
+                InputEvent::EncPush => match item {
+                    0 => GuiState::CalibrateOutput(0, 0),
+                    1 => GuiState::CalibrateInput(0, 0),
+                    2 => GuiState::MidiThru(0),
+                    3 => {
+                        self.engine.midi_clock = !self.engine.midi_clock;
+                        GuiState::Settings(item)
+                    }
+                    _ => GuiState::InputNormal(0),
+                },
+                InputEvent::BtnUp => GuiState::Settings(item),
+                InputEvent::BtnDn => GuiState::Idle,
+                InputEvent::EncHold | InputEvent::None => GuiState::Settings(item),
+            },
+            GuiState::CalibrateOutput(ch, point) => {
+                let output = self.engine.calibration.output_mut(ch as usize);
+                match input {
+                    InputEvent::EncInc => {
+                        output.trim(point as usize, self.trim);
+                        GuiState::CalibrateOutput(ch, point)
+                    }
+                    InputEvent::EncDec => {
+                        output.trim(point as usize, -self.trim);
+                        GuiState::CalibrateOutput(ch, point)
+                    }
+                    InputEvent::EncPush => {
+                        if (point as usize) < NUM_POINTS - 1 {
+                            GuiState::CalibrateOutput(ch, point + 1)
+                        } else if (ch as usize) < NUM_OUTPUTS - 1 {
+                            GuiState::CalibrateOutput(ch + 1, 0)
+                        } else {
+                            if let Some(dir) = &self.storage {
+                                calibrate::store_dac(dir, &self.engine.calibration);
+                            }
+                            GuiState::Settings(0)
+                        }
+                    }
+                    InputEvent::BtnUp => {
+                        self.trim = if self.trim == calibrate::FINE_TRIM {
+                            calibrate::COARSE_TRIM
+                        } else {
+                            calibrate::FINE_TRIM
+                        };
+                        GuiState::CalibrateOutput(ch, point)
+                    }
+                    InputEvent::BtnDn => {
+                        // Abandon the run, keep what was stored before
+                        self.engine.calibration = self.load_dac();
+                        GuiState::Settings(0)
+                    }
+                    InputEvent::EncHold | InputEvent::None => GuiState::CalibrateOutput(ch, point),
+                }
+            }
+            GuiState::CalibrateInput(cv, point) => match input {
+                InputEvent::EncPush => {
+                    let samples: SampleSet = (0..calibrate::INPUT_SAMPLES)
+                        .map(|_| {
+                            // Read the input after Out 1 has been driven to the reference
+                            self.run(1);
+                            self.read_inputs();
+                            self.cv_in[cv as usize].raw()
+                        })
+                        .collect();
+                    if point == 0 {
+                        self.low_samples = samples;
+                        GuiState::CalibrateInput(cv, 1)
+                    } else if let Some(cal) =
+                        InputCalibration::from_samples(&self.low_samples, &samples)
+                    {
+                        self.cv_calibration.set_input(cv as usize, cal);
+                        if (cv as usize) < NUM_INPUTS - 1 {
+                            GuiState::CalibrateInput(cv + 1, 0)
+                        } else {
+                            if let Some(dir) = &self.storage {
+                                calibrate::store_cv(dir, &self.cv_calibration);
+                            }
+                            GuiState::Settings(1)
+                        }
+                    } else {
+                        eprintln!("ERR: In {} did not follow Out 1, check the patch", cv + 1);
+                        GuiState::CalibrateInput(cv, 0)
+                    }
+                }
+                InputEvent::BtnDn => {
+                    self.cv_calibration = self.load_cv();
+                    GuiState::Settings(1)
+                }
+                _ => GuiState::CalibrateInput(cv, point),
+            },
+            GuiState::MidiThru(ch) => match input {
+                InputEvent::EncInc => GuiState::MidiThru(add_wrap(ch, 1, NUM_CHANNELS as u8)),
+                InputEvent::EncDec => GuiState::MidiThru(add_wrap(ch, -1, NUM_CHANNELS as u8)),
+                InputEvent::EncPush => {
+                    self.midi_thru.toggle(ch);
+                    GuiState::MidiThru(ch)
+                }
+                InputEvent::BtnDn => GuiState::Settings(2),
+                _ => GuiState::MidiThru(ch),
+            },
+            GuiState::InputNormal(cv) => {
+                let normal = &mut self.cv_normals[cv as usize];
+                match input {
+                    InputEvent::EncInc => *normal = (*normal + NORMAL_STEP).min(MAX_VOLTS),
+                    InputEvent::EncDec => *normal = (*normal - NORMAL_STEP).max(MIN_VOLTS),
+                    _ => {}
+                }
+                match input {
+                    InputEvent::EncPush => GuiState::InputNormal(add_wrap(cv, 1, NUM_INPUTS as u8)),
+                    InputEvent::BtnDn => GuiState::Settings(4),
+                    _ => GuiState::InputNormal(cv),
+                }
+            }
+            GuiState::Idle => {
+                match input {
+                    InputEvent::EncInc
+                    | InputEvent::EncDec
+                    | InputEvent::EncPush
+                    | InputEvent::EncHold => GuiState::ChannelSelect(0),
+                    InputEvent::BtnUp => {
+                        // Output.play_pause();
+                        GuiState::Idle
+                    }
+                    InputEvent::BtnDn => GuiState::Settings(0),
+                    InputEvent::None => GuiState::Idle,
+                }
+            }
+            GuiState::ChannelSelect(ch) => {
+                match input {
+                    InputEvent::EncInc => {
+                        GuiState::ChannelSelect(add_wrap(ch, 1, NUM_OUTPUTS as u8))
+                    }
+                    InputEvent::EncDec => {
+                        GuiState::ChannelSelect(add_wrap(ch, -1, NUM_OUTPUTS as u8))
+                    }
+                    // A linked channel is configured through its group's first
+                    InputEvent::EncPush => {
+                        GuiState::ModeSelect(self.engine.primary(ch as usize) as u8)
+                    }
+                    InputEvent::EncHold => GuiState::ChannelAction(ch, 0),
+                    InputEvent::BtnUp => {
+                        // Ouput.play_pause()
+                        GuiState::ChannelSelect(ch)
+                    }
+                    InputEvent::BtnDn => GuiState::Idle,
+                    InputEvent::None => GuiState::ChannelSelect(ch),
+                }
+            }
+            GuiState::ChannelAction(ch, action) => match input {
+                InputEvent::EncInc => {
+                    GuiState::ChannelAction(ch, add_wrap(action, 1, CHANNEL_ACTIONS.len() as u8))
+                }
+                InputEvent::EncDec => {
+                    GuiState::ChannelAction(ch, add_wrap(action, -1, CHANNEL_ACTIONS.len() as u8))
+                }
+                InputEvent::EncPush => {
+                    if self.channel_action(ch, action) {
+                        GuiState::ChannelSelect(ch)
+                    } else {
+                        GuiState::ChannelAction(ch, action)
+                    }
+                }
+                InputEvent::EncHold | InputEvent::BtnUp | InputEvent::BtnDn => {
+                    GuiState::ChannelSelect(ch)
+                }
+                InputEvent::None => GuiState::ChannelAction(ch, action),
+            },
+            GuiState::ModeSelect(ch) => {
+                match input {
+                    InputEvent::EncInc => {
+                        let mode = modes::step(&self.engine.outputs[ch as usize], 1);
+                        self.engine.set_mode(ch as usize, mode);
+                        GuiState::ModeSelect(ch)
+                    }
+                    InputEvent::EncDec => {
+                        let mode = modes::step(&self.engine.outputs[ch as usize], -1);
+                        self.engine.set_mode(ch as usize, mode);
+                        GuiState::ModeSelect(ch)
+                    }
+                    InputEvent::EncPush => GuiState::ParameterSelect(ch, 0),
+                    InputEvent::BtnUp => {
+                        // Ouput.play_pause()
+                        GuiState::ChannelSelect(ch)
+                    }
+                    InputEvent::BtnDn => GuiState::Idle,
+                    InputEvent::EncHold | InputEvent::None => GuiState::ModeSelect(ch),
+                }
+            }
+            GuiState::ParameterSelect(ch, param) => {
+                let num_params = self.engine.num_parameters(ch as usize) as u8;
+                match input {
+                    InputEvent::EncInc => {
+                        GuiState::ParameterSelect(ch, add_wrap(param, 1, num_params))
+                    }
+                    InputEvent::EncDec => {
+                        GuiState::ParameterSelect(ch, add_wrap(param, -1, num_params))
+                    }
+                    InputEvent::EncPush => GuiState::ParameterEdit(ch, param),
+                    InputEvent::BtnUp => {
+                        // Ouput.play_pause()
+                        GuiState::ParameterSelect(ch, param)
+                    }
+                    InputEvent::BtnDn => GuiState::ChannelSelect(ch),
+                    InputEvent::EncHold | InputEvent::None => GuiState::ParameterSelect(ch, param),
+                }
+            }
+            GuiState::ParameterEdit(ch, param) => {
+                if let Some((_, parameter)) = self.engine.parameter(ch as usize, param as usize) {
+                    match input {
+                        InputEvent::EncInc => {
+                            parameter.next();
+                            GuiState::ParameterEdit(ch, param)
+                        }
+                        InputEvent::EncDec => {
+                            parameter.prev();
+                            GuiState::ParameterEdit(ch, param)
+                        }
+                        InputEvent::EncPush => {
+                            if parameter.push() {
+                                GuiState::ParameterEdit(ch, param)
+                            } else {
+                                GuiState::ParameterSelect(ch, param)
+                            }
+                        }
+                        InputEvent::BtnUp => {
+                            // Ouput.play_pause()
+                            GuiState::ParameterEdit(ch, param)
+                        }
+                        InputEvent::BtnDn => GuiState::ParameterSelect(ch, param),
+                        InputEvent::EncHold | InputEvent::None => {
+                            GuiState::ParameterEdit(ch, param)
+                        }
+                    }
+                } else {
+                    // error?!
+                    GuiState::ParameterSelect(ch, param)
+                }
+            }
+        };
+    }
+
+    /// Apply one of `CHANNEL_ACTIONS` to `ch`. Pasting and swapping need
+    /// something copied first, without it they do nothing and return false.
+    fn channel_action(&mut self, ch: u8, action: u8) -> bool {
+        match (action, &self.clipboard) {
+            (0, _) => self.clipboard = Some((ch, self.engine.config(ch as usize))),
+            (1, Some((_, config))) => self.engine.set_config(ch as usize, config.clone()),
+            // With the channel copied from, as it is now
+            (2, Some((from, _))) => self.engine.swap_channels(*from as usize, ch as usize),
+            (3, _) => self.engine.reset_channel(ch as usize),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Run the outputs for `samples` samples.
+    pub fn run(&mut self, samples: u32) {
+        self.run_with(samples, |_| {});
+    }
+
+    /// Run the outputs, passing every sample's levels to `each`.
+    pub fn run_with(&mut self, samples: u32, each: impl FnMut(&[f32; NUM_OUTPUTS])) {
+        self.read_inputs();
+        self.engine.cv = core::array::from_fn(|i| self.cv_in[i].volts());
+        self.engine.reference = match self.state {
+            // Hold the channel being trimmed where it can be metered
+            GuiState::CalibrateOutput(ch, point) => {
+                Some((ch as usize, REFERENCE_VOLTS[point as usize]))
+            }
+            GuiState::CalibrateInput(_, point) => Some((0, INPUT_REFERENCE_VOLTS[point as usize])),
+            _ => None,
+        };
+        self.engine.run_with(samples, each);
+    }
+
+    /// Take an ADC reading of every input. The input being calibrated has
+    /// Out 1 patched into it while its jack is in, the rest read the patch
+    /// bay's sliders.
+    fn read_inputs(&mut self) {
+        for (i, cv) in self.cv_in.iter_mut().enumerate() {
+            let volts = match self.state {
+                GuiState::CalibrateInput(input, _)
+                    if input as usize == i && self.jacks.input(i) =>
+                {
+                    self.engine.levels()[0]
+                }
+                _ => self.cv_sources[i],
+            };
+            cv.set_calibration(*self.cv_calibration.input(i));
+            cv.set_raw(nominal_adc_code(volts));
+            cv.set_patched(self.jacks.input(i));
+            cv.set_normal(self.cv_normals[i]);
+        }
+    }
+
+    /// Everything the outputs sent over MIDI since the last call.
+    pub fn take_midi_out(&mut self) -> Vec<u8> {
+        core::iter::from_fn(|| self.engine.midi_out.pop()).collect()
+    }
+
+    /// Draw the current screen and copy it to `target`.
+    pub fn render<D>(&mut self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = Bgr565>,
+    {
+        self.frame.clear(BG).ok();
+
+        let main_window = Rectangle::new(Point::new(0, 10), Size::new(128, 80));
+        draw_output_state(&mut self.frame, &self.engine, &self.jacks);
+
+        match self.state {
+            GuiState::Idle => draw_idle(&mut self.frame, main_window),
+            GuiState::Settings(item) => {
+                draw_settings(&mut self.frame, main_window, item, self.engine.midi_clock)
+            }
+            GuiState::MidiThru(ch) => {
+                midi::draw_midi_thru(&mut self.frame, main_window, &self.midi_thru, ch)
+            }
+            GuiState::InputNormal(cv) => {
+                draw_input_normals(&mut self.frame, main_window, &self.cv_normals, cv)
+            }
+            GuiState::CalibrateOutput(ch, point) => calibrate::draw_calibrate_output(
+                &mut self.frame,
+                main_window,
+                &self.engine.calibration,
+                ch,
+                point,
+                self.trim,
+            ),
+            GuiState::CalibrateInput(cv, point) => calibrate::draw_calibrate_input(
+                &mut self.frame,
+                main_window,
+                cv,
+                point,
+                self.cv_in[cv as usize].raw(),
+            ),
+            GuiState::ChannelSelect(ch) => {
+                draw_channel(&mut self.frame, ch);
+                let window = Rectangle::new(Point::new(32, 10), Size::new(96, 80));
+                let primary = self.engine.primary(ch as usize);
+                if primary == ch as usize {
+                    self.engine.outputs[ch as usize].draw_configure(&mut self.frame, window)
+                } else {
+                    draw_linked(&mut self.frame, window, primary)
+                }
+            }
+            GuiState::ChannelAction(ch, action) => {
+                draw_channel(&mut self.frame, ch);
+                let window = Rectangle::new(Point::new(32, 10), Size::new(96, 80));
+                let copied = self.clipboard.as_ref().map(|(from, _)| *from);
+                draw_channel_actions(&mut self.frame, window, action, copied)
+            }
+            GuiState::ModeSelect(ch) => {
+                draw_channel(&mut self.frame, ch);
+                let window = Rectangle::new(Point::new(32, 10), Size::new(96, 80));
+                let index = modes::index_of(&self.engine.outputs[ch as usize]);
+                modes::draw_carousel(&mut self.frame, window, index)
+            }
+            GuiState::ParameterSelect(ch, param) | GuiState::ParameterEdit(ch, param) => {
+                draw_channel(&mut self.frame, ch);
+                let window = Rectangle::new(Point::new(32, 10), Size::new(96, 80));
+                let editing = matches!(self.state, GuiState::ParameterEdit(..));
+                draw_parameter(
+                    &mut self.frame,
+                    window,
+                    self.engine.parameter(ch as usize, param as usize),
+                    editing,
+                );
+            }
+        }
+
+        let area = self.frame.bounding_box();
+        let pixels = area.points().map(|p| self.frame.get_pixel(p));
+        target.fill_contiguous(&area, pixels)
+    }
+}
+
+fn draw_idle(display: &mut Display, window: Rectangle) {
+    let style = PrimitiveStyle::with_stroke(BLUE, 1);
+    window.offset(-2).draw_styled(&style, display);
+    let text_pos = window.anchor_point(AnchorPoint::CenterLeft) + Point::new(5, 0);
+
+    //Text::new("IDLE SCREEN", text_pos, TEXT_X10).draw(display);
+    FONT_10.render_aligned(
+        "Idle Screen",
+        text_pos,
+        u8g2_fonts::types::VerticalPosition::Bottom,
+        u8g2_fonts::types::HorizontalAlignment::Left,
+        u8g2_fonts::types::FontColor::Transparent(DARK),
+        display,
+    );
+}
+
+fn draw_settings(display: &mut Display, window: Rectangle, selected: u8, midi_clock: bool) {
+    let style = PrimitiveStyle::with_stroke(BLUE, 1);
+    window.offset(-2).draw_styled(&style, display).ok();
+
+    for (i, name) in SETTINGS.iter().enumerate() {
+        let color = if i == selected as usize { BRIGHT } else { DARK };
+        let value = match i {
+            3 if midi_clock => " On",
+            3 => " Off",
+            _ => "",
+        };
+        FONT_10
+            .render_aligned(
+                format_args!("{}{}", name, value),
+                window.top_left + Point::new(5, 5 + 14 * i as i32),
+                u8g2_fonts::types::VerticalPosition::Top,
+                u8g2_fonts::types::HorizontalAlignment::Left,
+                u8g2_fonts::types::FontColor::Transparent(color),
+                display,
+            )
+            .ok();
+    }
+}
+
+fn draw_input_normals(
+    display: &mut Display,
+    window: Rectangle,
+    normals: &[f32; NUM_INPUTS],
+    selected: u8,
+) {
+    let style = PrimitiveStyle::with_stroke(BLUE, 1);
+    window.offset(-2).draw_styled(&style, display).ok();
+
+    let anchor = window.top_left + Point::new(5, 5);
+    FONT_16
+        .render_aligned(
+            "In Normals",
+            anchor,
+            u8g2_fonts::types::VerticalPosition::Top,
+            u8g2_fonts::types::HorizontalAlignment::Left,
+            u8g2_fonts::types::FontColor::Transparent(TAN),
+            display,
+        )
+        .ok();
+
+    for (i, volts) in normals.iter().enumerate() {
+        let color = if i == selected as usize { BRIGHT } else { DARK };
+        FONT_10
+            .render_aligned(
+                format_args!("In {} {:+.1}V", i + 1, volts),
+                anchor + Point::new(0, 30 + 14 * i as i32),
+                u8g2_fonts::types::VerticalPosition::Top,
+                u8g2_fonts::types::HorizontalAlignment::Left,
+                u8g2_fonts::types::FontColor::Transparent(color),
+                display,
+            )
+            .ok();
+    }
+}
+
+/// `copied` is the channel on the clipboard, pasting and swapping are
+/// dimmed without one.
+fn draw_channel_actions(
+    display: &mut Display,
+    window: Rectangle,
+    selected: u8,
+    copied: Option<u8>,
+) {
+    let style = PrimitiveStyle::with_stroke(BLUE, 1);
+    window.offset(-1).draw_styled(&style, display).ok();
+
+    for (i, name) in CHANNEL_ACTIONS.iter().enumerate() {
+        let available = copied.is_some() || !matches!(i, 1 | 2);
+        let color = match (i == selected as usize, available) {
+            (true, true) => BRIGHT,
+            (false, true) => TAN,
+            (true, false) => BLUE,
+            (false, false) => DARK,
+        };
+        let from = match copied {
+            Some(from) if matches!(i, 1 | 2) => format!(" {}", from),
+            _ => String::new(),
+        };
+        FONT_10
+            .render_aligned(
+                format_args!("{}{}", name, from),
+                window.top_left + Point::new(5, 5 + 14 * i as i32),
+                u8g2_fonts::types::VerticalPosition::Top,
+                u8g2_fonts::types::HorizontalAlignment::Left,
+                u8g2_fonts::types::FontColor::Transparent(color),
+                display,
+            )
+            .ok();
+    }
+}
+
+fn draw_linked(display: &mut Display, window: Rectangle, primary: usize) {
+    FONT_10
+        .render_aligned(
+            format_args!("Linked to {}", primary),
+            window.top_left + Point::new(5, 5),
+            u8g2_fonts::types::VerticalPosition::Top,
+            u8g2_fonts::types::HorizontalAlignment::Left,
+            u8g2_fonts::types::FontColor::Transparent(TAN),
+            display,
+        )
+        .ok();
+}
+
+fn draw_channel(display: &mut Display, ch: u8) {
+    FONT_16
+        .render_aligned(
+            format_args!("{}", ch),
+            Point::new(5, 45),
+            u8g2_fonts::types::VerticalPosition::Bottom,
+            u8g2_fonts::types::HorizontalAlignment::Left,
+            u8g2_fonts::types::FontColor::Transparent(BRIGHT),
+            display,
+        )
+        .ok();
+}
+
+fn draw_parameter(
+    display: &mut Display,
+    window: Rectangle,
+    parameter: Option<(&'static str, &mut dyn ConfigParameter)>,
+    editing: bool,
+) {
+    let Some((name, parameter)) = parameter else {
+        return;
+    };
+
+    let frame = PrimitiveStyle::with_stroke(if editing { BLUE } else { DARK }, 1);
+    window.offset(-1).draw_styled(&frame, display).ok();
+
+    FONT_10
+        .render_aligned(
+            name,
+            window.top_left + Point::new(5, 5),
+            u8g2_fonts::types::VerticalPosition::Top,
+            u8g2_fonts::types::HorizontalAlignment::Left,
+            u8g2_fonts::types::FontColor::Transparent(TAN),
+            display,
+        )
+        .ok();
+
+    let value = Rectangle::new(
+        window.top_left + Point::new(5, 24),
+        window.size - Size::new(10, 29),
+    );
+    parameter.draw_edit(display, value);
+}
+
+/// Every channel's box on the strip, outlined in blue when it's part of
+/// a group.
+fn draw_output_state(display: &mut Display, engine: &Engine, jacks: &Jacks) {
+    let output_disp_corners = [
+        Point::new(0, 94),
+        Point::new(32, 94),
+        Point::new(64, 94),
+        Point::new(96, 94),
+        Point::new(0, 111),
+        Point::new(32, 111),
+        Point::new(64, 111),
+        Point::new(96, 111),
+    ];
+
+    // Draw current output state
+    for (ch, corner) in output_disp_corners.into_iter().enumerate() {
+        let rect = Rectangle::new(corner, Size::new(32, 16));
+        let primary = engine.primary(ch);
+        let out = &engine.outputs[primary];
+        if primary == ch {
+            out.draw_output(display, rect);
+        } else {
+            out.draw_linked(ch - primary, display, rect);
+        }
+        let grouped = primary != ch || out.width() > 1;
+        let style = PrimitiveStyle::with_stroke(if grouped { BLUE } else { DARK }, 1);
+
+        if !jacks.output(ch) {
+            // Dim unpatched outputs by blanking every other pixel
+            let stipple = rect
+                .points()
+                .filter(|p| (p.x + p.y) % 2 == 0)
+                .map(|p| Pixel(p, BG));
+            display.draw_iter(stipple).ok();
+        }
+
+        let r = RoundedRectangle::new(
+            Rectangle::new(corner, Size::new(32, 15)),
+            CornerRadii::new(Size::new_equal(3)),
+        );
+        r.draw_styled(&style, display);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::SAMPLE_RATE;
+    use InputEvent::{BtnDn, BtnUp, EncDec, EncHold, EncInc, EncPush};
+
+    fn gui_in(state: GuiState) -> Gui {
+        let mut gui = Gui::new(None);
+        gui.state = state;
+        gui
+    }
+
+    /// State after `inputs`, starting from `state`.
+    fn after(state: GuiState, inputs: &[InputEvent]) -> GuiState {
+        let mut gui = gui_in(state);
+        for input in inputs {
+            gui.handle(*input);
+        }
+        gui.state
+    }
+
+    fn mode_name(gui: &Gui, ch: usize) -> &'static str {
+        modes::MODES[modes::index_of(&gui.engine.outputs[ch])].name
+    }
+
+    #[test]
+    fn add_wrap_edges() {
+        assert_eq!(add_wrap(0, 1, 3), 1);
+        assert_eq!(add_wrap(2, 1, 3), 0);
+        assert_eq!(add_wrap(0, -1, 3), 2);
+        assert_eq!(add_wrap(0, 1, 1), 0);
+        assert_eq!(add_wrap(0, -1, 1), 0);
+        // Nothing to select
+        assert_eq!(add_wrap(0, 1, 0), 0);
+        assert_eq!(add_wrap(0, -1, 0), 0);
+        // Past the end after the list shrank
+        assert_eq!(add_wrap(12, 1, 11), 2);
+        assert_eq!(add_wrap(255, 1, 255), 1);
+    }
+
+    #[test]
+    fn idle() {
+        use GuiState::*;
+        for input in [EncInc, EncDec, EncPush] {
+            assert_eq!(after(Idle, &[input]), ChannelSelect(0));
+        }
+        assert_eq!(after(Idle, &[BtnUp]), Idle);
+        assert_eq!(after(Idle, &[BtnDn]), Settings(0));
+        assert_eq!(after(Idle, &[InputEvent::None]), Idle);
+    }
+
+    #[test]
+    fn settings() {
+        use GuiState::*;
+        assert_eq!(after(Settings(0), &[EncInc]), Settings(1));
+        assert_eq!(after(Settings(4), &[EncInc]), Settings(0));
+        assert_eq!(after(Settings(0), &[EncDec]), Settings(4));
+        assert_eq!(after(Settings(0), &[EncPush]), CalibrateOutput(0, 0));
+        assert_eq!(after(Settings(1), &[EncPush]), CalibrateInput(0, 0));
+        assert_eq!(after(Settings(2), &[EncPush]), MidiThru(0));
+        assert_eq!(after(Settings(4), &[EncPush]), InputNormal(0));
+        assert_eq!(after(Settings(2), &[BtnUp, InputEvent::None]), Settings(2));
+        assert_eq!(after(Settings(2), &[BtnDn]), Idle);
+
+        let mut gui = gui_in(Settings(3));
+        gui.handle(EncPush);
+        assert_eq!(gui.state, Settings(3));
+        assert!(!gui.engine.midi_clock);
+        gui.handle(EncPush);
+        assert!(gui.engine.midi_clock);
+    }
+
+    #[test]
+    fn calibrate_output() {
+        use GuiState::*;
+        let mut gui = gui_in(CalibrateOutput(2, 1));
+        let code = gui.engine.calibration.output(2).code(1);
+        gui.handle(EncInc);
+        assert_eq!(gui.engine.calibration.output(2).code(1), code + 1);
+        gui.handle(BtnUp);
+        gui.handle(EncDec);
+        assert_eq!(
+            gui.engine.calibration.output(2).code(1) as i32,
+            code as i32 + 1 - calibrate::COARSE_TRIM
+        );
+        gui.handle(BtnUp);
+        assert_eq!(gui.trim, calibrate::FINE_TRIM);
+        assert_eq!(gui.state, CalibrateOutput(2, 1));
+
+        // Abandoning goes back to what was stored
+        gui.handle(BtnDn);
+        assert_eq!(gui.state, Settings(0));
+        assert_eq!(gui.engine.calibration.output(2).code(1), code);
+
+        let last = NUM_POINTS as u8 - 1;
+        assert_eq!(
+            after(CalibrateOutput(0, 0), &[EncPush]),
+            CalibrateOutput(0, 1)
+        );
+        assert_eq!(
+            after(CalibrateOutput(0, last), &[EncPush]),
+            CalibrateOutput(1, 0)
+        );
+        let last_ch = NUM_OUTPUTS as u8 - 1;
+        assert_eq!(
+            after(CalibrateOutput(last_ch, last), &[EncPush]),
+            Settings(0)
+        );
+        assert_eq!(
+            after(CalibrateOutput(0, 0), &[InputEvent::None]),
+            CalibrateOutput(0, 0)
+        );
+    }
+
+    #[test]
+    fn calibrated_output_holds_its_reference() {
+        use GuiState::*;
+        let mut gui = gui_in(CalibrateOutput(2, 1));
+        gui.handle(EncInc);
+        gui.run(SAMPLE_RATE);
+        assert_eq!(gui.engine.levels()[2], REFERENCE_VOLTS[1]);
+        assert_eq!(
+            gui.engine.codes()[2],
+            gui.engine.calibration.output(2).code(1)
+        );
+
+        // Following the next point, then back to the mode when done
+        gui.handle(EncPush);
+        gui.run(1);
+        assert_eq!(gui.engine.levels()[2], REFERENCE_VOLTS[2]);
+        gui.handle(BtnDn);
+        gui.run(1);
+        assert_eq!(gui.engine.reference, None);
+    }
+
+    #[test]
+    fn calibrate_input() {
+        use GuiState::*;
+        let mut gui = gui_in(CalibrateInput(0, 0));
+        gui.handle(EncPush);
+        assert_eq!(gui.state, CalibrateInput(0, 1));
+        gui.handle(EncPush);
+        assert_eq!(gui.state, CalibrateInput(1, 0));
+        for _ in 0..4 {
+            gui.handle(EncPush);
+        }
+        assert_eq!(gui.state, Settings(1));
+
+        assert_eq!(
+            after(CalibrateInput(1, 1), &[EncInc, EncDec, BtnUp]),
+            CalibrateInput(1, 1)
+        );
+        assert_eq!(after(CalibrateInput(1, 1), &[BtnDn]), Settings(1));
+    }
+
+    #[test]
+    fn calibrate_input_follows_out_1() {
+        use GuiState::*;
+        let mut gui = gui_in(CalibrateInput(2, 0));
+        gui.handle(EncPush);
+        assert_eq!(gui.engine.levels()[0], INPUT_REFERENCE_VOLTS[0]);
+        gui.handle(EncPush);
+        assert_eq!(gui.engine.levels()[0], INPUT_REFERENCE_VOLTS[1]);
+        assert_eq!(gui.state, Settings(1));
+        let cal = gui.cv_calibration.input(2);
+        for volts in INPUT_REFERENCE_VOLTS {
+            assert!((cal.volts(nominal_adc_code(volts)) - volts).abs() < 0.01);
+        }
+
+        // Unplugged, the input reads the same whatever Out 1 does
+        let mut gui = gui_in(CalibrateInput(1, 0));
+        gui.toggle_jack(Jack::Input(1));
+        gui.set_cv(1, 2.0);
+        gui.handle(EncPush);
+        gui.handle(EncPush);
+        assert_eq!(gui.state, CalibrateInput(1, 0));
+        assert_eq!(*gui.cv_calibration.input(1), InputCalibration::default());
+    }
+
+    #[test]
+    fn input_normals() {
+        use GuiState::*;
+        let mut gui = gui_in(InputNormal(0));
+        gui.handle(EncPush);
+        assert_eq!(gui.state, InputNormal(1));
+        for _ in 0..25 {
+            gui.handle(EncInc);
+        }
+        gui.handle(EncDec);
+        assert!((gui.cv_normals[1] - 2.4).abs() < 1e-4);
+        assert_eq!(gui.cv_normals[0], 0.0);
+
+        // Only read while the input is unpatched
+        gui.set_cv(1, -3.0);
+        gui.run(1);
+        assert!((gui.engine.cv[1] + 3.0).abs() < 0.01);
+        gui.toggle_jack(Jack::Input(1));
+        gui.run(1);
+        assert!((gui.engine.cv[1] - 2.4).abs() < 1e-4);
+
+        assert_eq!(after(InputNormal(2), &[EncPush]), InputNormal(0));
+        assert_eq!(after(InputNormal(2), &[BtnDn]), Settings(4));
+    }
+
+    #[test]
+    fn midi_thru() {
+        use GuiState::*;
+        assert_eq!(after(MidiThru(0), &[EncInc]), MidiThru(1));
+        assert_eq!(after(MidiThru(0), &[EncDec]), MidiThru(15));
+        assert_eq!(after(MidiThru(15), &[EncInc]), MidiThru(0));
+        assert_eq!(after(MidiThru(4), &[BtnUp, InputEvent::None]), MidiThru(4));
+        assert_eq!(after(MidiThru(4), &[BtnDn]), Settings(2));
+
+        let mut gui = gui_in(MidiThru(4));
+        gui.handle(EncPush);
+        assert_eq!(gui.state, MidiThru(4));
+        assert!(!gui.midi_thru.enabled(4));
+        assert!(gui.midi_thru.enabled(3));
+    }
+
+    #[test]
+    fn channel_select() {
+        use GuiState::*;
+        assert_eq!(after(ChannelSelect(0), &[EncInc]), ChannelSelect(1));
+        assert_eq!(after(ChannelSelect(0), &[EncDec]), ChannelSelect(7));
+        assert_eq!(after(ChannelSelect(7), &[EncInc]), ChannelSelect(0));
+        assert_eq!(after(ChannelSelect(3), &[EncPush]), ModeSelect(3));
+        assert_eq!(
+            after(ChannelSelect(3), &[BtnUp, InputEvent::None]),
+            ChannelSelect(3)
+        );
+        assert_eq!(after(ChannelSelect(3), &[BtnDn]), Idle);
+    }
+
+    /// Long press `ch` and pick the `action`th channel action.
+    fn channel_action(gui: &mut Gui, ch: u8, action: usize) {
+        gui.state = GuiState::ChannelSelect(ch);
+        gui.handle(EncHold);
+        for _ in 0..action {
+            gui.handle(EncInc);
+        }
+        gui.handle(EncPush);
+    }
+
+    /// Samples `ch` is high for over a beat.
+    fn high_samples(gui: &mut Gui, ch: usize) -> usize {
+        let mut high = 0;
+        gui.run_with(SAMPLE_RATE * 60 / SIM_BPM, |levels| {
+            high += (levels[ch] > 1.0) as usize
+        });
+        high
+    }
+
+    #[test]
+    fn channel_actions() {
+        use GuiState::*;
+        assert_eq!(after(ChannelSelect(3), &[EncHold]), ChannelAction(3, 0));
+        assert_eq!(
+            after(ChannelSelect(3), &[EncHold, EncDec]),
+            ChannelAction(3, 3)
+        );
+        assert_eq!(after(ChannelAction(3, 2), &[BtnUp]), ChannelSelect(3));
+        assert_eq!(after(ChannelAction(3, 2), &[EncHold]), ChannelSelect(3));
+        // Nothing copied yet, pasting and swapping stay put
+        assert_eq!(after(ChannelAction(3, 1), &[EncPush]), ChannelAction(3, 1));
+        assert_eq!(after(ChannelAction(3, 2), &[EncPush]), ChannelAction(3, 2));
+
+        // A clock with a longer duty cycle on channel 1
+        let mut gui = gui_in(ModeSelect(1));
+        gui.handle(EncInc);
+        for _ in 0..6 {
+            gui.engine.parameter(1, 1).unwrap().1.next();
+        }
+        let long = high_samples(&mut gui, 1);
+
+        channel_action(&mut gui, 1, 0);
+        channel_action(&mut gui, 4, 1);
+        assert_eq!(gui.state, ChannelSelect(4));
+        assert_eq!(mode_name(&gui, 4), "ClockOut");
+        assert_eq!(high_samples(&mut gui, 4), long);
+
+        // Swapping goes with the channel copied from
+        gui.engine.reset_channel(4);
+        channel_action(&mut gui, 6, 2);
+        assert_eq!(mode_name(&gui, 1), "NoOutput");
+        assert_eq!(mode_name(&gui, 6), "ClockOut");
+        assert_eq!(high_samples(&mut gui, 6), long);
+
+        // Reset keeps the mode
+        channel_action(&mut gui, 6, 3);
+        assert_eq!(mode_name(&gui, 6), "ClockOut");
+        assert_eq!(high_samples(&mut gui, 6), high_samples(&mut gui, 4));
+        assert_ne!(high_samples(&mut gui, 6), long);
+    }
+
+    #[test]
+    fn channel_groups() {
+        use GuiState::*;
+        let mut gui = gui_in(ModeSelect(3));
+        for _ in 0..3 {
+            gui.handle(EncInc);
+        }
+        assert_eq!(mode_name(&gui, 3), "QuadLfo");
+        assert_eq!(
+            (0..NUM_OUTPUTS)
+                .map(|ch| gui.engine.primary(ch))
+                .collect::<Vec<_>>(),
+            [0, 1, 2, 3, 3, 5, 6, 7]
+        );
+
+        // The linked channel is configured through the first
+        gui.state = ChannelSelect(4);
+        gui.handle(EncPush);
+        assert_eq!(gui.state, ModeSelect(3));
+
+        // A quarter cycle in, the linked channel is a quarter behind
+        gui.run(SAMPLE_RATE / 4);
+        let levels = gui.engine.levels();
+        assert!((levels[3] - 5.0).abs() < 0.01);
+        assert!(levels[4].abs() < 0.01);
+
+        // Past the last output there's nothing to link
+        gui.engine.outputs[7] = modes::find("QuadLfo").map(|mode| (mode.new)()).unwrap();
+        assert_eq!(gui.engine.primary(7), 7);
+        gui.run(1);
+    }
+
+    #[test]
+    fn mode_select() {
+        use GuiState::*;
+        let mut gui = gui_in(ModeSelect(5));
+        gui.handle(EncInc);
+        assert_eq!(mode_name(&gui, 5), "ClockOut");
+        gui.handle(EncInc);
+        assert_eq!(mode_name(&gui, 5), "Envelope");
+        gui.handle(EncDec);
+        gui.handle(EncDec);
+        gui.handle(EncDec);
+        assert_eq!(mode_name(&gui, 5), "MidiCv");
+        assert_eq!(mode_name(&gui, 4), "NoOutput");
+        assert_eq!(gui.state, ModeSelect(5));
+
+        assert_eq!(after(ModeSelect(5), &[EncPush]), ParameterSelect(5, 0));
+        assert_eq!(after(ModeSelect(5), &[InputEvent::None]), ModeSelect(5));
+        assert_eq!(after(ModeSelect(5), &[BtnUp]), ChannelSelect(5));
+        assert_eq!(after(ModeSelect(5), &[BtnDn]), Idle);
+    }
+
+    #[test]
+    fn parameter_select_without_mode_parameters() {
+        use GuiState::*;
+        // NoOutput has no parameters of its own, only the slew stage's
+        let mut gui = gui_in(ParameterSelect(0, 0));
+        assert_eq!(gui.engine.outputs[0].num_parameters(), 0);
+        let count = gui.engine.num_parameters(0) as u8;
+        gui.handle(EncDec);
+        assert_eq!(gui.state, ParameterSelect(0, count - 1));
+        gui.handle(EncInc);
+        assert_eq!(gui.state, ParameterSelect(0, 0));
+        gui.handle(EncPush);
+        assert_eq!(gui.state, ParameterEdit(0, 0));
+        assert_eq!(gui.engine.parameter(0, 0).unwrap().0, "Slew");
+    }
+
+    #[test]
+    fn parameter_select() {
+        use GuiState::*;
+        assert_eq!(
+            after(ParameterSelect(1, 0), &[EncInc]),
+            ParameterSelect(1, 1)
+        );
+        assert_eq!(
+            after(ParameterSelect(1, 2), &[EncInc]),
+            ParameterSelect(1, 0)
+        );
+        assert_eq!(
+            after(ParameterSelect(1, 1), &[EncPush]),
+            ParameterEdit(1, 1)
+        );
+        assert_eq!(
+            after(ParameterSelect(1, 1), &[BtnUp, InputEvent::None]),
+            ParameterSelect(1, 1)
+        );
+        assert_eq!(after(ParameterSelect(1, 1), &[BtnDn]), ChannelSelect(1));
+    }
+
+    #[test]
+    fn parameter_edit() {
+        use GuiState::*;
+        // The slew stage's shape, Off first
+        let mut gui = gui_in(ParameterEdit(2, 0));
+        gui.handle(EncInc);
+        gui.handle(EncInc);
+        gui.handle(EncDec);
+        assert_eq!(gui.state, ParameterEdit(2, 0));
+        assert_eq!(
+            gui.engine.slews[2].parameter(0).map(|(name, _)| name),
+            Some("Slew")
+        );
+        gui.handle(BtnUp);
+        gui.handle(InputEvent::None);
+        assert_eq!(gui.state, ParameterEdit(2, 0));
+        gui.handle(EncPush);
+        assert_eq!(gui.state, ParameterSelect(2, 0));
+
+        assert_eq!(after(ParameterEdit(2, 1), &[BtnDn]), ParameterSelect(2, 1));
+        // Left behind by a mode change
+        assert_eq!(
+            after(ParameterEdit(2, 40), &[EncInc]),
+            ParameterSelect(2, 40)
+        );
+    }
+
+    #[test]
+    fn render_copies_frame() {
+        let mut gui = Gui::new(None);
+        let mut target = Display::new(Size::new_equal(SCREEN_SIZE));
+        gui.render(&mut target).unwrap();
+        assert_eq!(target.get_pixel(Point::new(127, 0)), BG);
+        assert_ne!(target.get_pixel(Point::new(16, 94)), BG);
+    }
+}
@@ -0,0 +1,166 @@
+//! The computer keyboard as a MIDI keyboard, the way DAWs lay it out: the
+//! home row is the white keys from `A` (C) to `L`, the row above holds the
+//! black keys. `Z`/`X` shift down/up an octave and `C`/`V` step the
+//! velocity. Keys bound to a panel control in `keys.cfg` take precedence.
+
+use common::midi::MidiMessage;
+use embedded_graphics_simulator::sdl2::Keycode;
+
+/// Note on the `A` key before any octave shift, middle C.
+const BASE_NOTE: u8 = 60;
+const VELOCITY_STEP: u8 = 16;
+const DEFAULT_VELOCITY: u8 = 100;
+
+// Semitones above the octave's C
+const PIANO_KEYS: [(Keycode, u8); 15] = [
+    (Keycode::A, 0),
+    (Keycode::W, 1),
+    (Keycode::S, 2),
+    (Keycode::E, 3),
+    (Keycode::D, 4),
+    (Keycode::F, 5),
+    (Keycode::T, 6),
+    (Keycode::G, 7),
+    (Keycode::Y, 8),
+    (Keycode::H, 9),
+    (Keycode::U, 10),
+    (Keycode::J, 11),
+    (Keycode::K, 12),
+    (Keycode::O, 13),
+    (Keycode::L, 14),
+];
+
+pub struct MidiKeyboard {
+    /// MIDI channel notes are sent on, from 0
+    pub channel: u8,
+    octave: i8,
+    velocity: u8,
+    /// Keys down and the note each started, so a shift while held still
+    /// releases the right note
+    held: Vec<(Keycode, u8)>,
+}
+
+impl Default for MidiKeyboard {
+    fn default() -> Self {
+        MidiKeyboard {
+            channel: 0,
+            octave: 0,
+            velocity: DEFAULT_VELOCITY,
+            held: Vec::new(),
+        }
+    }
+}
+
+impl MidiKeyboard {
+    pub fn octave(&self) -> i8 {
+        self.octave
+    }
+
+    pub fn velocity(&self) -> u8 {
+        self.velocity
+    }
+
+    /// Handle a key going down. Returns the note on it plays, if any;
+    /// `repeat` presses from holding a key down are ignored.
+    pub fn key_down(&mut self, key: Keycode, repeat: bool) -> Option<MidiMessage<'static>> {
+        if repeat {
+            return None;
+        }
+        match key {
+            Keycode::Z => self.octave = (self.octave - 1).max(-5),
+            Keycode::X => self.octave = (self.octave + 1).min(4),
+            Keycode::C => self.velocity = self.velocity.saturating_sub(VELOCITY_STEP).max(1),
+            Keycode::V => self.velocity = (self.velocity + VELOCITY_STEP).min(127),
+            _ => {
+                let (_, semitone) = PIANO_KEYS.iter().find(|(piano, _)| *piano == key)?;
+                // The octave limits keep every key within 0..=127
+                let note = (BASE_NOTE as i8 + self.octave * 12) as u8 + semitone;
+                if self.held.iter().any(|(held, _)| *held == key) {
+                    return None;
+                }
+                self.held.push((key, note));
+                return Some(MidiMessage::NoteOn {
+                    channel: self.channel,
+                    note,
+                    velocity: self.velocity,
+                });
+            }
+        }
+        None
+    }
+
+    /// Handle a key coming up, returning the note off for it if it was
+    /// playing.
+    pub fn key_up(&mut self, key: Keycode) -> Option<MidiMessage<'static>> {
+        let i = self.held.iter().position(|(held, _)| *held == key)?;
+        let (_, note) = self.held.remove(i);
+        Some(MidiMessage::NoteOff {
+            channel: self.channel,
+            note,
+            velocity: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_on(note: u8, velocity: u8) -> Option<MidiMessage<'static>> {
+        Some(MidiMessage::NoteOn {
+            channel: 0,
+            note,
+            velocity,
+        })
+    }
+
+    fn note_off(note: u8) -> Option<MidiMessage<'static>> {
+        Some(MidiMessage::NoteOff {
+            channel: 0,
+            note,
+            velocity: 0,
+        })
+    }
+
+    #[test]
+    fn plays_notes() {
+        let mut keyboard = MidiKeyboard::default();
+        assert_eq!(keyboard.key_down(Keycode::A, false), note_on(60, 100));
+        assert_eq!(keyboard.key_down(Keycode::W, false), note_on(61, 100));
+        assert_eq!(keyboard.key_down(Keycode::L, false), note_on(74, 100));
+        // Held keys repeat, but only the first press plays
+        assert_eq!(keyboard.key_down(Keycode::A, true), None);
+        assert_eq!(keyboard.key_up(Keycode::A), note_off(60));
+        assert_eq!(keyboard.key_up(Keycode::A), None);
+        // Not a piano key
+        assert_eq!(keyboard.key_down(Keycode::B, false), None);
+        assert_eq!(keyboard.key_up(Keycode::B), None);
+    }
+
+    #[test]
+    fn octave_and_velocity() {
+        let mut keyboard = MidiKeyboard::default();
+        keyboard.key_down(Keycode::G, false);
+        keyboard.key_down(Keycode::X, false);
+        keyboard.key_down(Keycode::V, false);
+        assert_eq!(keyboard.key_down(Keycode::A, false), note_on(72, 116));
+        // Released as the note it started as
+        assert_eq!(keyboard.key_up(Keycode::G), note_off(67));
+
+        for _ in 0..10 {
+            keyboard.key_down(Keycode::Z, false);
+            keyboard.key_down(Keycode::C, false);
+        }
+        assert_eq!(keyboard.octave(), -5);
+        assert_eq!(keyboard.velocity(), 1);
+        assert_eq!(keyboard.key_down(Keycode::S, false), note_on(2, 1));
+
+        for _ in 0..10 {
+            keyboard.key_down(Keycode::X, false);
+            keyboard.key_down(Keycode::V, false);
+        }
+        assert_eq!(keyboard.velocity(), 127);
+        assert_eq!(keyboard.octave(), 4);
+        assert_eq!(keyboard.key_down(Keycode::L, false), note_on(122, 127));
+    }
+}
@@ -0,0 +1,172 @@
+use common::dsp::{sine, Phase};
+use embedded_graphics::{
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, Rectangle, StyledDrawable},
+};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use crate::display::{BRIGHT, DARK, FONT_10, FONT_16, TAN};
+use crate::output::{InputState, OutSignal, SAMPLE_RATE};
+use crate::parameters::{Choice, ConfigParameter, Parameter};
+use crate::Display;
+
+const SHAPES: [&str; 2] = ["Sine", "Triangle"];
+const TRIANGLE: usize = 1;
+
+/// A whole cycle of `Phase`, as a float for conversions.
+const TURN: f32 = 4_294_967_296.0;
+const QUARTER_TURN: u32 = 1 << 30;
+
+/// Two LFOs a quarter cycle apart, for stereo movement or anything driven
+/// in two dimensions. The channel it's set on gets the first, the channel
+/// after it, linked, the second.
+#[derive(Clone)]
+pub struct QuadLfo {
+    rate: Parameter<f32>,
+    shape: Choice,
+    level: Parameter<f32>,
+    phase: Phase,
+    /// Rate `phase` was last set to, in Hz
+    hz: f32,
+    value: f32,
+    quadrature: f32,
+}
+
+impl Default for QuadLfo {
+    fn default() -> Self {
+        QuadLfo {
+            rate: Parameter::new_saturating(0.1, 20.0, 0.1, 1.0),
+            shape: Choice::new(&SHAPES, 0),
+            level: Parameter::new_saturating(0.5, 10.0, 0.5, 5.0),
+            phase: Phase::default(),
+            hz: 0.0,
+            value: 0.0,
+            quadrature: 0.0,
+        }
+    }
+}
+
+impl QuadLfo {
+    /// The wave at `phase`, -1 to 1 starting from 0 and rising.
+    fn wave(&self, phase: u32) -> f32 {
+        if *self.shape == TRIANGLE {
+            match phase as f32 / TURN {
+                p if p < 0.25 => 4.0 * p,
+                p if p < 0.75 => 2.0 - 4.0 * p,
+                p => 4.0 * p - 4.0,
+            }
+        } else {
+            // The table peaks at i16::MAX, so this reaches exactly 1
+            sine(phase) as f32 / i16::MAX as f32
+        }
+    }
+}
+
+/// A bar from the middle of `window` out to `value`, -1 to 1.
+fn draw_level(disp: &mut Display, window: Rectangle, value: f32) {
+    let center = window.center();
+    let start = Point::new(window.top_left.x + 2, center.y);
+    let end = Point::new(window.top_left.x + 28, center.y);
+    Line::new(start, end)
+        .draw_styled(&PrimitiveStyle::with_stroke(DARK, 3), disp)
+        .ok();
+    let reach = (13.0 * value) as i32;
+    if reach != 0 {
+        Line::new(
+            Point::new(center.x, center.y),
+            Point::new(center.x + reach, center.y),
+        )
+        .draw_styled(&PrimitiveStyle::with_stroke(BRIGHT, 3), disp)
+        .ok();
+    }
+}
+
+impl OutSignal for QuadLfo {
+    fn num_parameters(&self) -> usize {
+        3
+    }
+
+    fn parameter(&mut self, param: usize) -> Option<(&'static str, &mut dyn ConfigParameter)> {
+        match param {
+            0 => Some(("Rate Hz", &mut self.rate)),
+            1 => Some(("Shape", &mut self.shape)),
+            2 => Some(("Level", &mut self.level)),
+            _ => None,
+        }
+    }
+
+    fn width(&self) -> usize {
+        2
+    }
+
+    fn generate(&mut self, _input: &InputState) -> f32 {
+        if self.hz != *self.rate {
+            self.hz = *self.rate;
+            self.phase.set_hz(self.hz, SAMPLE_RATE);
+        }
+        self.phase.tick();
+        let phase = self.phase.phase();
+        self.value = self.wave(phase);
+        self.quadrature = self.wave(phase.wrapping_add(QUARTER_TURN));
+        self.value * *self.level
+    }
+
+    fn linked(&self, _link: usize) -> f32 {
+        self.quadrature * *self.level
+    }
+
+    fn draw_output(&self, disp: &mut Display, window: Rectangle) {
+        draw_level(disp, window, self.value);
+    }
+
+    fn draw_linked(&self, _link: usize, disp: &mut Display, window: Rectangle) {
+        draw_level(disp, window, self.quadrature);
+    }
+
+    fn draw_configure(&self, disp: &mut Display, window: Rectangle) {
+        let anchor = window.top_left + Point::new(5, 5);
+        FONT_16
+            .render_aligned(
+                "Quad LFO",
+                anchor,
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(TAN),
+                disp,
+            )
+            .ok();
+        FONT_10
+            .render_aligned(
+                format_args!("{} {:.1} Hz", self.shape.label(), *self.rate),
+                anchor + Point::new(0, 26),
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(BRIGHT),
+                disp,
+            )
+            .ok();
+
+        // A cycle of each, the linked output's dimmer
+        let plot = Rectangle::new(
+            window.top_left + Point::new(5, 44),
+            Size::new(window.size.width - 10, window.size.height - 49),
+        );
+        let half = (plot.size.height as i32 - 1) / 2;
+        let width = plot.size.width as i32 - 1;
+        for (offset, color) in [(0.25, DARK), (0.0, BRIGHT)] {
+            let points = (0..=width).map(|x| {
+                let turns = (x as f32 / width as f32 + offset).fract();
+                let y = self.wave((turns * TURN) as u32);
+                plot.top_left + Point::new(x, half - (y * half as f32).round() as i32)
+            });
+            let style = PrimitiveStyle::with_stroke(color, 1);
+            let mut last = None;
+            for point in points {
+                if let Some(last) = last {
+                    Line::new(last, point).draw_styled(&style, disp).ok();
+                }
+                last = Some(point);
+            }
+        }
+    }
+}
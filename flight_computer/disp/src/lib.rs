@@ -0,0 +1,29 @@
+pub mod bindings;
+pub mod calibrate;
+pub mod clk_out;
+pub mod display;
+pub mod engine;
+pub mod envelope;
+pub mod gate_out;
+pub mod gui;
+pub mod keyboard;
+pub mod lfo;
+pub mod midi;
+pub mod midi_cv;
+pub mod midi_file;
+#[cfg(feature = "midi-port")]
+pub mod midi_port;
+pub mod modes;
+pub mod monitor;
+pub mod output;
+pub mod panel;
+pub mod parameters;
+pub mod patchbay;
+pub mod poly;
+pub mod quantizer;
+pub mod render;
+pub mod scope;
+pub mod sequencer;
+pub mod slew;
+
+pub use display::Display;
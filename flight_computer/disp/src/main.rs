@@ -1,278 +1,375 @@
-use std::sync::OnceLock;
-use std::{thread, time::Duration};
+use std::path::{Path, PathBuf};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
 
-use embedded_graphics::draw_target::DrawTarget;
-use embedded_graphics::geometry::AnchorPoint;
+use embedded_graphics::draw_target::DrawTargetExt;
+use embedded_graphics::pixelcolor::Bgr565;
 use embedded_graphics::prelude::{Point, Size};
-use embedded_graphics::primitives::{
-    CornerRadii, PrimitiveStyle, Rectangle, RoundedRectangle, StyledDrawable,
-};
+use embedded_graphics::primitives::Rectangle;
 use embedded_graphics_simulator::{
-    sdl2::Keycode, OutputSettingsBuilder, SimulatorDisplay, SimulatorEvent, Window,
+    sdl2::{MouseButton, MouseWheelDirection},
+    OutputSettings, OutputSettingsBuilder, SimulatorDisplay, SimulatorEvent, Window,
 };
 
-mod clk_out;
-mod display;
-mod output;
-mod parameters;
+use common::calibration::NUM_OUTPUTS;
+use disp::bindings::{Action, KeyBindings, KEY_BINDINGS_FILE};
+use disp::gui::{Gui, InputEvent, Jack, SCREEN_SIZE};
+use disp::keyboard::MidiKeyboard;
+use disp::midi_file::{MidiFile, MidiPlayer};
+#[cfg(feature = "midi-port")]
+use disp::midi_port::MidiPort;
+#[cfg(feature = "audio")]
+use disp::monitor::DeviceSink;
+use disp::monitor::{AudioSink, Monitor, NullSink, RollingWav};
+use disp::output::SAMPLE_RATE;
+use disp::panel::{self, PANEL_WIDTH};
+use disp::patchbay::{PatchBay, PATCH_BAY_HEIGHT};
+use disp::scope::{Scope, SCOPE_WIDTH};
 
-use crate::display::{Display, FONT_08, FONT_10, FONT_16};
-use crate::display::{BG, BLUE, BRIGHT, DARK, TAN};
+/// Samples each headless frame runs the outputs for, fixed so that frames
+/// come out the same on every run.
+const HEADLESS_FRAME_SAMPLES: u32 = SAMPLE_RATE / 30;
 
-use core::iter::zip;
-use output::{NoOutput, OutSignal, OutputChannel};
+/// Pause between frames in the window.
+const FRAME_TIME: Duration = Duration::from_millis(20);
 
-static mut OUTPUTS: OnceLock<[OutputChannel; 8]> = OnceLock::new();
+/// Seconds of monitored audio kept by `--wav`.
+const WAV_SECONDS: u32 = 30;
 
-fn add_wrap(a: u8, b: i8, max: u8) -> u8 {
-    if a == (max - 1) && b > 0 {
-        0
-    } else if a == 0 && b < 0 {
-        max - 1
-    } else {
-        a.saturating_add_signed(b)
-    }
+#[cfg(feature = "midi-port")]
+const USAGE: &str = "usage: disp [--midi-file <file>] [--midi-in <port>|virtual]
+            [--monitor <outputs> [--wav <file>]] [--scope]
+       disp --headless <script> <frame dir>";
+#[cfg(not(feature = "midi-port"))]
+const USAGE: &str = "usage: disp [--midi-file <file>] [--monitor <outputs> [--wav <file>]]
+            [--scope]
+       disp --headless <script> <frame dir>";
+
+/// One frame of a headless script.
+#[derive(Clone, Copy)]
+enum Step {
+    Input(InputEvent),
+    Jack(Jack),
 }
 
-#[derive(Debug, PartialEq)]
-enum InputEvent {
-    EncInc,
-    EncDec,
-    EncPush,
-    BtnUp,
-    BtnDn,
-    None,
+/// Parse a headless script: a line per frame naming an `InputEvent`, or
+/// `Out <n>`/`In <n>` to toggle a jack, optionally followed by a repeat
+/// count. `#` starts a comment.
+fn parse_script(script: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    for (i, line) in script.lines().enumerate() {
+        let error = |message: String| format!("line {}: {}", i + 1, message);
+        let words: Vec<&str> = line
+            .split('#')
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        let (step, repeat) = match words.as_slice() {
+            [] => continue,
+            [kind @ ("In" | "Out"), n, rest @ ..] => {
+                let jack = Jack::from_words(kind, n)
+                    .ok_or_else(|| error(format!("no jack {} {}", kind, n)))?;
+                (Step::Jack(jack), rest)
+            }
+            [event, rest @ ..] => {
+                let input = InputEvent::from_name(event)
+                    .ok_or_else(|| error(format!("unknown event '{}'", event)))?;
+                (Step::Input(input), rest)
+            }
+        };
+        let count = match repeat {
+            [] => 1,
+            [count] => count
+                .parse()
+                .map_err(|_| error(format!("bad count '{}'", count)))?,
+            [_, extra, ..] => return Err(error(format!("unexpected '{}'", extra))),
+        };
+        steps.extend(std::iter::repeat_n(step, count));
+    }
+    Ok(steps)
 }
 
-#[derive(Debug)]
-enum GuiState {
-    Idle,
-    Settings,
-    ChannelSelect(u8),
-    ModeSelect(u8),
-    ParameterSelect(u8, u8),
-    ParameterEdit(u8, u8),
+/// Where input comes from and frames go to.
+enum Frontend {
+    Window {
+        window: Box<Window>,
+        bindings: KeyBindings,
+        /// Plays the unbound keys
+        keyboard: MidiKeyboard,
+        patch_bay: PatchBay,
+        player: Option<MidiPlayer>,
+        #[cfg(feature = "midi-port")]
+        port: Option<Box<MidiPort>>,
+        scope: Option<Box<Scope>>,
+    },
+    /// Steps through a script, saving every frame as a PNG
+    Headless {
+        steps: std::vec::IntoIter<Step>,
+        frame_dir: PathBuf,
+        frame: usize,
+    },
 }
 
-fn main() -> Result<(), core::convert::Infallible> {
-    unsafe {
-        OUTPUTS.get_or_init(|| core::array::from_fn(|_| NoOutput::new().into()));
+impl Frontend {
+    /// The frontend, and what's listening to the outputs.
+    fn from_args() -> Result<(Self, Monitor), String> {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        match args.as_slice() {
+            [flag, script, frame_dir] if flag == "--headless" => {
+                let text =
+                    std::fs::read_to_string(script).map_err(|e| format!("{}: {}", script, e))?;
+                let steps = parse_script(&text).map_err(|e| format!("{}: {}", script, e))?;
+                std::fs::create_dir_all(frame_dir).map_err(|e| format!("{}: {}", frame_dir, e))?;
+                let frontend = Frontend::Headless {
+                    steps: steps.into_iter(),
+                    frame_dir: frame_dir.into(),
+                    frame: 0,
+                };
+                Ok((frontend, Monitor::null()))
+            }
+            options => {
+                let mut player = None;
+                #[cfg(feature = "midi-port")]
+                let mut port = None;
+                let mut monitored = Vec::new();
+                let mut wav = None;
+                let mut scope = None;
+                let mut options = options.iter();
+                while let Some(option) = options.next() {
+                    if option == "--scope" {
+                        scope = Some(Box::default());
+                        continue;
+                    }
+                    match (option.as_str(), options.next()) {
+                        ("--midi-file", Some(path)) => {
+                            player = Some(MidiPlayer::new(MidiFile::load(Path::new(path))?))
+                        }
+                        #[cfg(feature = "midi-port")]
+                        ("--midi-in", Some(name)) => port = Some(Box::new(MidiPort::open(name)?)),
+                        ("--monitor", Some(list)) => monitored = Monitor::parse_outputs(list)?,
+                        ("--wav", Some(path)) => wav = Some(PathBuf::from(path)),
+                        _ => return Err(USAGE.into()),
+                    }
+                }
+                let sink: Box<dyn AudioSink> = match wav {
+                    Some(path) => Box::new(RollingWav::new(path, WAV_SECONDS)),
+                    None if monitored.is_empty() => Box::new(NullSink),
+                    #[cfg(feature = "audio")]
+                    None => Box::new(DeviceSink::open()?),
+                    #[cfg(not(feature = "audio"))]
+                    None => return Err("built without the audio feature, use --wav <file>".into()),
+                };
+                // Smaller, so the scope still fits on the screen
+                let scale = if scope.is_some() { 3 } else { 4 };
+                let output_settings = OutputSettingsBuilder::new().scale(scale).build();
+                let frontend = Frontend::Window {
+                    window: Box::new(Window::new("poco_pico", &output_settings)),
+                    bindings: KeyBindings::load(Path::new(KEY_BINDINGS_FILE))?,
+                    keyboard: MidiKeyboard::default(),
+                    patch_bay: PatchBay::default(),
+                    player,
+                    #[cfg(feature = "midi-port")]
+                    port,
+                    scope,
+                };
+                Ok((frontend, Monitor::new(monitored, sink)))
+            }
+        }
     }
-    let outputs = unsafe { OUTPUTS.get_mut().unwrap() };
+}
 
-    let mut display =
-        SimulatorDisplay::<embedded_graphics::pixelcolor::Bgr565>::new(Size::new(128, 128));
-    let output_settings = OutputSettingsBuilder::new().scale(4).build();
-    let mut window = Window::new("poco_pico", &output_settings);
+fn main() -> Result<(), core::convert::Infallible> {
+    let (mut frontend, mut monitor) = Frontend::from_args().unwrap_or_else(|message| {
+        eprintln!("ERR: {}", message);
+        std::process::exit(1)
+    });
+    // The window has the panel's buttons beside the screen, the patch bay
+    // below and the scope, if shown, on the right
+    let size = match &frontend {
+        Frontend::Window { scope, .. } => {
+            let scope_width = if scope.is_some() { SCOPE_WIDTH } else { 0 };
+            Size::new(
+                SCREEN_SIZE + PANEL_WIDTH + scope_width,
+                SCREEN_SIZE + PATCH_BAY_HEIGHT,
+            )
+        }
+        Frontend::Headless { .. } => Size::new_equal(SCREEN_SIZE),
+    };
+    let mut display = SimulatorDisplay::<Bgr565>::new(size);
+    let screen = Rectangle::new(Point::zero(), Size::new_equal(SCREEN_SIZE));
 
-    let mut state = GuiState::Idle;
+    // Calibration is kept in the working directory
+    let mut gui = Gui::new(Some(PathBuf::from(".")));
+    let mut last_frame = Instant::now();
 
     'main_loop: loop {
-        window.update(&display);
-        let input = match window.events().next() {
-            Some(SimulatorEvent::KeyDown {
-                keycode,
-                keymod,
-                repeat,
-            }) if keycode == Keycode::Right => InputEvent::EncInc,
-            Some(SimulatorEvent::KeyDown {
-                keycode,
-                keymod,
-                repeat,
-            }) if keycode == Keycode::Left => InputEvent::EncDec,
-            Some(SimulatorEvent::KeyDown {
-                keycode,
-                keymod,
-                repeat,
-            }) if keycode == Keycode::Down => InputEvent::EncPush,
-            Some(SimulatorEvent::KeyDown {
-                keycode,
-                keymod,
-                repeat,
-            }) if keycode == Keycode::Up => InputEvent::BtnUp,
-            Some(SimulatorEvent::KeyDown {
-                keycode,
-                keymod,
-                repeat,
-            }) if keycode == Keycode::Return => InputEvent::BtnDn,
-            Some(SimulatorEvent::KeyDown {
-                keycode,
-                keymod,
-                repeat,
-            }) if keycode == Keycode::Q => break 'main_loop,
-            Some(_) => InputEvent::None,
-            None => {
-                thread::sleep(Duration::from_millis(100));
-                InputEvent::None
+        let inputs = match &mut frontend {
+            Frontend::Headless { steps, .. } => match steps.next() {
+                Some(Step::Input(input)) => vec![input],
+                Some(Step::Jack(jack)) => {
+                    gui.toggle_jack(jack);
+                    vec![InputEvent::None]
+                }
+                None => break 'main_loop,
+            },
+            Frontend::Window {
+                window,
+                bindings,
+                keyboard,
+                patch_bay,
+                ..
+            } => {
+                window.update(&display);
+                // Everything since the last frame, so fast turns aren't dropped
+                let mut inputs = Vec::new();
+                for event in window.events() {
+                    match event {
+                        SimulatorEvent::KeyDown {
+                            keycode, repeat, ..
+                        } => match bindings.action(keycode) {
+                            Some(Action::Input(input)) => inputs.push(input),
+                            Some(Action::Jack(jack)) => gui.toggle_jack(jack),
+                            Some(Action::Quit) => break 'main_loop,
+                            None => {
+                                if let Some(message) = keyboard.key_down(keycode, repeat) {
+                                    gui.engine.receive(&message);
+                                }
+                            }
+                        },
+                        SimulatorEvent::KeyUp { keycode, .. } => {
+                            if let Some(message) = keyboard.key_up(keycode) {
+                                gui.engine.receive(&message);
+                            }
+                        }
+                        SimulatorEvent::MouseWheel {
+                            scroll_delta,
+                            direction,
+                        } => {
+                            let clicks = match direction {
+                                MouseWheelDirection::Flipped => -scroll_delta.y,
+                                _ => scroll_delta.y,
+                            };
+                            let input = if clicks > 0 {
+                                InputEvent::EncInc
+                            } else {
+                                InputEvent::EncDec
+                            };
+                            inputs
+                                .extend(std::iter::repeat_n(input, clicks.unsigned_abs() as usize));
+                        }
+                        SimulatorEvent::MouseButtonDown {
+                            mouse_btn: MouseButton::Right,
+                            point,
+                        } => inputs.extend(panel::long_press_at(point)),
+                        SimulatorEvent::MouseButtonDown { point, .. } => {
+                            match panel::button_at(point) {
+                                Some(input) => inputs.push(input),
+                                None => patch_bay.press(point),
+                            }
+                        }
+                        SimulatorEvent::MouseMove { point } => patch_bay.drag(point),
+                        SimulatorEvent::MouseButtonUp { .. } => patch_bay.release(),
+                        SimulatorEvent::Quit => break 'main_loop,
+                    }
+                }
+                inputs
             }
         };
 
-        if input != InputEvent::None {
-            println!("DBG: INPUT: {:?}: STATE: {:?}", input, state);
+        for input in inputs {
+            if input != InputEvent::None {
+                println!("DBG: INPUT: {:?}: STATE: {:?}", input, gui.state());
+            }
+            gui.handle(input);
         }
 
-        // Process Inputs
-        let new_state = match state {
-            GuiState::Settings => GuiState::Idle,
-            GuiState::Idle => {
-                match input {
-                    InputEvent::EncInc | InputEvent::EncDec | InputEvent::EncPush => {
-                        GuiState::ChannelSelect(0)
-                    }
-                    InputEvent::BtnUp => {
-                        // Output.play_pause();
-                        GuiState::Idle
-                    }
-                    InputEvent::BtnDn => GuiState::Settings,
-                    InputEvent::None => GuiState::Idle,
+        // Run the outputs for as long as the last frame took
+        match &mut frontend {
+            Frontend::Window {
+                patch_bay,
+                player,
+                #[cfg(feature = "midi-port")]
+                port,
+                scope,
+                ..
+            } => {
+                let samples = (last_frame.elapsed().as_secs_f32() * SAMPLE_RATE as f32) as u32;
+                patch_bay.apply(&mut gui);
+                #[cfg(feature = "midi-port")]
+                if let Some(port) = port {
+                    port.receive(|message| gui.engine.receive(message));
                 }
-            }
-            GuiState::ChannelSelect(ch) => {
-                match input {
-                    InputEvent::EncInc => GuiState::ChannelSelect(add_wrap(ch, 1, 8)),
-                    InputEvent::EncDec => GuiState::ChannelSelect(add_wrap(ch, -1, 8)),
-                    InputEvent::EncPush => GuiState::ModeSelect(ch),
-                    InputEvent::BtnUp => {
-                        // Ouput.play_pause()
-                        GuiState::ChannelSelect(ch)
+                let mut each = |levels: &[f32; NUM_OUTPUTS]| {
+                    monitor.push(levels);
+                    if let Some(scope) = scope {
+                        scope.push(levels);
                     }
-                    InputEvent::BtnDn => GuiState::Idle,
-                    InputEvent::None => GuiState::ChannelSelect(ch),
+                };
+                // Split where the file's messages fall, so they land on the
+                // sample they're timed for
+                let mut done = 0;
+                if let Some(player) = player {
+                    player.advance(samples, |offset, message| {
+                        gui.run_with(offset - done, &mut each);
+                        gui.engine.receive(message);
+                        done = offset;
+                    });
                 }
+                gui.run_with(samples - done, &mut each);
             }
-            GuiState::ModeSelect(ch) => {
-                match input {
-                    InputEvent::EncInc => {
-                        outputs[ch as usize] = outputs[ch as usize].next();
-                        GuiState::ModeSelect(ch)
-                    }
-                    InputEvent::EncDec => {
-                        outputs[ch as usize] = outputs[ch as usize].prev();
-                        GuiState::ModeSelect(ch)
-                    }
-                    InputEvent::EncPush => GuiState::ParameterSelect(ch, 0),
-                    InputEvent::BtnUp => {
-                        // Ouput.play_pause()
-                        GuiState::ChannelSelect(ch)
-                    }
-                    InputEvent::BtnDn => GuiState::Idle,
-                    InputEvent::None => GuiState::ModeSelect(ch),
-                }
+            Frontend::Headless { .. } => {
+                gui.run_with(HEADLESS_FRAME_SAMPLES, |levels| monitor.push(levels))
             }
-            GuiState::ParameterSelect(ch, param) => {
-                let num_params = outputs[ch as usize].num_parameters() as u8;
-                match input {
-                    InputEvent::EncInc => {
-                        GuiState::ParameterSelect(ch, add_wrap(param, 1, num_params))
-                    }
-                    InputEvent::EncDec => {
-                        GuiState::ParameterSelect(ch, add_wrap(param, -1, num_params))
-                    }
-                    InputEvent::EncPush => GuiState::ParameterEdit(ch, param),
-                    InputEvent::BtnUp => {
-                        // Ouput.play_pause()
-                        GuiState::ParameterSelect(ch, param)
-                    }
-                    InputEvent::BtnDn => GuiState::ChannelSelect(ch),
-                    InputEvent::None => GuiState::ParameterSelect(ch, param),
+        }
+        let midi_out = gui.take_midi_out();
+        #[cfg(feature = "midi-port")]
+        if let Frontend::Window {
+            port: Some(port), ..
+        } = &mut frontend
+        {
+            port.send(&midi_out);
+        }
+        // Nowhere to send it without a port
+        #[cfg(not(feature = "midi-port"))]
+        drop(midi_out);
+        last_frame = Instant::now();
+        if let Err(e) = monitor.flush() {
+            eprintln!("ERR: monitor: {}", e);
+        }
+        gui.render(&mut display.cropped(&screen))?;
+
+        match &mut frontend {
+            Frontend::Window {
+                patch_bay, scope, ..
+            } => {
+                panel::draw_panel(&mut display);
+                patch_bay.draw(&mut display, gui.jacks());
+                if let Some(scope) = scope {
+                    let left = (SCREEN_SIZE + PANEL_WIDTH) as i32;
+                    let area = Rectangle::new(
+                        Point::new(left, 0),
+                        Size::new(SCOPE_WIDTH, SCREEN_SIZE + PATCH_BAY_HEIGHT),
+                    );
+                    scope.draw(&mut display, area);
                 }
+                thread::sleep(FRAME_TIME);
             }
-            GuiState::ParameterEdit(ch, param) => {
-                if let Some((_, parameter)) = outputs[ch as usize].parameter(param as usize) {
-                    match input {
-                        InputEvent::EncInc => {
-                            parameter.next();
-                            GuiState::ParameterEdit(ch, param)
-                        }
-                        InputEvent::EncDec => {
-                            parameter.prev();
-                            GuiState::ParameterEdit(ch, param)
-                        }
-                        InputEvent::EncPush => GuiState::ParameterSelect(ch, param),
-                        InputEvent::BtnUp => {
-                            // Ouput.play_pause()
-                            GuiState::ParameterEdit(ch, param)
-                        }
-                        InputEvent::BtnDn => GuiState::ParameterSelect(ch, param),
-                        InputEvent::None => GuiState::ParameterEdit(ch, param),
-                    }
-                } else {
-                    // error?!
-                    GuiState::ParameterSelect(ch, param)
+            Frontend::Headless {
+                frame_dir, frame, ..
+            } => {
+                let path = frame_dir.join(format!("frame_{:03}.png", frame));
+                let image = display.to_rgb_output_image(&OutputSettings::default());
+                if let Err(e) = image.save_png(&path) {
+                    eprintln!("ERR: {}: {}", path.display(), e);
+                    std::process::exit(1);
                 }
+                *frame += 1;
             }
-        };
-
-        display.clear(BG);
-
-        let main_window = Rectangle::new(Point::new(0, 10), Size::new(128, 80));
-        draw_output_state(&mut display, outputs, 0);
-
-        match state {
-            GuiState::Idle => draw_idle(&mut display, main_window),
-            GuiState::Settings => draw_idle(&mut display, main_window),
-            GuiState::ChannelSelect(ch) => {
-                FONT_16.render_aligned(
-                    format_args!("{}", ch),
-                    Point::new(5, 45),
-                    u8g2_fonts::types::VerticalPosition::Bottom,
-                    u8g2_fonts::types::HorizontalAlignment::Left,
-                    u8g2_fonts::types::FontColor::Transparent(BRIGHT),
-                    &mut display,
-                );
-                let window = Rectangle::new(Point::new(32, 10), Size::new(96, 80));
-                outputs[ch as usize].draw_configure(&mut display, window)
-            }
-            GuiState::ModeSelect(ch) => {}
-            GuiState::ParameterSelect(ch, param) => {} //channel[ch].parameter(&disp, param, input),
-            GuiState::ParameterEdit(ch, param) => {} //channel[ch].get_param(param).edit(&disp, input),
         }
-
-        state = new_state;
-        // TODO Swap buffers
     }
 
     Ok(())
 }
-
-fn draw_idle(display: &mut Display, window: Rectangle) {
-    let style = PrimitiveStyle::with_stroke(BLUE, 1);
-    window.offset(-2).draw_styled(&style, display);
-    let text_pos = window.anchor_point(AnchorPoint::CenterLeft) + Point::new(5, 0);
-
-    //Text::new("IDLE SCREEN", text_pos, TEXT_X10).draw(display);
-    FONT_10.render_aligned(
-        "Idle Screen",
-        text_pos,
-        u8g2_fonts::types::VerticalPosition::Bottom,
-        u8g2_fonts::types::HorizontalAlignment::Left,
-        u8g2_fonts::types::FontColor::Transparent(DARK),
-        display,
-    );
-}
-
-fn draw_output_state(display: &mut Display, outputs: &[OutputChannel], active: u8) {
-    let output_disp_corners = [
-        Point::new(0, 94),
-        Point::new(32, 94),
-        Point::new(64, 94),
-        Point::new(96, 94),
-        Point::new(0, 111),
-        Point::new(32, 111),
-        Point::new(64, 111),
-        Point::new(96, 111),
-    ];
-
-    // Draw current output state
-    for (out, corner) in zip(outputs, output_disp_corners) {
-        let style = PrimitiveStyle::with_stroke(DARK, 1);
-        let rect = Rectangle::new(corner, Size::new(32, 16));
-        out.draw_output(display, rect);
-
-        let r = RoundedRectangle::new(
-            Rectangle::new(corner, Size::new(32, 15)),
-            CornerRadii::new(Size::new_equal(3)),
-        );
-        r.draw_styled(&style, display);
-    }
-}
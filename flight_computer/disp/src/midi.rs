@@ -0,0 +1,61 @@
+use common::midi::{ThruFilter, NUM_CHANNELS};
+use embedded_graphics::prelude::{Point, Size};
+use embedded_graphics::primitives::{
+    PrimitiveStyle, PrimitiveStyleBuilder, Rectangle, StyledDrawable,
+};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use crate::display::{BG, BLUE, BRIGHT, DARK, FONT_08, FONT_16, TAN};
+use crate::Display;
+
+/// MIDI channels a mode can send on, starting with not sending at all.
+pub const MIDI_CHANNELS: [&str; 17] = [
+    "Off", "Ch 1", "Ch 2", "Ch 3", "Ch 4", "Ch 5", "Ch 6", "Ch 7", "Ch 8", "Ch 9", "Ch 10",
+    "Ch 11", "Ch 12", "Ch 13", "Ch 14", "Ch 15", "Ch 16",
+];
+
+/// Sixteen channel boxes, lit when the channel is passed thru.
+pub fn draw_midi_thru(disp: &mut Display, window: Rectangle, thru: &ThruFilter, cursor: u8) {
+    let style = PrimitiveStyle::with_stroke(BLUE, 1);
+    window.offset(-2).draw_styled(&style, disp).ok();
+
+    let anchor = window.top_left + Point::new(5, 5);
+    FONT_16
+        .render_aligned(
+            "MIDI Thru",
+            anchor,
+            VerticalPosition::Top,
+            HorizontalAlignment::Left,
+            FontColor::Transparent(TAN),
+            disp,
+        )
+        .ok();
+
+    let cell = Size::new((window.size.width - 10) / 8, 20);
+    for ch in 0..NUM_CHANNELS as u8 {
+        let origin = anchor
+            + Point::new(
+                (ch % 8) as i32 * cell.width as i32,
+                30 + (ch / 8) as i32 * cell.height as i32,
+            );
+        let rect = Rectangle::new(origin, cell);
+        let enabled = thru.enabled(ch);
+        let style = PrimitiveStyleBuilder::new()
+            .fill_color(if enabled { TAN } else { BG })
+            .stroke_color(if ch == cursor { BLUE } else { DARK })
+            .stroke_width(if ch == cursor { 2 } else { 1 })
+            .build();
+        rect.draw_styled(&style, disp).ok();
+
+        FONT_08
+            .render_aligned(
+                format_args!("{}", ch + 1),
+                rect.center(),
+                VerticalPosition::Center,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(if enabled { BG } else { BRIGHT }),
+                disp,
+            )
+            .ok();
+    }
+}
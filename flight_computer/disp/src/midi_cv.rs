@@ -0,0 +1,190 @@
+use common::midi::{ChannelState, TICKS_PER_BEAT};
+use embedded_graphics::{
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, Rectangle, StyledDrawable},
+};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use crate::display::{BRIGHT, DARK, FONT_10, FONT_16, TAN};
+use crate::midi::MIDI_CHANNELS;
+use crate::output::{InputState, OutSignal, GATE_VOLTS, SAMPLE_RATE};
+use crate::parameters::{Choice, ConfigParameter, Parameter};
+use crate::Display;
+
+const SOURCES: [&str; 6] = [
+    "Velocity",
+    "Aftertouch",
+    "Poly AT",
+    "Bend",
+    "Mod Wheel",
+    "Clock",
+];
+const VELOCITY: usize = 0;
+const AFTERTOUCH: usize = 1;
+const POLY_AFTERTOUCH: usize = 2;
+const BEND: usize = 3;
+const MOD_WHEEL: usize = 4;
+const CLOCK: usize = 5;
+
+const DIVISIONS: [&str; 6] = ["1/1", "1/2", "1/4", "1/8", "1/16", "1/32"];
+const DIVISION_TICKS: [u32; 6] = [
+    4 * TICKS_PER_BEAT,
+    2 * TICKS_PER_BEAT,
+    TICKS_PER_BEAT,
+    TICKS_PER_BEAT / 2,
+    TICKS_PER_BEAT / 4,
+    TICKS_PER_BEAT / 8,
+];
+
+const MOD_WHEEL_CC: usize = 1;
+const TRIGGER_SAMPLES: u32 = SAMPLE_RATE / 100;
+
+/// One MIDI controller as a CV: the latest note's velocity, channel or
+/// polyphonic aftertouch, pitch bend, the mod wheel, or triggers on
+/// divisions of MIDI clock.
+#[derive(Clone)]
+pub struct MidiCv {
+    channel: Choice,
+    source: Choice,
+    division: Choice,
+    level: Parameter<f32>,
+    /// -1 to 1 for bend, 0 to 1 otherwise
+    value: f32,
+    /// Velocity of the latest note, held after it's released
+    velocity: u8,
+    last_ticks: u32,
+    trigger: u32,
+}
+
+impl Default for MidiCv {
+    fn default() -> Self {
+        MidiCv {
+            channel: Choice::new(&MIDI_CHANNELS, 1),
+            source: Choice::new(&SOURCES, VELOCITY),
+            division: Choice::new(&DIVISIONS, 2),
+            level: Parameter::new_saturating(0.5, 10.0, 0.5, 5.0),
+            value: 0.0,
+            velocity: 0,
+            last_ticks: 0,
+            trigger: 0,
+        }
+    }
+}
+
+impl MidiCv {
+    fn is_clock(&self) -> bool {
+        *self.source == CLOCK
+    }
+
+    fn follow(&mut self, state: &ChannelState) -> f32 {
+        let latest = state.notes.last();
+        if let Some(held) = latest {
+            self.velocity = held.velocity;
+        }
+        match *self.source {
+            VELOCITY => self.velocity as f32 / 127.0,
+            AFTERTOUCH => state.pressure as f32 / 127.0,
+            POLY_AFTERTOUCH => latest.map_or(0.0, |held| {
+                state.key_pressure[held.note as usize] as f32 / 127.0
+            }),
+            BEND => state.pitch_bend as f32 / 8192.0,
+            MOD_WHEEL => state.controllers[MOD_WHEEL_CC] as f32 / 127.0,
+            _ => 0.0,
+        }
+    }
+}
+
+impl OutSignal for MidiCv {
+    fn num_parameters(&self) -> usize {
+        4
+    }
+
+    fn parameter(&mut self, param: usize) -> Option<(&'static str, &mut dyn ConfigParameter)> {
+        match param {
+            0 => Some(("MIDI In", &mut self.channel)),
+            1 => Some(("Source", &mut self.source)),
+            2 => Some(("Division", &mut self.division)),
+            3 => Some(("Level", &mut self.level)),
+            _ => None,
+        }
+    }
+
+    fn gate(&self) -> bool {
+        self.is_clock() && self.trigger > 0
+    }
+
+    fn generate(&mut self, input: &InputState) -> f32 {
+        if self.is_clock() {
+            // The first tick after Start is the downbeat
+            let ticks = input.midi.clock_ticks;
+            if ticks != self.last_ticks
+                && ticks > 0
+                && (ticks - 1).is_multiple_of(DIVISION_TICKS[*self.division])
+            {
+                self.trigger = TRIGGER_SAMPLES;
+            }
+            self.last_ticks = ticks;
+            self.trigger = self.trigger.saturating_sub(1);
+            self.value = if self.trigger > 0 { 1.0 } else { 0.0 };
+            return self.value * GATE_VOLTS;
+        }
+
+        self.value = match *self.channel {
+            0 => 0.0,
+            channel => self.follow(input.midi.channel(channel as u8 - 1)),
+        };
+        self.value * *self.level
+    }
+
+    fn draw_output(&self, disp: &mut Display, window: Rectangle) {
+        let center = window.center();
+        let start = Point::new(window.top_left.x + 2, center.y);
+        let end = Point::new(window.top_left.x + 28, center.y);
+        Line::new(start, end)
+            .draw_styled(&PrimitiveStyle::with_stroke(DARK, 3), disp)
+            .ok();
+        // Bend grows from the middle, everything else from the left
+        let (from, reach) = if *self.source == BEND {
+            (center.x, (13.0 * self.value) as i32)
+        } else {
+            (start.x, (26.0 * self.value) as i32)
+        };
+        if reach != 0 {
+            Line::new(
+                Point::new(from, center.y),
+                Point::new(from + reach, center.y),
+            )
+            .draw_styled(&PrimitiveStyle::with_stroke(BRIGHT, 3), disp)
+            .ok();
+        }
+    }
+
+    fn draw_configure(&self, disp: &mut Display, window: Rectangle) {
+        let anchor = window.top_left + Point::new(5, 5);
+        FONT_16
+            .render_aligned(
+                "MIDI CV",
+                anchor,
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(TAN),
+                disp,
+            )
+            .ok();
+        let detail = if self.is_clock() {
+            format!("Clock {}", self.division.label())
+        } else {
+            format!("{} {}", self.channel.label(), self.source.label())
+        };
+        FONT_10
+            .render_aligned(
+                detail.as_str(),
+                anchor + Point::new(0, 26),
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(BRIGHT),
+                disp,
+            )
+            .ok();
+    }
+}
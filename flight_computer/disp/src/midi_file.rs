@@ -0,0 +1,373 @@
+//! Standard MIDI File playback, a stand-in for a controller plugged into
+//! the simulator's MIDI input.
+//!
+//! All tracks are merged and timed by the file's tempo map. SysEx and meta
+//! events other than tempo are skipped; the rest is replayed through the
+//! same parser the UART feeds.
+
+use std::path::Path;
+
+use common::midi::{MidiMessage, MidiParser};
+
+use crate::output::SAMPLE_RATE;
+
+/// Tempo until the file sets one, 120 BPM.
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// A channel message, status included, at the sample it plays.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TimedEvent {
+    sample: u32,
+    bytes: [u8; 3],
+    len: usize,
+}
+
+enum Kind {
+    /// Microseconds per quarter note
+    Tempo(u32),
+    Message([u8; 3], usize),
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len());
+        let end = end.ok_or_else(|| format!("truncated at byte {}", self.bytes.len()))?;
+        let taken = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// A variable length quantity, seven bits a byte.
+    fn varlen(&mut self) -> Result<u32, String> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = value << 7 | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(format!(
+            "variable length quantity too long at byte {}",
+            self.pos
+        ))
+    }
+
+    fn chunk(&mut self) -> Result<(&'a [u8], Reader<'a>), String> {
+        let id = self.take(4)?;
+        let len = self.u32()? as usize;
+        Ok((
+            id,
+            Reader {
+                bytes: self.take(len)?,
+                pos: 0,
+            },
+        ))
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+}
+
+/// A track's events as absolute ticks, and the tick it ends on.
+fn read_track(mut track: Reader) -> Result<(Vec<(u32, Kind)>, u32), String> {
+    let mut events = Vec::new();
+    let mut tick = 0u32;
+    let mut running = None;
+    while !track.done() {
+        tick = tick.saturating_add(track.varlen()?);
+        let mut status = track.u8()?;
+        let mut first = None;
+        if status < 0x80 {
+            first = Some(status);
+            status = running.ok_or("data byte with no running status")?;
+        }
+        match status {
+            0xFF => {
+                running = None;
+                let kind = track.u8()?;
+                let len = track.varlen()? as usize;
+                let data = track.take(len)?;
+                match (kind, data) {
+                    (0x2F, _) => break,
+                    (0x51, &[a, b, c]) => {
+                        events.push((tick, Kind::Tempo(u32::from_be_bytes([0, a, b, c]))))
+                    }
+                    _ => {}
+                }
+            }
+            0xF0 | 0xF7 => {
+                running = None;
+                let len = track.varlen()? as usize;
+                track.take(len)?;
+            }
+            0x80..=0xEF => {
+                running = Some(status);
+                let len = match status & 0xF0 {
+                    0xC0 | 0xD0 => 2,
+                    _ => 3,
+                };
+                let mut bytes = [status, 0, 0];
+                for byte in bytes[1..len].iter_mut() {
+                    *byte = match first.take() {
+                        Some(data) => data,
+                        None => track.u8()?,
+                    };
+                }
+                events.push((tick, Kind::Message(bytes, len)));
+            }
+            other => return Err(format!("unexpected status {:#04x}", other)),
+        }
+    }
+    Ok((events, tick))
+}
+
+pub struct MidiFile {
+    events: Vec<TimedEvent>,
+    /// Samples until the file loops
+    length: u32,
+}
+
+impl MidiFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let mut file = Reader { bytes, pos: 0 };
+        let (id, mut header) = file.chunk()?;
+        if id != b"MThd" {
+            return Err("not a MIDI file".into());
+        }
+        let _format = header.u16()?;
+        let tracks = header.u16()?;
+        let division = header.u16()?;
+
+        let mut ticks = Vec::new();
+        let mut end = 0;
+        for _ in 0..tracks {
+            let (id, track) = file.chunk()?;
+            // Unknown chunks are to be skipped
+            if id == b"MTrk" {
+                let (events, track_end) = read_track(track)?;
+                ticks.extend(events);
+                end = end.max(track_end);
+            }
+        }
+        // Stable, so a tempo change in the first track applies before
+        // anything else at the same tick
+        ticks.sort_by_key(|(tick, _)| *tick);
+
+        // Seconds a tick lasts at `tempo`
+        let tick_time = |tempo: u32| {
+            if division & 0x8000 != 0 {
+                let fps = -((division >> 8) as i8) as f64;
+                let fps = if fps == 29.0 { 29.97 } else { fps };
+                1.0 / (fps * (division & 0xFF) as f64)
+            } else {
+                tempo as f64 / 1e6 / division.max(1) as f64
+            }
+        };
+        let (mut tempo, mut from_tick, mut from_secs) = (DEFAULT_TEMPO, 0, 0.0);
+        let secs = |tick: u32, tempo, from_tick, from_secs| {
+            from_secs + (tick - from_tick) as f64 * tick_time(tempo)
+        };
+        let sample = |secs: f64| (secs * SAMPLE_RATE as f64).round() as u32;
+
+        let mut events = Vec::new();
+        for (tick, kind) in ticks {
+            match kind {
+                Kind::Tempo(new) => {
+                    from_secs = secs(tick, tempo, from_tick, from_secs);
+                    (tempo, from_tick) = (new, tick);
+                }
+                Kind::Message(bytes, len) => events.push(TimedEvent {
+                    sample: sample(secs(tick, tempo, from_tick, from_secs)),
+                    bytes,
+                    len,
+                }),
+            }
+        }
+        let last = events.last().map_or(0, |event| event.sample + 1);
+        let length = sample(secs(end.max(from_tick), tempo, from_tick, from_secs)).max(last);
+        Ok(MidiFile { events, length })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Self::parse(&bytes).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Samples the file plays for before it starts over.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+}
+
+/// Plays a `MidiFile` over and over.
+pub struct MidiPlayer {
+    file: MidiFile,
+    position: u32,
+    next: usize,
+    parser: MidiParser,
+}
+
+impl MidiPlayer {
+    pub fn new(file: MidiFile) -> Self {
+        MidiPlayer {
+            file,
+            position: 0,
+            next: 0,
+            parser: MidiParser::new(),
+        }
+    }
+
+    /// Play the next `samples` samples of the file, calling `play` with
+    /// each message and the sample into the run it falls on.
+    pub fn advance(&mut self, samples: u32, mut play: impl FnMut(u32, &MidiMessage)) {
+        let mut done = 0;
+        loop {
+            let until = self.position + (samples - done);
+            while let Some(event) = self.file.events.get(self.next) {
+                if event.sample >= until {
+                    break;
+                }
+                let offset = done + event.sample - self.position;
+                for byte in &event.bytes[..event.len] {
+                    if let Some(message) = self.parser.push(*byte) {
+                        play(offset, &message);
+                    }
+                }
+                self.next += 1;
+            }
+            if until < self.file.length || self.file.length == 0 {
+                self.position = until;
+                return;
+            }
+            done += self.file.length - self.position;
+            self.position = 0;
+            self.next = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((data.len() as u32).to_be_bytes());
+        chunk.extend(data);
+        chunk
+    }
+
+    /// Two tracks at 96 ticks a beat: tempo in the first, notes in the
+    /// second using running status, with a SysEx and a text event between.
+    fn two_tracks() -> Vec<u8> {
+        let mut file = chunk(b"MThd", &[0, 1, 0, 2, 0, 96]);
+        file.extend(chunk(
+            b"MTrk",
+            &[
+                0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // 120 BPM
+                0x60, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90, // 240 BPM after a beat
+                0x00, 0xFF, 0x2F, 0x00,
+            ],
+        ));
+        file.extend(chunk(
+            b"MTrk",
+            &[
+                0x00, 0x90, 60, 100, // note on
+                0x60, 60, 0, // running status note off, a beat later
+                0x00, 0xF0, 0x02, 0x7E, 0xF7, // SysEx
+                0x00, 0xFF, 0x01, 0x02, b'h', b'i', // text
+                0x60, 0x90, 62, 90, // after SysEx, status again
+                0x60, 0xE0, 0x00, 0x40, // centred bend
+                0x00, 0xFF, 0x2F, 0x00,
+            ],
+        ));
+        file
+    }
+
+    fn played(player: &mut MidiPlayer, samples: u32) -> Vec<(u32, String)> {
+        let mut played = Vec::new();
+        player.advance(samples, |offset, message| {
+            played.push((offset, format!("{:?}", message)))
+        });
+        played
+    }
+
+    #[test]
+    fn parses_and_times_events() {
+        let file = MidiFile::parse(&two_tracks()).unwrap();
+        let beat = SAMPLE_RATE / 2;
+        let samples: Vec<u32> = file.events.iter().map(|event| event.sample).collect();
+        // The second beat is at twice the tempo
+        assert_eq!(samples, [0, beat, beat + beat / 2, 2 * beat]);
+        assert_eq!(file.events[1].bytes, [0x90, 60, 0]);
+        assert_eq!(file.events[3].len, 3);
+        assert_eq!(file.length(), 2 * beat + 1);
+    }
+
+    #[test]
+    fn loops() {
+        let mut player = MidiPlayer::new(MidiFile::parse(&two_tracks()).unwrap());
+        let beat = SAMPLE_RATE / 2;
+        let first = played(&mut player, beat);
+        assert_eq!(
+            first,
+            [(0, "NoteOn { channel: 0, note: 60, velocity: 100 }".into())]
+        );
+        // Zero velocity note on arrives as note off
+        let second = played(&mut player, beat + 2);
+        assert_eq!(second.len(), 4);
+        assert_eq!(
+            second[0],
+            (0, "NoteOff { channel: 0, note: 60, velocity: 0 }".into())
+        );
+        assert_eq!(
+            second[2],
+            (beat, "PitchBend { channel: 0, value: 0 }".into())
+        );
+        // Back at the start, one sample after the end
+        assert_eq!(
+            second[3],
+            (
+                beat + 1,
+                "NoteOn { channel: 0, note: 60, velocity: 100 }".into()
+            )
+        );
+        assert!(played(&mut player, beat - 2).is_empty());
+    }
+
+    #[test]
+    fn errors() {
+        let error = |bytes: &[u8]| MidiFile::parse(bytes).err().unwrap();
+        assert_eq!(error(b"RIFF\0\0\0\0"), "not a MIDI file");
+        assert_eq!(error(b"MThd\0\0\0\x06\0\0"), "truncated at byte 10");
+
+        let mut header = chunk(b"MThd", &[0, 0, 0, 1, 0, 96]);
+        header.extend(chunk(b"MTrk", &[0x00, 60, 100]));
+        assert_eq!(error(&header), "data byte with no running status");
+        header.truncate(header.len() - 3);
+        header.extend([0x00, 0xF2, 0, 0]);
+        assert_eq!(error(&header), "unexpected status 0xf2");
+    }
+}
@@ -0,0 +1,134 @@
+//! A host MIDI port for the simulator, built with `--features midi-port`
+//! (ALSA on Linux).
+//!
+//! Bytes arrive on the backend's thread and are parsed on the simulator's,
+//! through the same parser the UART feeds. What the outputs send goes to
+//! the same device, if it takes MIDI in.
+
+use std::sync::mpsc::{channel, Receiver};
+
+use common::midi::{MidiMessage, MidiParser};
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+
+/// Name the simulator shows up as to other MIDI programs.
+const CLIENT_NAME: &str = "poco_pico";
+
+pub struct MidiPort {
+    _connection: MidiInputConnection<()>,
+    output: Option<MidiOutputConnection>,
+    bytes: Receiver<Vec<u8>>,
+    parser: MidiParser,
+    /// Splits the queued bytes back into whole messages for `output`
+    out_parser: MidiParser,
+}
+
+impl MidiPort {
+    /// Connect to the first input whose name contains `name`, or with
+    /// `virtual`, open a port other programs can connect to.
+    pub fn open(name: &str) -> Result<Self, String> {
+        let mut input = MidiInput::new(CLIENT_NAME).map_err(|e| e.to_string())?;
+        input.ignore(Ignore::None);
+        let (sender, bytes) = channel();
+        let callback = move |_: u64, message: &[u8], _: &mut ()| {
+            sender.send(message.to_vec()).ok();
+        };
+
+        let connection = if name == "virtual" {
+            #[cfg(unix)]
+            {
+                use midir::os::unix::VirtualInput;
+                input
+                    .create_virtual(CLIENT_NAME, callback, ())
+                    .map_err(|e| e.to_string())?
+            }
+            #[cfg(not(unix))]
+            return Err("virtual ports need ALSA or CoreMIDI".into());
+        } else {
+            let ports = input.ports();
+            let port = ports
+                .iter()
+                .find(|port| input.port_name(port).is_ok_and(|port| port.contains(name)))
+                .ok_or_else(|| {
+                    let names: Vec<String> = ports
+                        .iter()
+                        .filter_map(|port| input.port_name(port).ok())
+                        .collect();
+                    format!("no MIDI input '{}', found {:?}", name, names)
+                })?
+                .clone();
+            input
+                .connect(&port, CLIENT_NAME, callback, ())
+                .map_err(|e| e.to_string())?
+        };
+        Ok(MidiPort {
+            _connection: connection,
+            output: open_output(name)?,
+            bytes,
+            parser: MidiParser::new(),
+            out_parser: MidiParser::new(),
+        })
+    }
+
+    /// Send bytes queued by the outputs, a whole message at a time as the
+    /// backend wants them. Dropped if the device has no MIDI in.
+    pub fn send(&mut self, bytes: &[u8]) {
+        let Some(output) = &mut self.output else {
+            return;
+        };
+        for byte in bytes {
+            match self.out_parser.push(*byte) {
+                Some(MidiMessage::SysEx(data)) => {
+                    let message = [&[0xF0], data, &[0xF7]].concat();
+                    output.send(&message).ok();
+                }
+                Some(message) => {
+                    let mut buf = [0; 3];
+                    let len = message.encode(&mut buf);
+                    output.send(&buf[..len]).ok();
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Pass everything received since the last call to `receive`.
+    pub fn receive(&mut self, mut receive: impl FnMut(&MidiMessage)) {
+        while let Ok(message) = self.bytes.try_recv() {
+            for byte in message {
+                if let Some(message) = self.parser.push(byte) {
+                    receive(&message);
+                }
+            }
+        }
+    }
+}
+
+/// The output to go with the input `open` found, `None` if the device
+/// only sends MIDI.
+fn open_output(name: &str) -> Result<Option<MidiOutputConnection>, String> {
+    let output = MidiOutput::new(CLIENT_NAME).map_err(|e| e.to_string())?;
+    if name == "virtual" {
+        #[cfg(unix)]
+        {
+            use midir::os::unix::VirtualOutput;
+            return output
+                .create_virtual(CLIENT_NAME)
+                .map(Some)
+                .map_err(|e| e.to_string());
+        }
+        #[cfg(not(unix))]
+        return Err("virtual ports need ALSA or CoreMIDI".into());
+    }
+    let ports = output.ports();
+    let Some(port) = ports
+        .iter()
+        .find(|port| output.port_name(port).is_ok_and(|port| port.contains(name)))
+        .cloned()
+    else {
+        return Ok(None);
+    };
+    output
+        .connect(&port, CLIENT_NAME)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,343 @@
+//! Every output mode, in the order the mode carousel shows them.
+//!
+//! Adding a mode means adding its `OutputChannel` variant and an entry in
+//! `MODES`; switching, the carousel and scripts all go by this table.
+
+use embedded_graphics::pixelcolor::Bgr565;
+use embedded_graphics::prelude::{Point, Size};
+use embedded_graphics::primitives::{Polyline, PrimitiveStyle, Rectangle, StyledDrawable};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use crate::clk_out::ClockOut;
+use crate::display::{BLUE, BRIGHT, DARK, FONT_08, FONT_10, TAN};
+use crate::envelope::Envelope;
+use crate::gate_out::GateOut;
+use crate::lfo::QuadLfo;
+use crate::midi_cv::MidiCv;
+use crate::output::{NoOutput, OutputChannel};
+use crate::poly::PolyMidi;
+use crate::quantizer::Quantizer;
+use crate::sequencer::Sequencer;
+use crate::Display;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Category {
+    Clock,
+    Modulation,
+    Pitch,
+    Midi,
+}
+
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Clock => "Clock",
+            Category::Modulation => "Modulation",
+            Category::Pitch => "Pitch",
+            Category::Midi => "MIDI",
+        }
+    }
+}
+
+/// A polyline on an 8x8 grid, scaled to wherever it's drawn.
+pub type Icon = &'static [(i32, i32)];
+
+pub struct ModeInfo {
+    pub name: &'static str,
+    /// `None` for the disabled mode, which belongs to none
+    pub category: Option<Category>,
+    pub description: &'static str,
+    pub icon: Icon,
+    pub new: fn() -> OutputChannel,
+    pub is: fn(&OutputChannel) -> bool,
+}
+
+pub const MODES: [ModeInfo; 9] = [
+    ModeInfo {
+        name: "NoOutput",
+        category: None,
+        description: "Output held at 0V.",
+        icon: &[(0, 4), (8, 4)],
+        new: || NoOutput::new().into(),
+        is: |mode| matches!(mode, OutputChannel::NoOutput(_)),
+    },
+    ModeInfo {
+        name: "ClockOut",
+        category: Some(Category::Clock),
+        description: "Divided or multiplied master clock.",
+        icon: &[
+            (0, 8),
+            (0, 0),
+            (2, 0),
+            (2, 8),
+            (4, 8),
+            (4, 0),
+            (6, 0),
+            (6, 8),
+            (8, 8),
+        ],
+        new: || ClockOut::default().into(),
+        is: |mode| matches!(mode, OutputChannel::ClockOut(_)),
+    },
+    ModeInfo {
+        name: "Envelope",
+        category: Some(Category::Modulation),
+        description: "ADSR or AR on a clock, CV or MIDI gate.",
+        icon: &[(0, 8), (2, 0), (3, 3), (6, 3), (8, 8)],
+        new: || Envelope::default().into(),
+        is: |mode| matches!(mode, OutputChannel::Envelope(_)),
+    },
+    ModeInfo {
+        name: "QuadLfo",
+        category: Some(Category::Modulation),
+        description: "Two LFOs a quarter cycle apart.",
+        icon: &[(0, 4), (1, 1), (3, 1), (5, 7), (7, 7), (8, 4)],
+        new: || QuadLfo::default().into(),
+        is: |mode| matches!(mode, OutputChannel::QuadLfo(_)),
+    },
+    ModeInfo {
+        name: "Quantizer",
+        category: Some(Category::Pitch),
+        description: "Snaps a CV input or output to a scale.",
+        icon: &[
+            (0, 8),
+            (0, 6),
+            (2, 6),
+            (2, 4),
+            (4, 4),
+            (4, 2),
+            (6, 2),
+            (6, 0),
+            (8, 0),
+        ],
+        new: || Quantizer::default().into(),
+        is: |mode| matches!(mode, OutputChannel::Quantizer(_)),
+    },
+    ModeInfo {
+        name: "Sequencer",
+        category: Some(Category::Pitch),
+        description: "16 or 32 steps of pitch with gates.",
+        icon: &[
+            (0, 8),
+            (0, 3),
+            (2, 3),
+            (2, 6),
+            (4, 6),
+            (4, 1),
+            (6, 1),
+            (6, 5),
+            (8, 5),
+            (8, 8),
+        ],
+        new: || Sequencer::default().into(),
+        is: |mode| matches!(mode, OutputChannel::Sequencer(_)),
+    },
+    ModeInfo {
+        name: "GateOut",
+        category: Some(Category::Pitch),
+        description: "Gate of another output's pitch mode.",
+        icon: &[(0, 8), (2, 8), (2, 0), (6, 0), (6, 8), (8, 8)],
+        new: || GateOut::default().into(),
+        is: |mode| matches!(mode, OutputChannel::GateOut(_)),
+    },
+    ModeInfo {
+        name: "PolyMidi",
+        category: Some(Category::Midi),
+        description: "Up to four voices of pitch and gate.",
+        icon: &[
+            (0, 0),
+            (0, 8),
+            (8, 8),
+            (8, 0),
+            (0, 0),
+            (2, 0),
+            (2, 5),
+            (3, 5),
+            (3, 0),
+            (5, 0),
+            (5, 5),
+            (6, 5),
+            (6, 0),
+        ],
+        new: || PolyMidi::default().into(),
+        is: |mode| matches!(mode, OutputChannel::PolyMidi(_)),
+    },
+    ModeInfo {
+        name: "MidiCv",
+        category: Some(Category::Midi),
+        description: "Velocity, bend, pressure, mod or clock.",
+        icon: &[(0, 8), (2, 8), (4, 2), (6, 2), (6, 5), (8, 5)],
+        new: || MidiCv::default().into(),
+        is: |mode| matches!(mode, OutputChannel::MidiCv(_)),
+    },
+];
+
+/// Where `mode` sits in `MODES`.
+pub fn index_of(mode: &OutputChannel) -> usize {
+    MODES
+        .iter()
+        .position(|info| (info.is)(mode))
+        .expect("every mode is registered")
+}
+
+pub fn find(name: &str) -> Option<&'static ModeInfo> {
+    MODES.iter().find(|info| info.name == name)
+}
+
+/// A fresh mode `steps` along the carousel from `mode`, wrapping.
+pub fn step(mode: &OutputChannel, steps: i32) -> OutputChannel {
+    let index = (index_of(mode) as i32 + steps).rem_euclid(MODES.len() as i32);
+    (MODES[index as usize].new)()
+}
+
+fn draw_icon(disp: &mut Display, icon: Icon, area: Rectangle, style: &PrimitiveStyle<Bgr565>) {
+    let scale = |(x, y): (i32, i32)| {
+        area.top_left
+            + Point::new(
+                x * (area.size.width as i32 - 1) / 8,
+                y * (area.size.height as i32 - 1) / 8,
+            )
+    };
+    let points: Vec<Point> = icon.iter().copied().map(scale).collect();
+    Polyline::new(&points).draw_styled(style, disp).ok();
+}
+
+/// `text` broken into lines no wider than `width`.
+fn wrap(text: &str, width: u32) -> Vec<String> {
+    let fits = |line: &str| {
+        FONT_08
+            .get_rendered_dimensions(line, Point::zero(), VerticalPosition::Top)
+            .ok()
+            .and_then(|dimensions| dimensions.bounding_box)
+            .is_none_or(|bounds| bounds.size.width <= width)
+    };
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if fits(&format!("{} {}", line, word)) => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.into()),
+        }
+    }
+    lines
+}
+
+/// The mode at `index` with its neighbours either side, then its name,
+/// category and description.
+pub fn draw_carousel(disp: &mut Display, window: Rectangle, index: usize) {
+    let frame = PrimitiveStyle::with_stroke(BLUE, 1);
+    window.offset(-1).draw_styled(&frame, disp).ok();
+
+    let mode = &MODES[index];
+    let top = window.top_left.y;
+    let center = window.center().x;
+    let category = mode.category.map_or("Off", |category| category.name());
+    FONT_08
+        .render_aligned(
+            category,
+            Point::new(window.top_left.x + 4, top + 3),
+            VerticalPosition::Top,
+            HorizontalAlignment::Left,
+            FontColor::Transparent(TAN),
+            disp,
+        )
+        .ok();
+    FONT_08
+        .render_aligned(
+            format_args!("{}/{}", index + 1, MODES.len()),
+            Point::new(window.top_left.x + window.size.width as i32 - 4, top + 3),
+            VerticalPosition::Top,
+            HorizontalAlignment::Right,
+            FontColor::Transparent(DARK),
+            disp,
+        )
+        .ok();
+
+    let icon = Rectangle::with_center(Point::new(center, top + 24), Size::new(28, 18));
+    draw_icon(
+        disp,
+        mode.icon,
+        icon,
+        &PrimitiveStyle::with_stroke(BRIGHT, 1),
+    );
+    let neighbours = PrimitiveStyle::with_stroke(DARK, 1);
+    for (offset, x) in [(MODES.len() - 1, center - 34), (1, center + 34)] {
+        let neighbour = &MODES[(index + offset) % MODES.len()];
+        let area = Rectangle::with_center(Point::new(x, top + 24), Size::new(14, 10));
+        draw_icon(disp, neighbour.icon, area, &neighbours);
+    }
+
+    FONT_10
+        .render_aligned(
+            mode.name,
+            Point::new(center, top + 36),
+            VerticalPosition::Top,
+            HorizontalAlignment::Center,
+            FontColor::Transparent(TAN),
+            disp,
+        )
+        .ok();
+    for (i, line) in wrap(mode.description, window.size.width - 8)
+        .iter()
+        .take(3)
+        .enumerate()
+    {
+        FONT_08
+            .render_aligned(
+                line.as_str(),
+                Point::new(window.top_left.x + 4, top + 52 + 9 * i as i32),
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(DARK),
+                disp,
+            )
+            .ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_is_consistent() {
+        for (i, info) in MODES.iter().enumerate() {
+            assert_eq!(index_of(&(info.new)()), i, "{}", info.name);
+            assert!(find(info.name).is_some_and(|found| found.name == info.name));
+            assert!(info
+                .icon
+                .iter()
+                .all(|&(x, y)| (0..=8).contains(&x) && (0..=8).contains(&y)));
+            assert!(wrap(info.description, 88).len() <= 3, "{}", info.name);
+        }
+        // Grouped, so the carousel passes through each category once
+        let mut seen = Vec::new();
+        for info in &MODES {
+            if seen.last() != Some(&info.category) {
+                assert!(!seen.contains(&info.category), "{}", info.name);
+                seen.push(info.category);
+            }
+        }
+        assert!(find("Euclid").is_none());
+    }
+
+    #[test]
+    fn steps_wrap() {
+        let off = NoOutput::new().into();
+        assert!(matches!(step(&off, 1), OutputChannel::ClockOut(_)));
+        assert!(matches!(step(&off, -1), OutputChannel::MidiCv(_)));
+        assert!(matches!(
+            step(&off, MODES.len() as i32),
+            OutputChannel::NoOutput(_)
+        ));
+    }
+
+    #[test]
+    fn wraps_descriptions() {
+        assert_eq!(wrap("one two three", 1000), ["one two three".to_string()]);
+        assert_eq!(wrap("one two three", 1), ["one", "two", "three"]);
+        assert!(wrap("", 10).is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+//! Listening in on the outputs while tuning modes in the simulator.
+//!
+//! The monitored outputs are mixed down to one channel, ±10V at full
+//! scale, and handed to a sink: the host's sound card (built with
+//! `--features audio`), a rolling WAV of the last few seconds, or nothing
+//! at all when running headless.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use common::calibration::NUM_OUTPUTS;
+
+use crate::output::SAMPLE_RATE;
+use crate::render::{wav_sample, write_wav_header};
+
+/// Volts at full scale, the DAC's range.
+const FULL_SCALE: f32 = 10.0;
+
+/// Where the mix goes, a sample at a time at `SAMPLE_RATE`.
+pub trait AudioSink {
+    fn write(&mut self, sample: f32);
+
+    /// Called once a frame, for sinks that batch their work.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Drops everything, for headless runs and CI.
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn write(&mut self, _sample: f32) {}
+}
+
+/// Keeps the last few seconds and rewrites them to a WAV file each second,
+/// so the file always holds the most recent stretch.
+pub struct RollingWav {
+    path: PathBuf,
+    samples: VecDeque<i16>,
+    capacity: usize,
+    unwritten: u32,
+}
+
+impl RollingWav {
+    pub fn new(path: PathBuf, seconds: u32) -> Self {
+        let capacity = (seconds * SAMPLE_RATE) as usize;
+        RollingWav {
+            path,
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            unwritten: 0,
+        }
+    }
+
+    fn save(&mut self) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        write_wav_header(&mut out, 1, self.samples.len() as u32)?;
+        for sample in &self.samples {
+            out.write_all(&sample.to_le_bytes())?;
+        }
+        self.unwritten = 0;
+        out.flush()
+    }
+}
+
+impl AudioSink for RollingWav {
+    fn write(&mut self, sample: f32) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(wav_sample(sample));
+        self.unwritten += 1;
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.unwritten >= SAMPLE_RATE {
+            self.save()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RollingWav {
+    fn drop(&mut self) {
+        if self.unwritten > 0 {
+            if let Err(e) = self.save() {
+                eprintln!("ERR: {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// The host's default output device.
+#[cfg(feature = "audio")]
+pub struct DeviceSink {
+    queue: std::sync::Arc<std::sync::Mutex<VecDeque<f32>>>,
+    /// Most the queue holds before the oldest is dropped
+    max_queued: usize,
+    /// Device samples per simulator sample
+    ratio: f64,
+    phase: f64,
+    _stream: cpal::Stream,
+}
+
+#[cfg(feature = "audio")]
+impl DeviceSink {
+    pub fn open() -> Result<Self, String> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+        use std::sync::{Arc, Mutex};
+
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("no audio output device")?;
+        let config = device
+            .default_output_config()
+            .map_err(|e| e.to_string())?
+            .config();
+        let channels = config.channels as usize;
+        let rate = config.sample_rate.0;
+
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        let playing = queue.clone();
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    let mut queue = playing.lock().unwrap();
+                    for frame in data.chunks_mut(channels) {
+                        // Silence when the simulator falls behind
+                        frame.fill(queue.pop_front().unwrap_or(0.0));
+                    }
+                },
+                |e| eprintln!("ERR: audio: {}", e),
+                None,
+            )
+            .map_err(|e| e.to_string())?;
+        stream.play().map_err(|e| e.to_string())?;
+        Ok(DeviceSink {
+            queue,
+            // Frames arrive in bursts, a few of them is enough
+            max_queued: rate as usize / 5,
+            ratio: rate as f64 / SAMPLE_RATE as f64,
+            phase: 0.0,
+            _stream: stream,
+        })
+    }
+}
+
+#[cfg(feature = "audio")]
+impl AudioSink for DeviceSink {
+    fn write(&mut self, sample: f32) {
+        let mut queue = self.queue.lock().unwrap();
+        // Repeat or skip samples to match the device's rate
+        self.phase += self.ratio;
+        while self.phase >= 1.0 {
+            self.phase -= 1.0;
+            if queue.len() == self.max_queued {
+                queue.pop_front();
+            }
+            queue.push_back(sample);
+        }
+    }
+}
+
+/// Mixes the outputs being listened to into a sink.
+pub struct Monitor {
+    outputs: Vec<usize>,
+    sink: Box<dyn AudioSink>,
+}
+
+impl Monitor {
+    pub fn new(outputs: Vec<usize>, sink: Box<dyn AudioSink>) -> Self {
+        Monitor { outputs, sink }
+    }
+
+    /// Listens to nothing.
+    pub fn null() -> Self {
+        Self::new(Vec::new(), Box::new(NullSink))
+    }
+
+    /// Outputs to listen to, `1,3` style, counting from 1 like the panel.
+    pub fn parse_outputs(list: &str) -> Result<Vec<usize>, String> {
+        list.split(',')
+            .map(|n| match n.trim().parse::<usize>() {
+                Ok(n) if (1..=NUM_OUTPUTS).contains(&n) => Ok(n - 1),
+                _ => Err(format!("no output '{}'", n)),
+            })
+            .collect()
+    }
+
+    /// Mix one sample of every output's level.
+    pub fn push(&mut self, levels: &[f32; NUM_OUTPUTS]) {
+        if self.outputs.is_empty() {
+            return;
+        }
+        let sum: f32 = self.outputs.iter().map(|ch| levels[*ch]).sum();
+        let mix = sum / self.outputs.len() as f32 / FULL_SCALE;
+        self.sink.write(mix.clamp(-1.0, 1.0));
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Shares what it's given with the test.
+    struct Capture(Arc<Mutex<Vec<f32>>>);
+
+    impl AudioSink for Capture {
+        fn write(&mut self, sample: f32) {
+            self.0.lock().unwrap().push(sample);
+        }
+    }
+
+    #[test]
+    fn mixes_selected_outputs() {
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let outputs = Monitor::parse_outputs("2, 4").unwrap();
+        let mut monitor = Monitor::new(outputs, Box::new(Capture(captured.clone())));
+        let mut levels = [0.0; NUM_OUTPUTS];
+        levels[0] = 10.0;
+        levels[1] = 5.0;
+        levels[3] = -2.0;
+        monitor.push(&levels);
+        levels[1] = 20.0;
+        levels[3] = 20.0;
+        monitor.push(&levels);
+        assert_eq!(*captured.lock().unwrap(), [0.15, 1.0]);
+
+        // Nothing to listen to, nothing written
+        let mut monitor = Monitor::new(Vec::new(), Box::new(Capture(captured.clone())));
+        monitor.push(&levels);
+        assert_eq!(captured.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn parses_outputs() {
+        assert_eq!(Monitor::parse_outputs("1,8"), Ok(vec![0, 7]));
+        assert_eq!(Monitor::parse_outputs("3"), Ok(vec![2]));
+        assert_eq!(Monitor::parse_outputs("0"), Err("no output '0'".into()));
+        assert_eq!(Monitor::parse_outputs("1,x"), Err("no output 'x'".into()));
+    }
+
+    #[test]
+    fn rolling_wav_keeps_the_latest() {
+        let path = std::env::temp_dir().join(format!("monitor_{}.wav", std::process::id()));
+        let mut wav = RollingWav::new(path.clone(), 1);
+        for i in 0..SAMPLE_RATE + 10 {
+            wav.write(if i < 10 { 1.0 } else { 0.5 });
+        }
+        wav.flush().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 44 + 2 * SAMPLE_RATE as usize);
+        assert_eq!(&bytes[22..24], &1u16.to_le_bytes());
+        // The first ten have rolled off the front
+        assert_eq!(&bytes[44..46], &wav_sample(0.5).to_le_bytes());
+
+        // Anything left is saved on the way out
+        wav.write(-1.0);
+        drop(wav);
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[bytes.len() - 2..], &wav_sample(-1.0).to_le_bytes());
+        std::fs::remove_file(path).unwrap();
+    }
+}
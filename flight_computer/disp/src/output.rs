@@ -7,6 +7,52 @@ use crate::parameters::{ConfigParameter, Parameter};
 use crate::Display;
 
 use crate::clk_out::{ClockData, ClockOut};
+use crate::envelope::Envelope;
+use crate::gate_out::GateOut;
+use crate::lfo::QuadLfo;
+use crate::midi_cv::MidiCv;
+use crate::poly::PolyMidi;
+use crate::quantizer::Quantizer;
+use crate::sequencer::Sequencer;
+
+use common::calibration::{NUM_INPUTS, NUM_OUTPUTS};
+use common::midi::{MidiOut, MidiState};
+
+/// Rate `generate` is called at, matching the output core's SysTick.
+pub const SAMPLE_RATE: u32 = 48_000;
+/// Level of a high gate or trigger.
+pub const GATE_VOLTS: f32 = 5.0;
+
+/// Everything a mode can read while generating one sample.
+#[derive(Clone, Copy, Debug)]
+pub struct InputState<'a> {
+    pub cycle: u32,
+    /// Samples per beat of the master clock
+    pub beat_period: u32,
+    /// Calibrated CV inputs
+    pub cv: [f32; NUM_INPUTS],
+    /// Every channel's previous sample
+    pub outputs: [f32; NUM_OUTPUTS],
+    /// Every channel's previous gate, see `OutSignal::gate`
+    pub gates: [bool; NUM_OUTPUTS],
+    pub midi: &'a MidiState,
+}
+
+#[cfg(test)]
+impl<'a> InputState<'a> {
+    /// Sample `cycle` at one beat a second, with every input and output at
+    /// 0V, for tests to change what they need.
+    pub fn at(cycle: u32, midi: &'a MidiState) -> Self {
+        InputState {
+            cycle,
+            beat_period: SAMPLE_RATE,
+            cv: [0.0; NUM_INPUTS],
+            outputs: [0.0; NUM_OUTPUTS],
+            gates: [false; NUM_OUTPUTS],
+            midi,
+        }
+    }
+}
 
 #[enum_dispatch]
 pub trait OutSignal {
@@ -14,17 +60,47 @@ pub trait OutSignal {
     fn parameter(&mut self, param: usize) -> Option<(&'static str, &mut dyn ConfigParameter)>;
     fn draw_output(&self, disp: &mut Display, window: Rectangle);
     fn draw_configure(&self, disp: &mut Display, window: Rectangle);
-    fn next(&self) -> OutputChannel;
-    fn prev(&self) -> OutputChannel;
+    /// Outputs the mode drives: its own channel and the channels after it,
+    /// linked into a group that is configured through the first.
+    fn width(&self) -> usize {
+        1
+    }
+    /// Told how many outputs there are from its channel to the last, the
+    /// most `width` can cover, before each `generate`.
+    fn fit(&mut self, _outputs: usize) {}
+    /// Level of the `link`th linked channel, 1 for the one after this, as
+    /// of the last `generate`.
+    fn linked(&self, _link: usize) -> f32 {
+        0.0
+    }
+    /// Draw the `link`th linked channel on the output strip.
+    fn draw_linked(&self, _link: usize, _disp: &mut Display, _window: Rectangle) {}
     //fn store(&self) -> &[u8];
     //fn load(&mut self, state: &[u8]) {}
-    //fn generate(&self, input: InputState, private: &mut PrivateData) -> i16 {}
+    /// Produce the next sample, in volts.
+    fn generate(&mut self, input: &InputState) -> f32;
+    /// Gate a pitch mode pairs with its output, for a `GateOut` channel to follow.
+    fn gate(&self) -> bool {
+        false
+    }
+    /// Queue any MIDI the last sample produced.
+    fn midi(&mut self, _out: &mut MidiOut) {}
+    /// End any notes `midi` left sounding, the mode is being replaced.
+    fn release(&mut self, _out: &mut MidiOut) {}
 }
 
 #[enum_dispatch(OutSignal)]
+#[derive(Clone)]
 pub enum OutputChannel {
     NoOutput,
     ClockOut,
+    Quantizer,
+    Sequencer,
+    GateOut,
+    Envelope,
+    QuadLfo,
+    PolyMidi,
+    MidiCv,
 }
 
 pub union PrivateData {
@@ -49,12 +125,8 @@ impl OutSignal for NoOutput {
         None
     }
 
-    fn next(&self) -> OutputChannel {
-        ClockOut::default().into()
-    }
-
-    fn prev(&self) -> OutputChannel {
-        ClockOut::default().into()
+    fn generate(&mut self, _input: &InputState) -> f32 {
+        0.0
     }
 
     fn draw_output(&self, disp: &mut Display, window: Rectangle) {}
@@ -0,0 +1,82 @@
+//! Clickable stand-ins for the encoder and buttons, drawn beside the screen
+//! in the simulator's window.
+
+use embedded_graphics::prelude::{Point, Size};
+use embedded_graphics::primitives::StyledDrawable;
+use embedded_graphics::primitives::{CornerRadii, PrimitiveStyle, Rectangle, RoundedRectangle};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use crate::display::{DARK, FONT_10, TAN};
+use crate::gui::{InputEvent, SCREEN_SIZE};
+use crate::Display;
+
+/// Width of the strip of buttons right of the screen.
+pub const PANEL_WIDTH: u32 = 40;
+
+// Buttons top to bottom, with the encoder's three grouped in the middle
+const BUTTONS: [(InputEvent, &str, i32); 5] = [
+    (InputEvent::BtnUp, "Up", 6),
+    (InputEvent::EncDec, "<", 38),
+    (InputEvent::EncPush, "Push", 56),
+    (InputEvent::EncInc, ">", 74),
+    (InputEvent::BtnDn, "Dn", 106),
+];
+
+fn button_area(y: i32) -> Rectangle {
+    Rectangle::new(Point::new(SCREEN_SIZE as i32 + 4, y), Size::new(32, 16))
+}
+
+pub fn draw_panel(display: &mut Display) {
+    let style = PrimitiveStyle::with_stroke(DARK, 1);
+    for (_, label, y) in BUTTONS {
+        let area = button_area(y);
+        RoundedRectangle::new(area, CornerRadii::new(Size::new_equal(3)))
+            .draw_styled(&style, display)
+            .ok();
+        FONT_10
+            .render_aligned(
+                label,
+                area.center(),
+                VerticalPosition::Center,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(TAN),
+                display,
+            )
+            .ok();
+    }
+}
+
+/// A right click on the encoder's push button holds it down, the
+/// other buttons have no long press.
+pub fn long_press_at(point: Point) -> Option<InputEvent> {
+    match button_at(point) {
+        Some(InputEvent::EncPush) => Some(InputEvent::EncHold),
+        _ => None,
+    }
+}
+
+/// The control under a click at `point`, if any.
+pub fn button_at(point: Point) -> Option<InputEvent> {
+    BUTTONS
+        .iter()
+        .find(|(_, _, y)| button_area(*y).contains(point))
+        .map(|(input, _, _)| *input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clicks_hit_buttons() {
+        let x = SCREEN_SIZE as i32 + 20;
+        assert_eq!(button_at(Point::new(x, 10)), Some(InputEvent::BtnUp));
+        assert_eq!(button_at(Point::new(x, 63)), Some(InputEvent::EncPush));
+        assert_eq!(button_at(Point::new(x, 121)), Some(InputEvent::BtnDn));
+        // Between buttons and on the screen itself
+        assert_eq!(button_at(Point::new(x, 30)), None);
+        assert_eq!(button_at(Point::new(20, 63)), None);
+        assert_eq!(long_press_at(Point::new(x, 63)), Some(InputEvent::EncHold));
+        assert_eq!(long_press_at(Point::new(x, 10)), None);
+    }
+}
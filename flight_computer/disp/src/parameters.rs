@@ -1,12 +1,22 @@
+use core::fmt::Display as FmtDisplay;
 use core::ops::{Add, Deref, Sub};
 
+use embedded_graphics::primitives::Rectangle;
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use crate::display::{BRIGHT, FONT_16};
 use crate::Display;
 
 pub trait ConfigParameter {
     fn next(&mut self);
     fn prev(&mut self);
 
-    fn draw_edit(&self, disp: &mut Display);
+    /// Handle an encoder push while editing, returns false to leave the editor.
+    fn push(&mut self) -> bool {
+        false
+    }
+
+    fn draw_edit(&self, disp: &mut Display, window: Rectangle);
 }
 
 #[derive(Clone)]
@@ -42,7 +52,7 @@ impl<T> Parameter<T> {
 
 impl<T> ConfigParameter for Parameter<T>
 where
-    T: Copy + PartialOrd + Add<Output = T> + Sub<Output = T>,
+    T: Copy + PartialOrd + Add<Output = T> + Sub<Output = T> + FmtDisplay,
 {
     fn next(&mut self) {
         let mut new_value = self.value.add(self.step);
@@ -62,7 +72,9 @@ where
         self.value = new_value;
     }
 
-    fn draw_edit(&self, disp: &mut Display) {}
+    fn draw_edit(&self, disp: &mut Display, window: Rectangle) {
+        draw_value(disp, window, format_args!("{:.2}", self.value));
+    }
 }
 
 impl<T> Deref for Parameter<T> {
@@ -72,3 +84,103 @@ impl<T> Deref for Parameter<T> {
         &self.value
     }
 }
+
+/// One of a fixed list of named options, always rolls over.
+#[derive(Clone)]
+pub struct Choice {
+    labels: &'static [&'static str],
+    index: usize,
+}
+
+impl Choice {
+    pub fn new(labels: &'static [&'static str], index: usize) -> Self {
+        Choice { labels, index }
+    }
+
+    pub fn set(&mut self, index: usize) {
+        self.index = index.min(self.labels.len() - 1);
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.labels[self.index]
+    }
+}
+
+impl ConfigParameter for Choice {
+    fn next(&mut self) {
+        self.index = (self.index + 1) % self.labels.len();
+    }
+
+    fn prev(&mut self) {
+        self.index = self.index.checked_sub(1).unwrap_or(self.labels.len() - 1);
+    }
+
+    fn draw_edit(&self, disp: &mut Display, window: Rectangle) {
+        draw_value(disp, window, format_args!("{}", self.label()));
+    }
+}
+
+impl Deref for Choice {
+    type Target = usize;
+
+    fn deref(&self) -> &Self::Target {
+        &self.index
+    }
+}
+
+/// A time from 1ms to 10s, each step a fixed ratio longer than the last.
+#[derive(Clone, Copy, PartialEq)]
+pub struct LogTime {
+    step: u8,
+}
+
+impl LogTime {
+    const MIN_MS: f32 = 1.0;
+    const MAX_MS: f32 = 10_000.0;
+    const STEPS: u8 = 64;
+
+    pub fn new(millis: f32) -> Self {
+        let position = (millis / Self::MIN_MS).ln() / (Self::MAX_MS / Self::MIN_MS).ln();
+        LogTime {
+            step: (position * Self::STEPS as f32)
+                .round()
+                .clamp(0.0, Self::STEPS as f32) as u8,
+        }
+    }
+
+    pub fn millis(&self) -> f32 {
+        let position = self.step as f32 / Self::STEPS as f32;
+        Self::MIN_MS * (Self::MAX_MS / Self::MIN_MS).powf(position)
+    }
+}
+
+impl ConfigParameter for LogTime {
+    fn next(&mut self) {
+        self.step = (self.step + 1).min(Self::STEPS);
+    }
+
+    fn prev(&mut self) {
+        self.step = self.step.saturating_sub(1);
+    }
+
+    fn draw_edit(&self, disp: &mut Display, window: Rectangle) {
+        match self.millis() {
+            ms if ms < 10.0 => draw_value(disp, window, format_args!("{:.1} ms", ms)),
+            ms if ms < 1000.0 => draw_value(disp, window, format_args!("{:.0} ms", ms)),
+            ms => draw_value(disp, window, format_args!("{:.2} s", ms / 1000.0)),
+        }
+    }
+}
+
+fn draw_value(disp: &mut Display, window: Rectangle, value: core::fmt::Arguments<'_>) {
+    FONT_16
+        .render_aligned(
+            value,
+            window.center(),
+            VerticalPosition::Center,
+            HorizontalAlignment::Center,
+            FontColor::Transparent(BRIGHT),
+            disp,
+        )
+        .ok();
+}
@@ -0,0 +1,241 @@
+//! The simulator's stand-ins for what gets patched into the panel: the
+//! master clock's tempo and a voltage for each CV input, drawn as a strip
+//! below the screen and set with the mouse.
+
+use common::calibration::{MAX_VOLTS, MIN_VOLTS, NUM_INPUTS};
+use common::jacks::Jacks;
+use embedded_graphics::pixelcolor::Bgr565;
+use embedded_graphics::prelude::{DrawTarget, Point, RgbColor, Size};
+use embedded_graphics::primitives::{
+    CornerRadii, Line, PrimitiveStyle, Rectangle, RoundedRectangle, StyledDrawable,
+};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use crate::display::{BLUE, BRIGHT, DARK, FONT_08, FONT_10, TAN};
+use crate::engine::{DEFAULT_BPM, MAX_BPM, MIN_BPM};
+use crate::gui::{Gui, SCREEN_SIZE};
+use crate::panel::PANEL_WIDTH;
+use crate::Display;
+
+/// Height of the strip below the screen.
+pub const PATCH_BAY_HEIGHT: u32 = 60;
+
+/// Sliders snap to this many volts.
+const SLIDER_STEP: f32 = 0.1;
+
+const TOP: i32 = SCREEN_SIZE as i32;
+const WIDTH: i32 = (SCREEN_SIZE + PANEL_WIDTH) as i32;
+const TRACK_LEFT: i32 = 24;
+const TRACK_WIDTH: i32 = 100;
+
+fn slower_button() -> Rectangle {
+    Rectangle::new(Point::new(4, TOP + 4), Size::new(16, 12))
+}
+
+fn faster_button() -> Rectangle {
+    Rectangle::new(Point::new(WIDTH - 20, TOP + 4), Size::new(16, 12))
+}
+
+/// Row of the `input`'s slider, the full width so a click anywhere on it
+/// counts.
+fn slider_row(input: usize) -> Rectangle {
+    Rectangle::new(
+        Point::new(0, TOP + 20 + 13 * input as i32),
+        Size::new(WIDTH as u32, 12),
+    )
+}
+
+fn knob_x(volts: f32) -> i32 {
+    let fraction = (volts - MIN_VOLTS) / (MAX_VOLTS - MIN_VOLTS);
+    TRACK_LEFT + (fraction * TRACK_WIDTH as f32).round() as i32
+}
+
+fn volts_at(x: i32) -> f32 {
+    let fraction = (x - TRACK_LEFT).clamp(0, TRACK_WIDTH) as f32 / TRACK_WIDTH as f32;
+    let volts = MIN_VOLTS + fraction * (MAX_VOLTS - MIN_VOLTS);
+    (volts / SLIDER_STEP).round() * SLIDER_STEP
+}
+
+pub struct PatchBay {
+    pub bpm: u32,
+    /// Volts patched into each CV input
+    pub cv: [f32; NUM_INPUTS],
+    /// Slider following the mouse while its button is held
+    dragging: Option<usize>,
+}
+
+impl Default for PatchBay {
+    fn default() -> Self {
+        PatchBay {
+            bpm: DEFAULT_BPM,
+            cv: [0.0; NUM_INPUTS],
+            dragging: None,
+        }
+    }
+}
+
+impl PatchBay {
+    /// Handle a click anywhere in the window.
+    pub fn press(&mut self, point: Point) {
+        if slower_button().contains(point) {
+            self.bpm = (self.bpm - 1).max(MIN_BPM);
+        } else if faster_button().contains(point) {
+            self.bpm = (self.bpm + 1).min(MAX_BPM);
+        } else if let Some(input) = (0..NUM_INPUTS).find(|i| slider_row(*i).contains(point)) {
+            self.dragging = Some(input);
+            self.drag(point);
+        }
+    }
+
+    pub fn drag(&mut self, point: Point) {
+        if let Some(input) = self.dragging {
+            self.cv[input] = volts_at(point.x);
+        }
+    }
+
+    pub fn release(&mut self) {
+        self.dragging = None;
+    }
+
+    /// Feed the clock and inputs to `gui`.
+    pub fn apply(&self, gui: &mut Gui) {
+        gui.engine.set_bpm(self.bpm);
+        for (input, volts) in self.cv.iter().enumerate() {
+            gui.set_cv(input, *volts);
+        }
+    }
+
+    /// Draw the strip, dimming the sliders of unpatched inputs.
+    pub fn draw(&self, display: &mut Display, jacks: &Jacks) {
+        // The window keeps last frame's values
+        let strip = Rectangle::new(
+            Point::new(0, TOP),
+            Size::new(WIDTH as u32, PATCH_BAY_HEIGHT),
+        );
+        display.fill_solid(&strip, Bgr565::BLACK).ok();
+
+        let frame = PrimitiveStyle::with_stroke(DARK, 1);
+        for (button, label) in [(slower_button(), "-"), (faster_button(), "+")] {
+            RoundedRectangle::new(button, CornerRadii::new(Size::new_equal(3)))
+                .draw_styled(&frame, display)
+                .ok();
+            FONT_10
+                .render_aligned(
+                    label,
+                    button.center(),
+                    VerticalPosition::Center,
+                    HorizontalAlignment::Center,
+                    FontColor::Transparent(TAN),
+                    display,
+                )
+                .ok();
+        }
+        FONT_10
+            .render_aligned(
+                format_args!("Clock {} BPM", self.bpm),
+                Point::new(WIDTH / 2, TOP + 10),
+                VerticalPosition::Center,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(TAN),
+                display,
+            )
+            .ok();
+
+        for (input, volts) in self.cv.iter().enumerate() {
+            let row = slider_row(input);
+            let y = row.center().y;
+            let color = if jacks.input(input) { TAN } else { DARK };
+            FONT_08
+                .render_aligned(
+                    format_args!("In{}", input + 1),
+                    Point::new(4, y),
+                    VerticalPosition::Center,
+                    HorizontalAlignment::Left,
+                    FontColor::Transparent(color),
+                    display,
+                )
+                .ok();
+
+            let track = Line::new(
+                Point::new(TRACK_LEFT, y),
+                Point::new(TRACK_LEFT + TRACK_WIDTH, y),
+            );
+            track.draw_styled(&frame, display).ok();
+            let zero = knob_x(0.0);
+            Line::new(Point::new(zero, y - 2), Point::new(zero, y + 2))
+                .draw_styled(&frame, display)
+                .ok();
+            let knob = Rectangle::new(Point::new(knob_x(*volts) - 1, y - 4), Size::new(3, 9));
+            let knob_color = if self.dragging == Some(input) {
+                BRIGHT
+            } else {
+                BLUE
+            };
+            knob.draw_styled(&PrimitiveStyle::with_fill(knob_color), display)
+                .ok();
+
+            FONT_08
+                .render_aligned(
+                    format_args!("{:+.1}V", volts),
+                    Point::new(WIDTH - 4, y),
+                    VerticalPosition::Center,
+                    HorizontalAlignment::Right,
+                    FontColor::Transparent(color),
+                    display,
+                )
+                .ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_buttons() {
+        let mut patch_bay = PatchBay::default();
+        patch_bay.press(faster_button().center());
+        assert_eq!(patch_bay.bpm, DEFAULT_BPM + 1);
+        for _ in 0..MAX_BPM {
+            patch_bay.press(slower_button().center());
+        }
+        assert_eq!(patch_bay.bpm, MIN_BPM);
+        // On the screen, not the patch bay
+        patch_bay.press(Point::new(10, 10));
+        patch_bay.drag(Point::new(TRACK_LEFT, TOP + 30));
+        assert_eq!(patch_bay.bpm, MIN_BPM);
+        assert_eq!(patch_bay.cv, [0.0; NUM_INPUTS]);
+    }
+
+    #[test]
+    fn sliders() {
+        let mut patch_bay = PatchBay::default();
+        let y = slider_row(1).center().y;
+        patch_bay.press(Point::new(TRACK_LEFT + TRACK_WIDTH * 3 / 4, y));
+        assert_eq!(patch_bay.cv, [0.0, 5.0, 0.0]);
+
+        // Dragging holds on to the slider, even off its row and track
+        patch_bay.drag(Point::new(0, TOP));
+        assert_eq!(patch_bay.cv[1], MIN_VOLTS);
+        patch_bay.drag(Point::new(TRACK_LEFT + 63, TOP));
+        assert!((patch_bay.cv[1] - 2.6).abs() < 1e-4);
+        patch_bay.release();
+        patch_bay.drag(Point::new(TRACK_LEFT, y));
+        assert!((patch_bay.cv[1] - 2.6).abs() < 1e-4);
+
+        let mut gui = Gui::new(None);
+        patch_bay.bpm = 60;
+        patch_bay.apply(&mut gui);
+        gui.run(1);
+        assert!((gui.engine.cv[1] - 2.6).abs() < 0.01);
+        assert!(gui.engine.cv[0].abs() < 0.01);
+    }
+
+    #[test]
+    fn knob_follows_volts() {
+        assert_eq!(knob_x(MIN_VOLTS), TRACK_LEFT);
+        assert_eq!(knob_x(0.0), TRACK_LEFT + TRACK_WIDTH / 2);
+        assert_eq!(volts_at(knob_x(-3.0)), -3.0);
+    }
+}
@@ -0,0 +1,778 @@
+//! Polyphonic MIDI to CV: the notes held on one MIDI channel, or across
+//! an MPE zone's member channels, shared out over up to four voices, each
+//! a pitch and gate pair of linked outputs with optional pressure and
+//! timbre.
+//!
+//! Modes only see the held notes in `MidiState`, so `PolyMidi` compares
+//! them with the notes it has already seen to recover note on and off
+//! events, then hands those to a `VoiceAllocator`.
+
+use core::ops::Range;
+
+use embedded_graphics::{
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, Rectangle, StyledDrawable},
+};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use common::calibration::NUM_OUTPUTS;
+
+use crate::display::{BRIGHT, DARK, FONT_08, FONT_10, FONT_16, TAN};
+use crate::midi::MIDI_CHANNELS;
+use crate::output::{InputState, OutSignal, GATE_VOLTS, SAMPLE_RATE};
+use crate::parameters::{Choice, ConfigParameter, Parameter};
+use crate::quantizer::note_name;
+use crate::Display;
+
+pub const MAX_VOICES: usize = 4;
+
+/// Gate held low when a sounding voice is given a new note, so envelopes
+/// it drives start again.
+const RETRIGGER_SAMPLES: u32 = SAMPLE_RATE / 1000;
+
+// MIDI note at 0V
+const MIDI_C4: u8 = 60;
+const SUSTAIN_PEDAL: usize = 64;
+
+const ASSIGN: [&str; 4] = ["Rotate", "First Free", "Reuse", "Unison"];
+const STEAL: [&str; 5] = ["Oldest", "Newest", "Low Note", "High Note", "No Steal"];
+const SUSTAIN: [&str; 2] = ["Off", "On"];
+const PER_VOICE: [&str; 3] = ["Pitch Gate", "+Pressure", "+Timbre"];
+
+const ZONES: [&str; 3] = ["Off", "Lower", "Upper"];
+const NO_ZONE: usize = 0;
+const LOWER_ZONE: usize = 1;
+const UPPER_ZONE: usize = 2;
+
+// A voice's outputs, in the order they're linked
+const PITCH: usize = 0;
+const GATE: usize = 1;
+const PRESSURE: usize = 2;
+
+/// Pressure and timbre at full scale
+const EXPRESSION_VOLTS: f32 = 5.0;
+const TIMBRE: usize = 74;
+
+/// How a new note picks its voice.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Assign {
+    /// The next free voice after the last one used
+    RoundRobin,
+    LowestFree,
+    /// The voice that last played the same note, else as round robin
+    Reuse,
+    /// Every voice plays the latest note
+    Unison,
+}
+
+/// Which sounding voice a new note takes when none are free.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Steal {
+    Oldest,
+    Newest,
+    LowestNote,
+    HighestNote,
+    /// Drop the new note
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Voice {
+    pub note: u8,
+    /// MIDI channel the note came in on, its own under MPE
+    pub channel: u8,
+    /// Key still down
+    key: bool,
+    /// Key up, but held on by the sustain pedal
+    sustained: bool,
+    /// Samples left with the gate forced low
+    rest: u32,
+    /// Count of notes when this one started, 0 before the first
+    started: u32,
+}
+
+impl Default for Voice {
+    fn default() -> Self {
+        Voice {
+            note: MIDI_C4,
+            channel: 0,
+            key: false,
+            sustained: false,
+            rest: 0,
+            started: 0,
+        }
+    }
+}
+
+impl Voice {
+    pub fn gate(&self) -> bool {
+        self.sounding() && self.rest == 0
+    }
+
+    pub fn volts(&self) -> f32 {
+        (self.note as f32 - MIDI_C4 as f32) / 12.0
+    }
+
+    fn sounding(&self) -> bool {
+        self.key || self.sustained
+    }
+
+    fn start(&mut self, channel: u8, note: u8, started: u32) {
+        self.rest = if self.sounding() {
+            RETRIGGER_SAMPLES
+        } else {
+            0
+        };
+        self.note = note;
+        self.channel = channel;
+        self.key = true;
+        self.sustained = false;
+        self.started = started;
+    }
+
+    fn release(&mut self, pedal: bool) {
+        self.key = false;
+        self.sustained = pedal;
+    }
+}
+
+/// Note on and off events in, the voices playing them out.
+#[derive(Clone)]
+pub struct VoiceAllocator {
+    voices: [Voice; MAX_VOICES],
+    count: usize,
+    pub assign: Assign,
+    pub steal: Steal,
+    /// Channels and keys down, oldest first, for unison to fall back on
+    keys: Vec<(u8, u8)>,
+    pedal: bool,
+    /// Where round robin looks first
+    next: usize,
+    notes: u32,
+}
+
+impl VoiceAllocator {
+    pub fn new(count: usize) -> Self {
+        VoiceAllocator {
+            voices: [Voice::default(); MAX_VOICES],
+            count: count.clamp(1, MAX_VOICES),
+            assign: Assign::RoundRobin,
+            steal: Steal::Oldest,
+            keys: Vec::new(),
+            pedal: false,
+            next: 0,
+            notes: 0,
+        }
+    }
+
+    /// The voices in use.
+    pub fn voices(&self) -> &[Voice] {
+        &self.voices[..self.count]
+    }
+
+    /// Use `count` voices, silencing any dropped.
+    pub fn set_voices(&mut self, count: usize) {
+        let count = count.clamp(1, MAX_VOICES);
+        for voice in &mut self.voices[count..] {
+            *voice = Voice::default();
+        }
+        self.count = count;
+        self.next %= count;
+    }
+
+    pub fn note_on(&mut self, note: u8) {
+        self.channel_note_on(0, note);
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        self.channel_note_off(0, note);
+    }
+
+    /// A note on `channel`, for when notes arrive on several, as with MPE.
+    pub fn channel_note_on(&mut self, channel: u8, note: u8) {
+        self.keys.retain(|key| *key != (channel, note));
+        self.keys.push((channel, note));
+        self.notes += 1;
+        let (notes, count) = (self.notes, self.count);
+        let voices = &mut self.voices[..count];
+
+        let free = |voice: &Voice| !voice.sounding();
+        let next_free = (0..count)
+            .map(|i| (self.next + i) % count)
+            .find(|i| free(&voices[*i]));
+        let chosen = match self.assign {
+            Assign::Unison => {
+                for voice in voices {
+                    voice.start(channel, note, notes);
+                }
+                return;
+            }
+            Assign::RoundRobin => next_free,
+            Assign::LowestFree => voices.iter().position(free),
+            Assign::Reuse => voices
+                .iter()
+                .position(|voice| voice.started > 0 && voice.note == note)
+                .or(next_free),
+        };
+        let stolen = || match self.steal {
+            Steal::Oldest => (0..count).min_by_key(|i| voices[*i].started),
+            Steal::Newest => (0..count).max_by_key(|i| voices[*i].started),
+            Steal::LowestNote => (0..count).min_by_key(|i| voices[*i].note),
+            Steal::HighestNote => (0..count).max_by_key(|i| voices[*i].note),
+            Steal::None => None,
+        };
+        if let Some(i) = chosen.or_else(stolen) {
+            voices[i].start(channel, note, notes);
+            self.next = (i + 1) % count;
+        }
+    }
+
+    pub fn channel_note_off(&mut self, channel: u8, note: u8) {
+        self.keys.retain(|key| *key != (channel, note));
+        let voices = &mut self.voices[..self.count];
+        match (self.assign, self.keys.last()) {
+            // Back to the last key still down, without a new gate
+            (Assign::Unison, Some(&(last_channel, last))) => {
+                for voice in voices.iter_mut().filter(|voice| voice.key) {
+                    voice.note = last;
+                    voice.channel = last_channel;
+                }
+            }
+            (Assign::Unison, None) => {
+                for voice in voices.iter_mut().filter(|voice| voice.key) {
+                    voice.release(self.pedal);
+                }
+            }
+            _ => {
+                for voice in voices
+                    .iter_mut()
+                    .filter(|voice| voice.key && voice.note == note && voice.channel == channel)
+                {
+                    voice.release(self.pedal);
+                }
+            }
+        }
+    }
+
+    /// Follow the sustain pedal, letting go of sustained voices as it's
+    /// lifted.
+    pub fn pedal(&mut self, down: bool) {
+        self.pedal = down;
+        if !down {
+            for voice in &mut self.voices {
+                voice.sustained = false;
+            }
+        }
+    }
+
+    /// Advance a sample.
+    pub fn tick(&mut self) {
+        for voice in &mut self.voices {
+            voice.rest = voice.rest.saturating_sub(1);
+        }
+    }
+}
+
+/// What each voice follows besides its note, from channel wide and per
+/// note controllers.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Expression {
+    /// Volts added to the pitch
+    bend: f32,
+    /// 0 to 1
+    pressure: f32,
+    /// 0 to 1
+    timbre: f32,
+}
+
+/// Pitch and gate pairs for up to four voices, the first on its own
+/// channel and the rest on the channels linked after it. Each voice can
+/// add pressure and timbre outputs after its gate.
+///
+/// With an MPE zone set, notes come in on the zone's member channels,
+/// each bending, pressing and shaping its own voice, while the zone's
+/// master channel bends every voice and holds the sustain pedal.
+#[derive(Clone)]
+pub struct PolyMidi {
+    channel: Choice,
+    voices: Parameter<i32>,
+    assign: Choice,
+    steal: Choice,
+    sustain: Choice,
+    /// Semitones at full pitch bend
+    bend_range: Parameter<i32>,
+    per_voice: Choice,
+    zone: Choice,
+    /// Member channels in the MPE zone, counted from the master
+    members: Parameter<i32>,
+    /// Semitones at full per note bend
+    note_bend_range: Parameter<i32>,
+    /// Outputs from its channel to the last, see `OutSignal::fit`
+    outputs: usize,
+    allocator: VoiceAllocator,
+    expression: [Expression; MAX_VOICES],
+    /// Channels and notes already seen held, to tell new notes and
+    /// released ones
+    down: Vec<(u8, u8)>,
+}
+
+impl Default for PolyMidi {
+    fn default() -> Self {
+        PolyMidi {
+            channel: Choice::new(&MIDI_CHANNELS, 1),
+            voices: Parameter::new_saturating(1, MAX_VOICES as i32, 1, MAX_VOICES as i32),
+            assign: Choice::new(&ASSIGN, 0),
+            steal: Choice::new(&STEAL, 0),
+            sustain: Choice::new(&SUSTAIN, 1),
+            bend_range: Parameter::new_saturating(0, 24, 1, 2),
+            per_voice: Choice::new(&PER_VOICE, 0),
+            zone: Choice::new(&ZONES, NO_ZONE),
+            members: Parameter::new_saturating(1, 15, 1, 15),
+            note_bend_range: Parameter::new_saturating(1, 96, 1, 48),
+            outputs: NUM_OUTPUTS,
+            allocator: VoiceAllocator::new(MAX_VOICES),
+            expression: [Expression::default(); MAX_VOICES],
+            down: Vec::new(),
+        }
+    }
+}
+
+impl PolyMidi {
+    fn configure(&mut self) {
+        self.allocator.set_voices(self.num_voices());
+        self.allocator.assign = match *self.assign {
+            0 => Assign::RoundRobin,
+            1 => Assign::LowestFree,
+            2 => Assign::Reuse,
+            _ => Assign::Unison,
+        };
+        self.allocator.steal = match *self.steal {
+            0 => Steal::Oldest,
+            1 => Steal::Newest,
+            2 => Steal::LowestNote,
+            3 => Steal::HighestNote,
+            _ => Steal::None,
+        };
+    }
+
+    fn is_mpe(&self) -> bool {
+        *self.zone != NO_ZONE
+    }
+
+    /// The channel that bends every voice and holds the pedal, and the
+    /// channels notes are played on.
+    fn channels(&self) -> (Option<u8>, Range<u8>) {
+        let members = *self.members as u8;
+        match (*self.zone, *self.channel) {
+            (LOWER_ZONE, _) => (Some(0), 1..members + 1),
+            (UPPER_ZONE, _) => (Some(15), 15 - members..15),
+            (_, 0) => (None, 0..0),
+            (_, channel) => (Some(channel as u8 - 1), channel as u8 - 1..channel as u8),
+        }
+    }
+
+    fn outputs_per_voice(&self) -> usize {
+        2 + *self.per_voice
+    }
+
+    /// Voices played, as many as asked for that have outputs left for them.
+    fn num_voices(&self) -> usize {
+        (self.outputs / self.outputs_per_voice()).clamp(1, *self.voices as usize)
+    }
+
+    fn voice_level(&self, i: usize, output: usize) -> f32 {
+        let voice = &self.allocator.voices()[i];
+        let expression = &self.expression[i];
+        match output {
+            PITCH => voice.volts() + expression.bend,
+            GATE if voice.gate() => GATE_VOLTS,
+            GATE => 0.0,
+            PRESSURE => expression.pressure * EXPRESSION_VOLTS,
+            _ => expression.timbre * EXPRESSION_VOLTS,
+        }
+    }
+}
+
+/// Volts of pitch for a bend of -8192 to 8191 when full bend is `range`
+/// semitones.
+pub fn bend_volts(bend: i16, range: i32) -> f32 {
+    bend as f32 / 8192.0 * range as f32 / 12.0
+}
+
+fn draw_note(disp: &mut Display, window: Rectangle, voice: &Voice) {
+    let name = match voice.started {
+        0 => "-".into(),
+        _ => note_name(voice.note as i32 - MIDI_C4 as i32),
+    };
+    FONT_08
+        .render_aligned(
+            name.as_str(),
+            window.center(),
+            VerticalPosition::Center,
+            HorizontalAlignment::Center,
+            FontColor::Transparent(if voice.gate() { BRIGHT } else { DARK }),
+            disp,
+        )
+        .ok();
+}
+
+/// A bar across `window` lit from the left by `amount`, 0 to 1.
+fn draw_amount(disp: &mut Display, window: Rectangle, amount: f32) {
+    let center = window.center().y;
+    let start = Point::new(window.top_left.x + 2, center);
+    let end = Point::new(window.top_left.x + 28, center);
+    Line::new(start, end)
+        .draw_styled(&PrimitiveStyle::with_stroke(DARK, 3), disp)
+        .ok();
+    let reach = (26.0 * amount) as i32;
+    if reach > 0 {
+        Line::new(start, start + Point::new(reach, 0))
+            .draw_styled(&PrimitiveStyle::with_stroke(BRIGHT, 3), disp)
+            .ok();
+    }
+}
+
+impl OutSignal for PolyMidi {
+    fn num_parameters(&self) -> usize {
+        10
+    }
+
+    fn parameter(&mut self, param: usize) -> Option<(&'static str, &mut dyn ConfigParameter)> {
+        match param {
+            0 => Some(("MIDI In", &mut self.channel)),
+            1 => Some(("Voices", &mut self.voices)),
+            2 => Some(("Assign", &mut self.assign)),
+            3 => Some(("Steal", &mut self.steal)),
+            4 => Some(("Sustain", &mut self.sustain)),
+            5 => Some(("Bend Range", &mut self.bend_range)),
+            6 => Some(("Per Voice", &mut self.per_voice)),
+            7 => Some(("MPE", &mut self.zone)),
+            8 => Some(("Members", &mut self.members)),
+            9 => Some(("Note Bend", &mut self.note_bend_range)),
+            _ => None,
+        }
+    }
+
+    fn width(&self) -> usize {
+        self.outputs_per_voice() * self.num_voices()
+    }
+
+    fn fit(&mut self, outputs: usize) {
+        self.outputs = outputs;
+    }
+
+    fn generate(&mut self, input: &InputState) -> f32 {
+        self.configure();
+        let (master, members) = self.channels();
+        let master = master.map(|channel| input.midi.channel(channel));
+        let pedal = master
+            .is_some_and(|state| *self.sustain == 1 && state.controllers[SUSTAIN_PEDAL] >= 64);
+        self.allocator.pedal(pedal);
+        let held = |channel: u8, note: u8| {
+            members.contains(&channel)
+                && input
+                    .midi
+                    .channel(channel)
+                    .notes
+                    .as_slice()
+                    .iter()
+                    .any(|held| held.note == note)
+        };
+
+        // Releases first, so their voices are free for new notes
+        let allocator = &mut self.allocator;
+        self.down.retain(|&(channel, note)| {
+            let still = held(channel, note);
+            if !still {
+                allocator.channel_note_off(channel, note);
+            }
+            still
+        });
+        for channel in members {
+            for held in input.midi.channel(channel).notes.as_slice() {
+                if !self.down.contains(&(channel, held.note)) {
+                    self.down.push((channel, held.note));
+                    self.allocator.channel_note_on(channel, held.note);
+                }
+            }
+        }
+        self.allocator.tick();
+
+        let bend = master.map_or(0.0, |state| bend_volts(state.pitch_bend, *self.bend_range));
+        let mpe = self.is_mpe();
+        for (voice, expression) in self.allocator.voices().iter().zip(&mut self.expression) {
+            let state = input.midi.channel(voice.channel);
+            *expression = if mpe {
+                Expression {
+                    bend: bend + bend_volts(state.pitch_bend, *self.note_bend_range),
+                    pressure: state.pressure as f32 / 127.0,
+                    timbre: state.controllers[TIMBRE] as f32 / 127.0,
+                }
+            } else {
+                Expression {
+                    bend,
+                    pressure: state.key_pressure[voice.note as usize] as f32 / 127.0,
+                    timbre: state.controllers[TIMBRE] as f32 / 127.0,
+                }
+            };
+        }
+
+        self.voice_level(0, PITCH)
+    }
+
+    fn gate(&self) -> bool {
+        self.allocator.voices()[0].gate()
+    }
+
+    fn linked(&self, link: usize) -> f32 {
+        let per_voice = self.outputs_per_voice();
+        self.voice_level(link / per_voice, link % per_voice)
+    }
+
+    fn draw_output(&self, disp: &mut Display, window: Rectangle) {
+        draw_note(disp, window, &self.allocator.voices()[0]);
+    }
+
+    fn draw_linked(&self, link: usize, disp: &mut Display, window: Rectangle) {
+        let per_voice = self.outputs_per_voice();
+        let i = link / per_voice;
+        let voice = &self.allocator.voices()[i];
+        match link % per_voice {
+            PITCH => draw_note(disp, window, voice),
+            GATE => draw_amount(disp, window, if voice.gate() { 1.0 } else { 0.0 }),
+            PRESSURE => draw_amount(disp, window, self.expression[i].pressure),
+            _ => draw_amount(disp, window, self.expression[i].timbre),
+        }
+    }
+
+    fn draw_configure(&self, disp: &mut Display, window: Rectangle) {
+        let anchor = window.top_left + Point::new(5, 5);
+        FONT_16
+            .render_aligned(
+                "Poly MIDI",
+                anchor,
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(TAN),
+                disp,
+            )
+            .ok();
+        let input = if self.is_mpe() {
+            format!("MPE {}", self.zone.label())
+        } else {
+            self.channel.label().into()
+        };
+        FONT_10
+            .render_aligned(
+                format_args!("{} {}", input, self.assign.label()),
+                anchor + Point::new(0, 26),
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(BRIGHT),
+                disp,
+            )
+            .ok();
+
+        let frame = PrimitiveStyle::with_stroke(DARK, 1);
+        for (i, voice) in self.allocator.voices().iter().enumerate() {
+            let cell = Rectangle::new(anchor + Point::new(22 * i as i32, 44), Size::new(20, 14));
+            cell.draw_styled(&frame, disp).ok();
+            draw_note(disp, cell, voice);
+        }
+        if self.num_voices() < *self.voices as usize {
+            FONT_08
+                .render_aligned(
+                    format_args!("Only {} voices fit", self.num_voices()),
+                    anchor + Point::new(0, 62),
+                    VerticalPosition::Top,
+                    HorizontalAlignment::Left,
+                    FontColor::Transparent(DARK),
+                    disp,
+                )
+                .ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes(allocator: &VoiceAllocator) -> Vec<Option<u8>> {
+        allocator
+            .voices()
+            .iter()
+            .map(|voice| voice.sounding().then_some(voice.note))
+            .collect()
+    }
+
+    fn allocator(assign: Assign, steal: Steal) -> VoiceAllocator {
+        let mut allocator = VoiceAllocator::new(MAX_VOICES);
+        allocator.assign = assign;
+        allocator.steal = steal;
+        allocator
+    }
+
+    #[test]
+    fn round_robin_and_lowest_free() {
+        let mut rotate = allocator(Assign::RoundRobin, Steal::Oldest);
+        let mut lowest = allocator(Assign::LowestFree, Steal::Oldest);
+        for allocator in [&mut rotate, &mut lowest] {
+            allocator.note_on(60);
+            allocator.note_on(62);
+            allocator.note_off(60);
+            allocator.note_on(64);
+        }
+        assert_eq!(notes(&rotate), [None, Some(62), Some(64), None]);
+        assert_eq!(notes(&lowest), [Some(64), Some(62), None, None]);
+    }
+
+    #[test]
+    fn reuses_the_same_note() {
+        let mut allocator = allocator(Assign::Reuse, Steal::Oldest);
+        allocator.note_on(60);
+        allocator.note_on(62);
+        allocator.note_off(60);
+        allocator.note_on(64);
+        allocator.note_on(60);
+        assert_eq!(notes(&allocator), [Some(60), Some(62), Some(64), None]);
+        // Pressed again while sustained, the same voice starts over
+        allocator.pedal(true);
+        allocator.note_off(62);
+        allocator.note_on(62);
+        assert_eq!(notes(&allocator), [Some(60), Some(62), Some(64), None]);
+        assert!(!allocator.voices()[1].gate());
+    }
+
+    #[test]
+    fn steals() {
+        let expect = [
+            (Steal::Oldest, [Some(69), Some(62), Some(67), Some(64)]),
+            (Steal::Newest, [Some(65), Some(62), Some(67), Some(69)]),
+            (Steal::LowestNote, [Some(65), Some(69), Some(67), Some(64)]),
+            (Steal::HighestNote, [Some(65), Some(62), Some(69), Some(64)]),
+            (Steal::None, [Some(65), Some(62), Some(67), Some(64)]),
+        ];
+        for (steal, voices) in expect {
+            let mut allocator = allocator(Assign::RoundRobin, steal);
+            for note in [65, 62, 67, 64, 69] {
+                allocator.note_on(note);
+            }
+            assert_eq!(notes(&allocator), voices, "{:?}", steal);
+        }
+    }
+
+    #[test]
+    fn sustain_pedal() {
+        let mut allocator = allocator(Assign::RoundRobin, Steal::Oldest);
+        allocator.note_on(60);
+        allocator.pedal(true);
+        allocator.note_off(60);
+        allocator.note_on(64);
+        allocator.note_off(64);
+        assert_eq!(notes(&allocator), [Some(60), Some(64), None, None]);
+        assert!(allocator.voices()[0].gate());
+        allocator.pedal(false);
+        assert_eq!(notes(&allocator), [None; 4]);
+    }
+
+    #[test]
+    fn unison() {
+        let mut allocator = allocator(Assign::Unison, Steal::Oldest);
+        allocator.set_voices(3);
+        allocator.note_on(60);
+        allocator.note_on(67);
+        assert_eq!(notes(&allocator), [Some(67); 3]);
+        // Falls back to the key still down, keeping the gate
+        for _ in 0..RETRIGGER_SAMPLES {
+            allocator.tick();
+        }
+        allocator.note_off(67);
+        assert_eq!(notes(&allocator), [Some(60); 3]);
+        assert!(allocator.voices().iter().all(Voice::gate));
+        allocator.note_off(60);
+        assert_eq!(notes(&allocator), [None; 3]);
+    }
+
+    #[test]
+    fn retriggers_stolen_voices() {
+        let mut allocator = allocator(Assign::RoundRobin, Steal::Oldest);
+        allocator.set_voices(1);
+        allocator.note_on(60);
+        assert!(allocator.voices()[0].gate());
+        allocator.note_on(62);
+        assert!(!allocator.voices()[0].gate());
+        for _ in 0..RETRIGGER_SAMPLES {
+            allocator.tick();
+        }
+        assert!(allocator.voices()[0].gate());
+        assert_eq!(allocator.voices()[0].volts(), 2.0 / 12.0);
+    }
+
+    #[test]
+    fn same_note_on_two_channels() {
+        let mut allocator = allocator(Assign::RoundRobin, Steal::Oldest);
+        allocator.channel_note_on(1, 60);
+        allocator.channel_note_on(2, 60);
+        assert_eq!(notes(&allocator), [Some(60), Some(60), None, None]);
+        allocator.channel_note_off(2, 60);
+        assert_eq!(notes(&allocator), [Some(60), None, None, None]);
+        assert_eq!(allocator.voices()[0].channel, 1);
+    }
+
+    #[test]
+    fn voices_fit_the_outputs_left() {
+        use crate::engine::Engine;
+        use common::midi::MidiMessage;
+
+        // Four voices of pitch and gate from channel 5 only leave room for two
+        let mut engine = Engine::default();
+        engine.set_mode(4, PolyMidi::default().into());
+        for note in [60, 64, 67] {
+            engine.receive(&MidiMessage::NoteOn {
+                channel: 0,
+                note,
+                velocity: 100,
+            });
+            engine.tick();
+        }
+        engine.run(SAMPLE_RATE / 100);
+        assert_eq!(engine.outputs[4].width(), 4);
+        // The third note took the oldest voice rather than one with no outputs
+        let levels = engine.levels();
+        assert!((levels[4] - 7.0 / 12.0).abs() < 1e-4);
+        assert!((levels[6] - 4.0 / 12.0).abs() < 1e-4);
+        assert_eq!([levels[5], levels[7]], [GATE_VOLTS; 2]);
+    }
+
+    #[test]
+    fn outputs_per_voice_limit_the_voices() {
+        let mut poly = PolyMidi::default();
+        for _ in 0..2 {
+            poly.per_voice.next();
+        }
+        // Four outputs each, so two voices across every output
+        assert_eq!(poly.num_voices(), 2);
+        assert_eq!(poly.width(), 8);
+        poly.fit(7);
+        assert_eq!(poly.num_voices(), 1);
+        assert_eq!(poly.width(), 4);
+        // A voice's pitch at least, whatever is left
+        poly.fit(1);
+        assert_eq!(poly.num_voices(), 1);
+        // Fewer asked for than fit
+        poly.fit(NUM_OUTPUTS);
+        poly.voices.prev();
+        poly.voices.prev();
+        poly.voices.prev();
+        assert_eq!(poly.num_voices(), 1);
+    }
+
+    #[test]
+    fn bend_range() {
+        assert_eq!(bend_volts(0, 2), 0.0);
+        assert_eq!(bend_volts(-8192, 2), -2.0 / 12.0);
+        assert_eq!(bend_volts(4096, 24), 1.0);
+        assert_eq!(bend_volts(-8192, 0), 0.0);
+    }
+}
@@ -0,0 +1,470 @@
+use embedded_graphics::{
+    pixelcolor::Bgr565,
+    prelude::*,
+    primitives::{PrimitiveStyleBuilder, Rectangle, StyledDrawable},
+};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use common::calibration::NUM_INPUTS;
+
+use crate::display::{BG, BLUE, BRIGHT, DARK, FONT_08, FONT_10, FONT_16, TAN};
+use crate::output::{InputState, OutSignal, GATE_VOLTS, SAMPLE_RATE};
+use crate::parameters::{Choice, ConfigParameter, Parameter};
+use crate::Display;
+
+pub const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+const SOURCES: [&str; 11] = [
+    "CV 1", "CV 2", "CV 3", "Out 1", "Out 2", "Out 3", "Out 4", "Out 5", "Out 6", "Out 7", "Out 8",
+];
+
+const SCALES: [&str; 11] = [
+    "Chromatic",
+    "Major",
+    "Minor",
+    "Dorian",
+    "Phrygian",
+    "Lydian",
+    "Mixolydian",
+    "Locrian",
+    "Maj Pent",
+    "Min Pent",
+    "User",
+];
+const USER_SCALE: usize = SCALES.len() - 1;
+
+// Bit n set when the note n semitones above the root is in the scale
+const SCALE_MASKS: [u16; USER_SCALE] = [
+    0xFFF,
+    mask(&[0, 2, 4, 5, 7, 9, 11]),
+    mask(&[0, 2, 3, 5, 7, 8, 10]),
+    mask(&[0, 2, 3, 5, 7, 9, 10]),
+    mask(&[0, 1, 3, 5, 7, 8, 10]),
+    mask(&[0, 2, 4, 6, 7, 9, 11]),
+    mask(&[0, 2, 4, 5, 7, 9, 10]),
+    mask(&[0, 1, 3, 5, 6, 8, 10]),
+    mask(&[0, 2, 4, 7, 9]),
+    mask(&[0, 3, 5, 7, 10]),
+];
+
+const OUTPUTS: [&str; 2] = ["Pitch", "Trigger"];
+const TRIGGER_SAMPLES: u32 = SAMPLE_RATE / 200;
+
+/// How much closer, in semitones, a new note has to be before leaving the current one.
+const HYSTERESIS: f32 = 0.1;
+
+const fn mask(notes: &[u8]) -> u16 {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < notes.len() {
+        mask |= 1 << notes[i];
+        i += 1;
+    }
+    mask
+}
+
+/// Move a root-relative note mask onto absolute pitch classes.
+fn rotate(mask: u16, root: usize) -> u16 {
+    ((mask << root) | (mask >> (12 - root))) & 0xFFF
+}
+
+/// Snap `semitones` to the nearest note in `mask`, sticking with `last` when close.
+fn quantize(semitones: f32, mask: u16, last: i32) -> i32 {
+    let allowed = |note: i32| mask & (1 << note.rem_euclid(12)) != 0;
+    if mask == 0 {
+        return last;
+    }
+
+    let rounded = semitones.round() as i32;
+    let mut nearest = rounded;
+    for distance in 0..=6 {
+        let candidates = [rounded - distance, rounded + distance];
+        if let Some(note) = candidates
+            .into_iter()
+            .filter(|note| allowed(*note))
+            .min_by(|a, b| {
+                let (da, db) = ((semitones - *a as f32).abs(), (semitones - *b as f32).abs());
+                da.total_cmp(&db)
+            })
+        {
+            nearest = note;
+            break;
+        }
+    }
+
+    let stay = (semitones - last as f32).abs() <= (semitones - nearest as f32).abs() + HYSTERESIS;
+    if allowed(last) && stay {
+        last
+    } else {
+        nearest
+    }
+}
+
+pub fn note_name(note: i32) -> String {
+    // 0V is C4
+    format!(
+        "{}{}",
+        NOTE_NAMES[note.rem_euclid(12) as usize],
+        4 + note.div_euclid(12)
+    )
+}
+
+/// The user scale, edited one key at a time on a piano roll.
+#[derive(Clone)]
+pub struct NoteMask {
+    mask: u16,
+    // Copies of the quantizer's scale and root, see `Quantizer::sync`
+    scale: usize,
+    root: usize,
+    cursor: usize,
+    to_user: bool,
+}
+
+impl NoteMask {
+    fn relative(&self) -> u16 {
+        match self.scale {
+            USER_SCALE => self.mask,
+            preset => SCALE_MASKS[preset],
+        }
+    }
+}
+
+impl ConfigParameter for NoteMask {
+    fn next(&mut self) {
+        self.cursor = (self.cursor + 1) % 12;
+    }
+
+    fn prev(&mut self) {
+        self.cursor = (self.cursor + 11) % 12;
+    }
+
+    fn push(&mut self) -> bool {
+        // Editing a preset starts a user scale from it
+        if self.scale != USER_SCALE {
+            self.mask = self.relative();
+            self.scale = USER_SCALE;
+            self.to_user = true;
+        }
+        self.mask ^= 1 << ((self.cursor + 12 - self.root) % 12);
+        true
+    }
+
+    fn draw_edit(&self, disp: &mut Display, window: Rectangle) {
+        draw_piano_roll(
+            disp,
+            window,
+            rotate(self.relative(), self.root),
+            Some(self.cursor),
+        );
+    }
+}
+
+#[derive(Clone)]
+pub struct Quantizer {
+    source: Choice,
+    scale: Choice,
+    notes: NoteMask,
+    root: Choice,
+    transpose: Parameter<i32>,
+    output: Choice,
+    note: i32,
+    trigger: u32,
+}
+
+impl Default for Quantizer {
+    fn default() -> Self {
+        Quantizer {
+            source: Choice::new(&SOURCES, 0),
+            scale: Choice::new(&SCALES, 0),
+            notes: NoteMask {
+                mask: SCALE_MASKS[0],
+                scale: 0,
+                root: 0,
+                cursor: 0,
+                to_user: false,
+            },
+            root: Choice::new(&NOTE_NAMES, 0),
+            transpose: Parameter::new_saturating(-24, 24, 1, 0),
+            output: Choice::new(&OUTPUTS, 0),
+            note: 0,
+            trigger: 0,
+        }
+    }
+}
+
+impl Quantizer {
+    /// Absolute pitch classes currently allowed.
+    fn mask(&self) -> u16 {
+        let relative = match *self.scale {
+            USER_SCALE => self.notes.mask,
+            preset => SCALE_MASKS[preset],
+        };
+        rotate(relative, *self.root)
+    }
+
+    /// Reconcile the note editor with the scale and root parameters.
+    fn sync(&mut self) {
+        if self.notes.to_user {
+            self.scale.set(USER_SCALE);
+            self.notes.to_user = false;
+        }
+        self.notes.scale = *self.scale;
+        self.notes.root = *self.root;
+    }
+}
+
+impl OutSignal for Quantizer {
+    fn num_parameters(&self) -> usize {
+        6
+    }
+
+    fn parameter(&mut self, param: usize) -> Option<(&'static str, &mut dyn ConfigParameter)> {
+        self.sync();
+        match param {
+            0 => Some(("Source", &mut self.source)),
+            1 => Some(("Scale", &mut self.scale)),
+            2 => Some(("Notes", &mut self.notes)),
+            3 => Some(("Root", &mut self.root)),
+            4 => Some(("Transpose", &mut self.transpose)),
+            5 => Some(("Output", &mut self.output)),
+            _ => None,
+        }
+    }
+
+    fn generate(&mut self, input: &InputState) -> f32 {
+        self.sync();
+        let volts = match *self.source {
+            cv if cv < NUM_INPUTS => input.cv[cv],
+            out => input.outputs[out - NUM_INPUTS],
+        };
+
+        let note = quantize(volts * 12.0, self.mask(), self.note);
+        if note != self.note {
+            self.note = note;
+            self.trigger = TRIGGER_SAMPLES;
+        }
+
+        let triggered = self.trigger > 0;
+        self.trigger = self.trigger.saturating_sub(1);
+        match *self.output {
+            0 => (self.note + *self.transpose) as f32 / 12.0,
+            _ if triggered => GATE_VOLTS,
+            _ => 0.0,
+        }
+    }
+
+    fn draw_output(&self, disp: &mut Display, window: Rectangle) {
+        FONT_08
+            .render_aligned(
+                note_name(self.note + *self.transpose).as_str(),
+                window.center(),
+                VerticalPosition::Center,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(BRIGHT),
+                disp,
+            )
+            .ok();
+    }
+
+    fn draw_configure(&self, disp: &mut Display, window: Rectangle) {
+        let anchor = window.top_left + Point::new(5, 5);
+        FONT_16
+            .render_aligned(
+                "Quantizer",
+                anchor,
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(TAN),
+                disp,
+            )
+            .ok();
+
+        FONT_10
+            .render_aligned(
+                format_args!("{} {}", self.root.label(), self.scale.label()),
+                anchor + Point::new(0, 26),
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(BRIGHT),
+                disp,
+            )
+            .ok();
+
+        let roll = Rectangle::new(
+            window.top_left + Point::new(5, 44),
+            Size::new(window.size.width - 10, 32),
+        );
+        draw_piano_roll(disp, roll, self.mask(), None);
+    }
+}
+
+/// One octave of keys, with the notes in `mask` lit and the `cursor` key outlined.
+fn draw_piano_roll(disp: &mut Display, window: Rectangle, mask: u16, cursor: Option<usize>) {
+    const WHITE_KEYS: [usize; 7] = [0, 2, 4, 5, 7, 9, 11];
+    // Black keys and the white key they sit to the right of
+    const BLACK_KEYS: [(usize, i32); 5] = [(1, 0), (3, 1), (6, 3), (8, 4), (10, 5)];
+
+    let white_width = window.size.width as i32 / 7;
+    let black_width = white_width * 2 / 3;
+    let black_height = window.size.height * 3 / 5;
+    let lit = |note: usize| mask & (1 << note) != 0;
+    let style = |note: usize, on: Bgr565, off: Bgr565| {
+        let mut style = PrimitiveStyleBuilder::new()
+            .fill_color(if lit(note) { on } else { off })
+            .stroke_color(DARK)
+            .stroke_width(1);
+        if cursor == Some(note) {
+            style = style.stroke_color(BLUE).stroke_width(2);
+        }
+        style.build()
+    };
+
+    for (i, note) in WHITE_KEYS.into_iter().enumerate() {
+        let key = Rectangle::new(
+            window.top_left + Point::new(i as i32 * white_width, 0),
+            Size::new(white_width as u32, window.size.height),
+        );
+        key.draw_styled(&style(note, BRIGHT, BG), disp).ok();
+    }
+
+    for (note, after) in BLACK_KEYS {
+        let key = Rectangle::new(
+            window.top_left + Point::new((after + 1) * white_width - black_width / 2, 0),
+            Size::new(black_width as u32, black_height),
+        );
+        key.draw_styled(&style(note, TAN, DARK), disp).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::midi::MidiState;
+
+    /// Quantize with no previous note anywhere near.
+    fn fresh(semitones: f32, mask: u16) -> i32 {
+        quantize(semitones, mask, 1000)
+    }
+
+    #[test]
+    fn each_scale_from_c() {
+        // Every semitone of the octave, ties between two notes go down
+        let expect: [[i32; 12]; USER_SCALE] = [
+            [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            [0, 0, 2, 2, 4, 5, 5, 7, 7, 9, 9, 11],
+            [0, 0, 2, 3, 3, 5, 5, 7, 8, 8, 10, 10],
+            [0, 0, 2, 3, 3, 5, 5, 7, 7, 9, 10, 10],
+            [0, 1, 1, 3, 3, 5, 5, 7, 8, 8, 10, 10],
+            [0, 0, 2, 2, 4, 4, 6, 7, 7, 9, 9, 11],
+            [0, 0, 2, 2, 4, 5, 5, 7, 7, 9, 10, 10],
+            [0, 1, 1, 3, 3, 5, 6, 6, 8, 8, 10, 10],
+            [0, 0, 2, 2, 4, 4, 7, 7, 7, 9, 9, 12],
+            [0, 0, 3, 3, 3, 5, 5, 7, 7, 10, 10, 10],
+        ];
+        for (scale, notes) in expect.iter().enumerate() {
+            let got: Vec<i32> = (0..12)
+                .map(|note| fresh(note as f32, SCALE_MASKS[scale]))
+                .collect();
+            assert_eq!(got, notes, "{}", SCALES[scale]);
+        }
+    }
+
+    #[test]
+    fn modes_are_rotations_of_major() {
+        let major = SCALE_MASKS[1];
+        // Each mode's root, in semitones above the major scale's
+        let expect = [
+            ("Minor", 9),
+            ("Dorian", 2),
+            ("Phrygian", 4),
+            ("Lydian", 5),
+            ("Mixolydian", 7),
+            ("Locrian", 11),
+        ];
+        for (name, root) in expect {
+            let scale = SCALES.iter().position(|s| *s == name).unwrap();
+            assert_eq!(rotate(major, 12 - root), SCALE_MASKS[scale], "{}", name);
+        }
+        assert_eq!(rotate(SCALE_MASKS[8], 12 - 9), SCALE_MASKS[9]);
+    }
+
+    #[test]
+    fn root_moves_the_scale() {
+        let major = SCALE_MASKS[1];
+        assert_eq!(rotate(major, 0), major);
+        // D major has F# and C#
+        let d_major = rotate(major, 2);
+        assert_eq!(d_major, mask(&[1, 2, 4, 6, 7, 9, 11]));
+        assert_eq!(fresh(5.0, d_major), 4);
+        assert_eq!(fresh(1.0, d_major), 1);
+        // Notes wrap from B round to C
+        assert_eq!(rotate(mask(&[11]), 1), mask(&[0]));
+    }
+
+    #[test]
+    fn negative_volts() {
+        let major = SCALE_MASKS[1];
+        assert_eq!(fresh(-12.0, 0xFFF), -12);
+        assert_eq!(fresh(-0.4, 0xFFF), 0);
+        assert_eq!(fresh(-0.6, 0xFFF), -1);
+        // B below middle C, then a tie between A and B
+        assert_eq!(fresh(-1.0, major), -1);
+        assert_eq!(fresh(-2.0, major), -3);
+        assert_eq!(fresh(-13.0, major), -13);
+        assert_eq!(fresh(-30.0, major), -31);
+        assert_eq!(note_name(-1), "B3");
+        assert_eq!(note_name(-12), "C3");
+    }
+
+    #[test]
+    fn octave_boundaries() {
+        let major = SCALE_MASKS[1];
+        let min_pent = SCALE_MASKS[9];
+        assert_eq!(fresh(11.6, major), 12);
+        assert_eq!(fresh(12.4, major), 12);
+        assert_eq!(fresh(11.4, min_pent), 12);
+        assert_eq!(fresh(10.6, min_pent), 10);
+        assert_eq!(fresh(-12.4, min_pent), -12);
+        assert_eq!(fresh(24.0, min_pent), 24);
+        assert_eq!(note_name(12), "C5");
+    }
+
+    #[test]
+    fn hysteresis_and_empty_scale() {
+        assert_eq!(quantize(0.55, 0xFFF, 0), 0);
+        assert_eq!(quantize(0.7, 0xFFF, 0), 1);
+        assert_eq!(quantize(0.45, 0xFFF, 1), 1);
+        // A note that's left the scale isn't held onto
+        assert_eq!(quantize(0.55, SCALE_MASKS[1], 1), 0);
+        // No notes at all, hold the last
+        assert_eq!(quantize(7.0, 0, 3), 3);
+    }
+
+    #[test]
+    fn triggers_only_on_a_new_note() {
+        let midi = MidiState::new();
+        let mut quantizer = Quantizer::default();
+        quantizer.scale.set(1);
+        quantizer.output.set(1);
+
+        let mut run = |volts: f32| {
+            let input = InputState {
+                cv: [volts, 0.0, 0.0],
+                ..InputState::at(0, &midi)
+            };
+            (0..2 * TRIGGER_SAMPLES)
+                .map(|_| quantizer.generate(&input))
+                .filter(|volts| *volts > 0.0)
+                .count() as u32
+        };
+        assert_eq!(run(0.0), 0);
+        // C# is between C and D, so stays on C
+        assert_eq!(run(1.0 / 12.0), 0);
+        assert_eq!(run(2.0 / 12.0), TRIGGER_SAMPLES);
+        assert_eq!(run(2.05 / 12.0), 0);
+        assert_eq!(run(-1.0), TRIGGER_SAMPLES);
+        assert_eq!(run(-1.0), 0);
+    }
+}
@@ -0,0 +1,320 @@
+//! Offline rendering of the output engine from a scripted timeline, so
+//! modes can be checked against golden files without hardware.
+//!
+//! A timeline is plain text, one event per line, `#` starting a comment:
+//!
+//! ```text
+//! 0    bpm 140
+//! 0    mode 1 ClockOut
+//! 0    param 1 +2 Division
+//! 250  cv 1 2.5
+//! 500  note_on 1 60 100
+//! 750  note_off 1 60
+//! 2000 end
+//! ```
+//!
+//! Times are in milliseconds. Outputs, CV inputs and MIDI channels count
+//! from 1 like the panel does. `param` steps a parameter, found by the
+//! name the configure screen shows, by as many encoder clicks. Events at
+//! the same time apply in the order written, before that sample runs.
+//! Tempos are held to the patch bay's range, 20 to 300 BPM.
+
+use std::fmt;
+use std::io::{self, Write};
+
+use common::calibration::{NUM_INPUTS, NUM_OUTPUTS};
+use common::midi::{MidiMessage, NUM_CHANNELS};
+
+use crate::engine::Engine;
+use crate::modes;
+use crate::output::SAMPLE_RATE;
+
+/// Volts at WAV full scale, the DAC's range.
+const WAV_FULL_SCALE: f32 = 10.0;
+
+#[derive(Debug, PartialEq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Bpm(u32),
+    Mode(usize, &'static str),
+    Param { ch: usize, steps: i32, name: String },
+    Cv(usize, f32),
+    Midi(MidiMessage<'static>),
+    End,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub sample: u32,
+    pub line: usize,
+    pub action: Action,
+}
+
+#[derive(Clone, Debug)]
+pub struct Timeline {
+    events: Vec<Event>,
+    length: u32,
+}
+
+/// Every channel's level at every sample, in volts.
+pub struct Recording {
+    pub frames: Vec<[f32; NUM_OUTPUTS]>,
+}
+
+fn number<T: core::str::FromStr>(word: Option<&str>, what: &str) -> Result<T, String> {
+    let word = word.ok_or_else(|| format!("missing {}", what))?;
+    word.parse().map_err(|_| format!("bad {} '{}'", what, word))
+}
+
+/// A 1-based index from a script, as a 0-based one.
+fn index(word: Option<&str>, what: &str, count: usize) -> Result<usize, String> {
+    match number::<usize>(word, what)? {
+        n if (1..=count).contains(&n) => Ok(n - 1),
+        n => Err(format!("{} {} out of range 1-{}", what, n, count)),
+    }
+}
+
+fn data_byte(word: Option<&str>, what: &str) -> Result<u8, String> {
+    match number::<u8>(word, what)? {
+        n if n < 0x80 => Ok(n),
+        n => Err(format!("{} {} out of range 0-127", what, n)),
+    }
+}
+
+fn parse_action<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<Action, String> {
+    let command = words.next().ok_or("missing action")?;
+    let channel = |words: &mut dyn Iterator<Item = &'a str>| {
+        index(words.next(), "MIDI channel", NUM_CHANNELS).map(|ch| ch as u8)
+    };
+    let action = match command {
+        "bpm" => Action::Bpm(number(words.next(), "tempo")?),
+        "mode" => {
+            let ch = index(words.next(), "output", NUM_OUTPUTS)?;
+            let name = words.next().ok_or("missing mode")?;
+            let mode = modes::find(name).ok_or_else(|| format!("unknown mode '{}'", name))?;
+            Action::Mode(ch, mode.name)
+        }
+        "param" => {
+            let ch = index(words.next(), "output", NUM_OUTPUTS)?;
+            let steps = number(words.next(), "steps")?;
+            let name = words.by_ref().collect::<Vec<_>>().join(" ");
+            if name.is_empty() {
+                return Err("missing parameter".into());
+            }
+            Action::Param { ch, steps, name }
+        }
+        "cv" => {
+            let input = index(words.next(), "input", NUM_INPUTS)?;
+            Action::Cv(input, number(words.next(), "volts")?)
+        }
+        "note_on" => Action::Midi(MidiMessage::NoteOn {
+            channel: channel(&mut words)?,
+            note: data_byte(words.next(), "note")?,
+            velocity: data_byte(words.next(), "velocity")?,
+        }),
+        "note_off" => Action::Midi(MidiMessage::NoteOff {
+            channel: channel(&mut words)?,
+            note: data_byte(words.next(), "note")?,
+            velocity: 0,
+        }),
+        "cc" => Action::Midi(MidiMessage::ControlChange {
+            channel: channel(&mut words)?,
+            control: data_byte(words.next(), "controller")?,
+            value: data_byte(words.next(), "value")?,
+        }),
+        "pressure" => Action::Midi(MidiMessage::ChannelPressure {
+            channel: channel(&mut words)?,
+            pressure: data_byte(words.next(), "pressure")?,
+        }),
+        "key_pressure" => Action::Midi(MidiMessage::PolyPressure {
+            channel: channel(&mut words)?,
+            note: data_byte(words.next(), "note")?,
+            pressure: data_byte(words.next(), "pressure")?,
+        }),
+        "bend" => {
+            let channel = channel(&mut words)?;
+            match number::<i16>(words.next(), "bend")? {
+                value if (-8192..8192).contains(&value) => {
+                    Action::Midi(MidiMessage::PitchBend { channel, value })
+                }
+                value => return Err(format!("bend {} out of range -8192-8191", value)),
+            }
+        }
+        "clock" => Action::Midi(MidiMessage::Clock),
+        "start" => Action::Midi(MidiMessage::Start),
+        "continue" => Action::Midi(MidiMessage::Continue),
+        "stop" => Action::Midi(MidiMessage::Stop),
+        "end" => Action::End,
+        other => return Err(format!("unknown action '{}'", other)),
+    };
+    match words.next() {
+        Some(extra) => Err(format!("unexpected '{}'", extra)),
+        None => Ok(action),
+    }
+}
+
+impl Timeline {
+    pub fn parse(script: &str) -> Result<Self, ScriptError> {
+        let mut events = Vec::new();
+        for (i, text) in script.lines().enumerate() {
+            let line = i + 1;
+            let text = text.split('#').next().unwrap_or_default();
+            let mut words = text.split_whitespace();
+            let Some(time) = words.next() else {
+                continue;
+            };
+            let error = |message| ScriptError { line, message };
+            let millis: f64 = number(Some(time), "time").map_err(error)?;
+            if millis < 0.0 {
+                return Err(error(format!("negative time {}", millis)));
+            }
+            let action = parse_action(words).map_err(error)?;
+            let sample = (millis * SAMPLE_RATE as f64 / 1000.0).round() as u32;
+            events.push(Event {
+                sample,
+                line,
+                action,
+            });
+        }
+
+        // Stable, so events at the same time keep their order
+        events.sort_by_key(|event| event.sample);
+        let length = events
+            .iter()
+            .find(|event| event.action == Action::End)
+            .map(|event| event.sample)
+            .ok_or(ScriptError {
+                line: script.lines().count(),
+                message: "no end".into(),
+            })?;
+        Ok(Timeline { events, length })
+    }
+
+    /// Samples rendered, up to the first `end`.
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn render(&self) -> Result<Recording, ScriptError> {
+        let mut engine = Engine::default();
+        let mut frames = Vec::with_capacity(self.length as usize);
+        let mut events = self.events.iter().peekable();
+        for sample in 0..self.length {
+            while let Some(event) = events.next_if(|event| event.sample == sample) {
+                apply(&mut engine, event)?;
+            }
+            engine.tick();
+            // Not recorded, but drained so the queue never fills
+            while engine.midi_out.pop().is_some() {}
+            frames.push(*engine.levels());
+        }
+        Ok(Recording { frames })
+    }
+}
+
+fn apply(engine: &mut Engine, event: &Event) -> Result<(), ScriptError> {
+    match &event.action {
+        Action::Bpm(bpm) => engine.set_bpm(*bpm),
+        Action::Mode(ch, name) => {
+            engine.set_mode(*ch, (modes::find(name).unwrap().new)());
+        }
+        Action::Param { ch, steps, name } => {
+            let param = (0..engine.num_parameters(*ch))
+                .find(|param| {
+                    engine
+                        .parameter(*ch, *param)
+                        .is_some_and(|(label, _)| label == name)
+                })
+                .ok_or_else(|| ScriptError {
+                    line: event.line,
+                    message: format!("output {} has no parameter '{}'", ch + 1, name),
+                })?;
+            let (_, parameter) = engine.parameter(*ch, param).unwrap();
+            for _ in 0..steps.unsigned_abs() {
+                if *steps > 0 {
+                    parameter.next();
+                } else {
+                    parameter.prev();
+                }
+            }
+        }
+        Action::Cv(input, volts) => engine.cv[*input] = *volts,
+        Action::Midi(message) => engine.receive(message),
+        Action::End => {}
+    }
+    Ok(())
+}
+
+impl Recording {
+    /// One row per `every` samples: the sample number then each output.
+    pub fn write_csv(&self, out: &mut impl Write, every: usize) -> io::Result<()> {
+        write!(out, "sample")?;
+        for ch in 1..=NUM_OUTPUTS {
+            write!(out, ",out{}", ch)?;
+        }
+        writeln!(out)?;
+        for (sample, frame) in self.frames.iter().enumerate().step_by(every.max(1)) {
+            write!(out, "{}", sample)?;
+            for volts in frame {
+                write!(out, ",{:.4}", volts)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    /// 16 bit PCM, a channel per output, ±10V at full scale.
+    pub fn write_wav(&self, out: &mut impl Write) -> io::Result<()> {
+        write_wav_header(out, NUM_OUTPUTS as u16, self.frames.len() as u32)?;
+        for frame in &self.frames {
+            for volts in frame {
+                out.write_all(&wav_sample(volts / WAV_FULL_SCALE).to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A full scale of ±1 as 16 bit PCM, clipped.
+pub fn wav_sample(level: f32) -> i16 {
+    let scaled = (level * i16::MAX as f32).round();
+    scaled.clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+/// Header of a 16 bit PCM WAV with `frames` samples on each of `channels`,
+/// to be followed by the samples, interleaved.
+pub fn write_wav_header(out: &mut impl Write, channels: u16, frames: u32) -> io::Result<()> {
+    const BYTES_PER_SAMPLE: u32 = 2;
+    let block_align = channels as u32 * BYTES_PER_SAMPLE;
+    let data_len = frames * block_align;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&(36 + data_len).to_le_bytes())?;
+    out.write_all(b"WAVEfmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?; // PCM
+    out.write_all(&channels.to_le_bytes())?;
+    out.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    out.write_all(&(SAMPLE_RATE * block_align).to_le_bytes())?;
+    out.write_all(&(block_align as u16).to_le_bytes())?;
+    out.write_all(&(BYTES_PER_SAMPLE as u16 * 8).to_le_bytes())?;
+    out.write_all(b"data")?;
+    out.write_all(&data_len.to_le_bytes())
+}
@@ -0,0 +1,239 @@
+//! An eight lane oscilloscope of what the DAC would output, drawn right of
+//! the panel with `--scope` to compare against the device's own screen.
+//!
+//! The trace rolls right to left. Each column covers many samples, so it
+//! is drawn from their lowest to highest level and fast signals show as a
+//! band. Rising crossings of `TRIGGER_VOLTS` are marked above the lane.
+
+use std::collections::VecDeque;
+
+use common::calibration::{MAX_VOLTS, MIN_VOLTS, NUM_OUTPUTS};
+use embedded_graphics::pixelcolor::Bgr565;
+use embedded_graphics::prelude::{DrawTarget, Point, RgbColor};
+use embedded_graphics::primitives::{Line, PrimitiveStyle, Rectangle, StyledDrawable};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use crate::display::{BLUE, BRIGHT, DARK, FONT_08, TAN};
+use crate::output::SAMPLE_RATE;
+use crate::Display;
+
+/// Width the scope adds to the window.
+pub const SCOPE_WIDTH: u32 = 240;
+
+/// Time across the whole trace.
+const SCOPE_SECONDS: u32 = 2;
+/// Level a rising signal has to cross to count as a trigger.
+const TRIGGER_VOLTS: f32 = 1.0;
+
+const HEADER_HEIGHT: i32 = 12;
+const LABEL_WIDTH: i32 = 12;
+/// Columns of trace, a pixel each.
+const COLUMNS: usize = SCOPE_WIDTH as usize - LABEL_WIDTH as usize - 2;
+const SAMPLES_PER_COLUMN: u32 = SAMPLE_RATE * SCOPE_SECONDS / COLUMNS as u32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Column {
+    min: [f32; NUM_OUTPUTS],
+    max: [f32; NUM_OUTPUTS],
+    triggered: [bool; NUM_OUTPUTS],
+}
+
+impl Column {
+    fn new(levels: &[f32; NUM_OUTPUTS]) -> Self {
+        Column {
+            min: *levels,
+            max: *levels,
+            triggered: [false; NUM_OUTPUTS],
+        }
+    }
+}
+
+pub struct Scope {
+    columns: VecDeque<Column>,
+    /// The column still being filled, and how many samples it has
+    current: Option<(Column, u32)>,
+    last: [f32; NUM_OUTPUTS],
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Scope {
+            columns: VecDeque::with_capacity(COLUMNS),
+            current: None,
+            last: [0.0; NUM_OUTPUTS],
+        }
+    }
+}
+
+impl Scope {
+    /// Add one sample of every output's level.
+    pub fn push(&mut self, levels: &[f32; NUM_OUTPUTS]) {
+        let (column, samples) = self.current.get_or_insert((Column::new(levels), 0));
+        for (ch, &level) in levels.iter().enumerate() {
+            column.min[ch] = column.min[ch].min(level);
+            column.max[ch] = column.max[ch].max(level);
+            if self.last[ch] < TRIGGER_VOLTS && level >= TRIGGER_VOLTS {
+                column.triggered[ch] = true;
+            }
+        }
+        self.last = *levels;
+        *samples += 1;
+        if *samples == SAMPLES_PER_COLUMN {
+            if self.columns.len() == COLUMNS {
+                self.columns.pop_front();
+            }
+            self.columns.push_back(*column);
+            self.current = None;
+        }
+    }
+
+    /// Draw the scope into `area`, a lane per output.
+    pub fn draw(&self, display: &mut Display, area: Rectangle) {
+        display.fill_solid(&area, Bgr565::BLACK).ok();
+        let dim = PrimitiveStyle::with_stroke(DARK, 1);
+        let trace = PrimitiveStyle::with_stroke(TAN, 1);
+        let marker = PrimitiveStyle::with_stroke(BRIGHT, 1);
+
+        FONT_08
+            .render_aligned(
+                format_args!("{:+.0}..{:+.0}V  {}s", MIN_VOLTS, MAX_VOLTS, SCOPE_SECONDS),
+                area.top_left + Point::new(LABEL_WIDTH, HEADER_HEIGHT / 2),
+                VerticalPosition::Center,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(TAN),
+                display,
+            )
+            .ok();
+
+        let lane_height = (area.size.height as i32 - HEADER_HEIGHT) / NUM_OUTPUTS as i32;
+        let left = area.top_left.x + LABEL_WIDTH;
+        let right = left + COLUMNS as i32 - 1;
+        // Newest on the right
+        let first_x = right + 1 - self.columns.len() as i32;
+
+        for ch in 0..NUM_OUTPUTS {
+            let top = area.top_left.y + HEADER_HEIGHT + ch as i32 * lane_height;
+            // Markers go in the two rows above the trace
+            let (high, low) = (top + 2, top + lane_height - 2);
+            let y = |volts: f32| {
+                let fraction =
+                    (volts.clamp(MIN_VOLTS, MAX_VOLTS) - MIN_VOLTS) / (MAX_VOLTS - MIN_VOLTS);
+                low - (fraction * (low - high) as f32).round() as i32
+            };
+
+            FONT_08
+                .render_aligned(
+                    format_args!("{}", ch + 1),
+                    Point::new(area.top_left.x + LABEL_WIDTH / 2, (high + low) / 2),
+                    VerticalPosition::Center,
+                    HorizontalAlignment::Center,
+                    FontColor::Transparent(TAN),
+                    display,
+                )
+                .ok();
+            Rectangle::with_corners(Point::new(left, high), Point::new(right, low))
+                .draw_styled(&dim, display)
+                .ok();
+            // 0V, dotted
+            let zero = y(0.0);
+            let dots = (left..=right)
+                .step_by(4)
+                .map(|x| embedded_graphics::Pixel(Point::new(x, zero), BLUE));
+            display.draw_iter(dots).ok();
+
+            for (i, column) in self.columns.iter().enumerate() {
+                let x = first_x + i as i32;
+                Line::new(
+                    Point::new(x, y(column.max[ch])),
+                    Point::new(x, y(column.min[ch])),
+                )
+                .draw_styled(&trace, display)
+                .ok();
+                if column.triggered[ch] {
+                    Line::new(Point::new(x, top), Point::new(x, high - 1))
+                        .draw_styled(&marker, display)
+                        .ok();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::prelude::Size;
+
+    fn levels(volts: f32) -> [f32; NUM_OUTPUTS] {
+        let mut levels = [0.0; NUM_OUTPUTS];
+        levels[2] = volts;
+        levels
+    }
+
+    #[test]
+    fn columns_hold_the_range() {
+        let mut scope = Scope::default();
+        for i in 0..SAMPLES_PER_COLUMN {
+            scope.push(&levels(if i % 2 == 0 { -3.0 } else { 4.0 }));
+        }
+        assert_eq!(scope.columns.len(), 1);
+        assert_eq!(scope.columns[0].min[2], -3.0);
+        assert_eq!(scope.columns[0].max[2], 4.0);
+        assert_eq!(scope.columns[0].max[0], 0.0);
+        assert!(scope.current.is_none());
+    }
+
+    #[test]
+    fn marks_rising_triggers() {
+        // Already high, then falling, don't count
+        let mut scope = Scope {
+            last: levels(5.0),
+            ..Default::default()
+        };
+        scope.push(&levels(5.0));
+        for _ in 1..SAMPLES_PER_COLUMN {
+            scope.push(&levels(0.0));
+        }
+        scope.push(&levels(TRIGGER_VOLTS));
+        for _ in 1..SAMPLES_PER_COLUMN {
+            scope.push(&levels(TRIGGER_VOLTS));
+        }
+        assert!(!scope.columns[0].triggered[2]);
+        assert!(scope.columns[1].triggered[2]);
+        assert!(!scope.columns[1].triggered[1]);
+    }
+
+    #[test]
+    fn rolls() {
+        let mut scope = Scope::default();
+        for column in 0..COLUMNS as u32 + 3 {
+            for _ in 0..SAMPLES_PER_COLUMN {
+                scope.push(&levels(column as f32 / 100.0));
+            }
+        }
+        assert_eq!(scope.columns.len(), COLUMNS);
+        assert_eq!(scope.columns[0].min[2], 0.03);
+    }
+
+    #[test]
+    fn draws_traces() {
+        let mut scope = Scope::default();
+        for _ in 0..SAMPLES_PER_COLUMN {
+            scope.push(&levels(MAX_VOLTS));
+        }
+        let area = Rectangle::new(Point::zero(), Size::new(SCOPE_WIDTH, 188));
+        let mut display = Display::new(area.size);
+        scope.draw(&mut display, area);
+        let lane_height = (188 - HEADER_HEIGHT) / NUM_OUTPUTS as i32;
+        let right = LABEL_WIDTH + COLUMNS as i32 - 1;
+        // Output 3's newest column reaches the top of its lane
+        let top = HEADER_HEIGHT + 2 * lane_height + 2;
+        assert_eq!(display.get_pixel(Point::new(right, top)), TAN);
+        // Rising from 0V on the first sample
+        assert_eq!(display.get_pixel(Point::new(right, top - 2)), BRIGHT);
+        assert_eq!(
+            display.get_pixel(Point::new(right - 1, top + 1)),
+            Bgr565::BLACK
+        );
+    }
+}
@@ -0,0 +1,559 @@
+use embedded_graphics::{
+    prelude::*,
+    primitives::{PrimitiveStyle, PrimitiveStyleBuilder, Rectangle, StyledDrawable},
+};
+use u8g2_fonts::types::{FontColor, HorizontalAlignment, VerticalPosition};
+
+use crate::display::{BG, BLUE, BRIGHT, DARK, FONT_08, FONT_16, TAN};
+use crate::midi::MIDI_CHANNELS;
+use crate::output::{InputState, OutSignal, SAMPLE_RATE};
+use crate::parameters::{Choice, ConfigParameter, Parameter};
+use crate::quantizer::note_name;
+use crate::Display;
+
+use common::dsp::{from_q16, lerp, ONE_VOLT, Q16};
+use common::midi::{MidiMessage, MidiOut};
+
+pub const MAX_STEPS: usize = 32;
+
+const DIRECTIONS: [&str; 4] = ["Forward", "Reverse", "Pendulum", "Random"];
+const FORWARD: usize = 0;
+const REVERSE: usize = 1;
+const PENDULUM: usize = 2;
+
+// MIDI note sent for a step at 0V
+const MIDI_C4: i8 = 60;
+const MIDI_VELOCITY: u8 = 100;
+
+// Semitones from C4
+const MIN_NOTE: i8 = -24;
+const MAX_NOTE: i8 = 36;
+const MAX_RATCHET: u8 = 4;
+
+const FIELDS: [&str; 5] = ["Note", "Gate", "Ratchet", "Prob", "Slide"];
+
+#[derive(Clone, Copy)]
+pub struct Step {
+    note: i8,
+    /// Percent of the step the gate is high for, 0 rests
+    gate: u8,
+    ratchet: u8,
+    probability: u8,
+    slide: bool,
+}
+
+impl Default for Step {
+    fn default() -> Self {
+        Step {
+            note: 0,
+            gate: 50,
+            ratchet: 1,
+            probability: 100,
+            slide: false,
+        }
+    }
+}
+
+impl Step {
+    fn adjust(&mut self, field: usize, delta: i8) {
+        match field {
+            0 => self.note = self.note.saturating_add(delta).clamp(MIN_NOTE, MAX_NOTE),
+            1 => self.gate = self.gate.saturating_add_signed(delta * 5).min(100),
+            2 => {
+                self.ratchet = self
+                    .ratchet
+                    .saturating_add_signed(delta)
+                    .clamp(1, MAX_RATCHET)
+            }
+            3 => self.probability = self.probability.saturating_add_signed(delta * 5).min(100),
+            _ => self.slide = !self.slide,
+        }
+    }
+}
+
+/// Grid of steps, turn to pick a step and push to walk through its fields.
+#[derive(Clone)]
+pub struct Steps {
+    steps: [Step; MAX_STEPS],
+    cursor: usize,
+    field: Option<usize>,
+    // Copies of the sequencer's length and playhead, see `Sequencer::sync`
+    length: usize,
+    playing: usize,
+}
+
+impl ConfigParameter for Steps {
+    fn next(&mut self) {
+        match self.field {
+            Some(field) => self.steps[self.cursor].adjust(field, 1),
+            None => self.cursor = (self.cursor + 1) % self.length,
+        }
+    }
+
+    fn prev(&mut self) {
+        match self.field {
+            Some(field) => self.steps[self.cursor].adjust(field, -1),
+            None => self.cursor = (self.cursor + self.length - 1) % self.length,
+        }
+    }
+
+    fn push(&mut self) -> bool {
+        self.field = match self.field {
+            None => Some(0),
+            Some(field) if field + 1 < FIELDS.len() => Some(field + 1),
+            Some(_) => None,
+        };
+        true
+    }
+
+    fn draw_edit(&self, disp: &mut Display, window: Rectangle) {
+        let grid = Rectangle::new(window.top_left, window.size - Size::new(0, 12));
+        draw_grid(
+            disp,
+            grid,
+            &self.steps[..self.length],
+            self.playing,
+            Some(self.cursor),
+        );
+
+        let step = &self.steps[self.cursor];
+        let fields = [
+            note_name(step.note as i32),
+            format!("{}%", step.gate),
+            format!("x{}", step.ratchet),
+            format!("{}%", step.probability),
+            (if step.slide { "Sl" } else { "--" }).to_string(),
+        ];
+        let mut x = window.top_left.x;
+        let y = window.bottom_right().unwrap_or(window.top_left).y;
+        for (i, text) in fields.iter().enumerate() {
+            let color = if self.field == Some(i) { BRIGHT } else { DARK };
+            let text = if i == 0 {
+                format!("{:02} {}", self.cursor + 1, text)
+            } else {
+                text.clone()
+            };
+            if let Ok(Some(drawn)) = FONT_08.render_aligned(
+                text.as_str(),
+                Point::new(x, y),
+                VerticalPosition::Bottom,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(color),
+                disp,
+            ) {
+                x += drawn.size.width as i32 + 2;
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Sequencer {
+    length: Parameter<i32>,
+    direction: Choice,
+    steps_per_beat: Parameter<i32>,
+    slide_ms: Parameter<i32>,
+    midi_channel: Choice,
+    steps: Steps,
+    position: usize,
+    /// Past the first step, `position` has been played
+    started: bool,
+    reverse: bool,
+    tick: u32,
+    fire: bool,
+    slide_from: Q16,
+    pitch: Q16,
+    gate: bool,
+    seed: u32,
+    /// Channel and note of the MIDI note left on
+    sent: Option<(u8, u8)>,
+}
+
+impl Default for Sequencer {
+    fn default() -> Self {
+        Sequencer {
+            length: Parameter::new_saturating(1, MAX_STEPS as i32, 1, 16),
+            direction: Choice::new(&DIRECTIONS, FORWARD),
+            steps_per_beat: Parameter::new_saturating(1, 8, 1, 4),
+            slide_ms: Parameter::new_saturating(10, 1000, 10, 100),
+            midi_channel: Choice::new(&MIDI_CHANNELS, 0),
+            steps: Steps {
+                steps: [Step::default(); MAX_STEPS],
+                cursor: 0,
+                field: None,
+                length: 16,
+                playing: 0,
+            },
+            position: 0,
+            started: false,
+            reverse: false,
+            tick: u32::MAX,
+            fire: false,
+            slide_from: 0,
+            pitch: 0,
+            gate: false,
+            seed: 0x2545_F491,
+            sent: None,
+        }
+    }
+}
+
+impl Sequencer {
+    fn length(&self) -> usize {
+        *self.length as usize
+    }
+
+    /// xorshift32, plenty for step probability and random walks.
+    fn random(&mut self) -> u32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed
+    }
+
+    /// Where each direction starts, pendulums start out forwards.
+    fn first_step(&mut self) -> usize {
+        let last = self.length() - 1;
+        self.reverse = false;
+        match *self.direction {
+            FORWARD | PENDULUM => 0,
+            REVERSE => last,
+            _ => self.random() as usize % (last + 1),
+        }
+    }
+
+    fn advance(&mut self) {
+        let last = self.length() - 1;
+        self.position = match *self.direction {
+            _ if !self.started => {
+                self.started = true;
+                self.first_step()
+            }
+            FORWARD => (self.position + 1) % (last + 1),
+            REVERSE => self.position.checked_sub(1).unwrap_or(last).min(last),
+            PENDULUM => {
+                if last == 0 {
+                    0
+                } else {
+                    if self.position >= last {
+                        self.reverse = true;
+                    } else if self.position == 0 {
+                        self.reverse = false;
+                    }
+                    if self.reverse {
+                        self.position.min(last) - 1
+                    } else {
+                        self.position + 1
+                    }
+                }
+            }
+            _ => self.random() as usize % (last + 1),
+        };
+
+        let step = self.steps.steps[self.position];
+        self.fire = self.random() % 100 < step.probability as u32;
+        self.slide_from = self.pitch;
+    }
+
+    /// Keep the step grid's view of the length and playhead current.
+    fn sync(&mut self) {
+        self.steps.length = self.length();
+        self.steps.cursor = self.steps.cursor.min(self.length() - 1);
+        self.steps.playing = self.position;
+    }
+}
+
+impl OutSignal for Sequencer {
+    fn num_parameters(&self) -> usize {
+        6
+    }
+
+    fn parameter(&mut self, param: usize) -> Option<(&'static str, &mut dyn ConfigParameter)> {
+        self.sync();
+        match param {
+            0 => Some(("Steps", &mut self.steps)),
+            1 => Some(("Length", &mut self.length)),
+            2 => Some(("Direction", &mut self.direction)),
+            3 => Some(("Steps/Beat", &mut self.steps_per_beat)),
+            4 => Some(("Slide ms", &mut self.slide_ms)),
+            5 => Some(("MIDI Out", &mut self.midi_channel)),
+            _ => None,
+        }
+    }
+
+    fn gate(&self) -> bool {
+        self.gate
+    }
+
+    fn midi(&mut self, out: &mut MidiOut) {
+        let note = (MIDI_C4 + self.steps.steps[self.position].note) as u8;
+        let wanted = match *self.midi_channel {
+            ch if ch > 0 && self.gate => Some((ch as u8 - 1, note)),
+            _ => None,
+        };
+        if wanted == self.sent {
+            return;
+        }
+
+        self.release(out);
+        if let Some((channel, note)) = wanted {
+            out.send(&MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity: MIDI_VELOCITY,
+            });
+        }
+        self.sent = wanted;
+    }
+
+    fn release(&mut self, out: &mut MidiOut) {
+        if let Some((channel, note)) = self.sent.take() {
+            out.send(&MidiMessage::NoteOff {
+                channel,
+                note,
+                velocity: 0,
+            });
+        }
+    }
+
+    fn generate(&mut self, input: &InputState) -> f32 {
+        let period = (input.beat_period / *self.steps_per_beat as u32).max(1);
+        let tick = input.cycle / period;
+        if tick != self.tick {
+            self.tick = tick;
+            self.advance();
+            self.sync();
+        }
+
+        let step = self.steps.steps[self.position];
+        let elapsed = input.cycle % period;
+
+        let ratchet = (period / step.ratchet as u32).max(1);
+        let high = ratchet * step.gate as u32 / 100;
+        self.gate = self.fire && (step.gate >= 100 || elapsed % ratchet < high);
+
+        let target = step.note as Q16 * ONE_VOLT / 12;
+        let slide = (*self.slide_ms as u32 * SAMPLE_RATE / 1000).max(1);
+        self.pitch = if step.slide && elapsed < slide {
+            let frac = (elapsed as u64 * 0x1_0000 / slide as u64) as u16;
+            lerp(self.slide_from, target, frac)
+        } else {
+            target
+        };
+        from_q16(self.pitch)
+    }
+
+    fn draw_output(&self, disp: &mut Display, window: Rectangle) {
+        let color = if self.gate { BRIGHT } else { DARK };
+        FONT_08
+            .render_aligned(
+                note_name(self.steps.steps[self.position].note as i32).as_str(),
+                window.center(),
+                VerticalPosition::Center,
+                HorizontalAlignment::Center,
+                FontColor::Transparent(color),
+                disp,
+            )
+            .ok();
+    }
+
+    fn draw_configure(&self, disp: &mut Display, window: Rectangle) {
+        let anchor = window.top_left + Point::new(5, 5);
+        FONT_16
+            .render_aligned(
+                "Sequencer",
+                anchor,
+                VerticalPosition::Top,
+                HorizontalAlignment::Left,
+                FontColor::Transparent(TAN),
+                disp,
+            )
+            .ok();
+
+        let grid = Rectangle::new(
+            window.top_left + Point::new(5, 30),
+            Size::new(window.size.width - 10, window.size.height - 35),
+        );
+        draw_grid(
+            disp,
+            grid,
+            &self.steps.steps[..self.length()],
+            self.position,
+            None,
+        );
+    }
+}
+
+/// Steps in rows of eight, the bar in each cell rising with the step's note.
+fn draw_grid(
+    disp: &mut Display,
+    window: Rectangle,
+    steps: &[Step],
+    playing: usize,
+    cursor: Option<usize>,
+) {
+    let rows = steps.len().div_ceil(8).max(2) as u32;
+    let cell = Size::new(window.size.width / 8, window.size.height / rows);
+
+    for (i, step) in steps.iter().enumerate() {
+        let origin = window.top_left
+            + Point::new(
+                (i % 8) as i32 * cell.width as i32,
+                (i / 8) as i32 * cell.height as i32,
+            );
+        let rect = Rectangle::new(origin, cell);
+
+        let outline = if cursor == Some(i) {
+            BLUE
+        } else if i == playing {
+            BRIGHT
+        } else {
+            DARK
+        };
+        let style = PrimitiveStyleBuilder::new()
+            .fill_color(BG)
+            .stroke_color(outline)
+            .stroke_width(1)
+            .build();
+        rect.draw_styled(&style, disp).ok();
+
+        if step.gate > 0 {
+            let inner = rect.offset(-2);
+            let span = (MAX_NOTE - MIN_NOTE) as u32;
+            let height = (inner.size.height * (step.note - MIN_NOTE) as u32 / span).max(1);
+            let bar = Rectangle::new(
+                inner.top_left + Point::new(0, (inner.size.height - height) as i32),
+                Size::new(inner.size.width, height),
+            );
+            let fill = if step.slide { BRIGHT } else { TAN };
+            bar.draw_styled(&PrimitiveStyle::with_fill(fill), disp).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::midi::MidiState;
+
+    // Samples per step at the default four steps a beat
+    const STEP: u32 = 100;
+
+    fn sequencer(length: i32, direction: usize) -> Sequencer {
+        let mut seq = Sequencer {
+            length: Parameter::new_saturating(1, MAX_STEPS as i32, 1, length),
+            ..Default::default()
+        };
+        seq.direction.set(direction);
+        seq
+    }
+
+    /// Run from the start, one entry per sample of the sequencer's gate.
+    fn gates(seq: &mut Sequencer, samples: u32) -> Vec<bool> {
+        let midi = MidiState::new();
+        (0..samples)
+            .map(|cycle| {
+                let input = InputState {
+                    beat_period: 4 * STEP,
+                    ..InputState::at(cycle, &midi)
+                };
+                seq.generate(&input);
+                seq.gate
+            })
+            .collect()
+    }
+
+    /// The step played for each of the first `steps` steps.
+    fn order(seq: &mut Sequencer, steps: u32) -> Vec<usize> {
+        let midi = MidiState::new();
+        (0..steps)
+            .map(|step| {
+                let input = InputState {
+                    beat_period: 4 * STEP,
+                    ..InputState::at(step * STEP, &midi)
+                };
+                seq.generate(&input);
+                seq.position
+            })
+            .collect()
+    }
+
+    #[test]
+    fn forward_and_reverse() {
+        let mut seq = sequencer(4, FORWARD);
+        assert_eq!(order(&mut seq, 9), [0, 1, 2, 3, 0, 1, 2, 3, 0]);
+        let mut seq = sequencer(4, REVERSE);
+        assert_eq!(order(&mut seq, 9), [3, 2, 1, 0, 3, 2, 1, 0, 3]);
+    }
+
+    #[test]
+    fn pendulum_starts_from_the_first_step() {
+        let mut seq = sequencer(4, PENDULUM);
+        assert_eq!(order(&mut seq, 10), [0, 1, 2, 3, 2, 1, 0, 1, 2, 3]);
+        let mut seq = sequencer(1, PENDULUM);
+        assert_eq!(order(&mut seq, 3), [0, 0, 0]);
+    }
+
+    #[test]
+    fn shortening_mid_sequence() {
+        let mut seq = sequencer(8, PENDULUM);
+        assert_eq!(order(&mut seq, 7), [0, 1, 2, 3, 4, 5, 6]);
+        (0..4).for_each(|_| seq.length.prev());
+        assert_eq!(order(&mut seq, 4)[..3], [2, 1, 0]);
+    }
+
+    #[test]
+    fn random_stays_within_length() {
+        let mut seq = sequencer(5, 3);
+        let played = order(&mut seq, 200);
+        assert!(played.iter().all(|step| *step < 5));
+        for step in 0..5 {
+            assert!(played.contains(&step), "step {} never played", step);
+        }
+    }
+
+    #[test]
+    fn ratchets() {
+        let mut seq = sequencer(2, FORWARD);
+        seq.steps.steps[0].ratchet = 2;
+        seq.steps.steps[1].ratchet = 4;
+        seq.steps.steps[1].gate = 100;
+        let gates = gates(&mut seq, 2 * STEP);
+
+        // Count the gates rising in each step
+        let rises = |samples: &[bool]| {
+            let mut last = false;
+            samples
+                .iter()
+                .filter(|gate| {
+                    let rise = **gate && !last;
+                    last = **gate;
+                    rise
+                })
+                .count()
+        };
+        assert_eq!(rises(&gates[..STEP as usize]), 2);
+        assert_eq!(&gates[..50], [[true; 25], [false; 25]].concat());
+        // A full gate ties its ratchets into one
+        assert_eq!(rises(&gates[STEP as usize..]), 1);
+    }
+
+    #[test]
+    fn probability() {
+        let mut seq = sequencer(3, FORWARD);
+        seq.steps.steps[1].probability = 0;
+        seq.steps.steps[2].probability = 50;
+        let gates = gates(&mut seq, 300 * STEP);
+        let fired: Vec<bool> = gates.chunks(STEP as usize).map(|step| step[0]).collect();
+
+        assert!(fired.iter().step_by(3).all(|fired| *fired));
+        assert!(!fired.iter().skip(1).step_by(3).any(|fired| *fired));
+        let half = fired
+            .iter()
+            .skip(2)
+            .step_by(3)
+            .filter(|fired| **fired)
+            .count();
+        assert!((30..=70).contains(&half), "{} of 100 fired", half);
+    }
+}
@@ -0,0 +1,77 @@
+use common::dsp::{from_q16, to_q16};
+use common::slew::{Glide, GlideShape, OnePole};
+
+use crate::output::SAMPLE_RATE;
+use crate::parameters::{Choice, ConfigParameter, LogTime};
+
+const SHAPES: [&str; 4] = ["Off", "Linear", "Expo", "Const Time"];
+const GLIDE_SHAPES: [GlideShape; 4] = [
+    GlideShape::Off,
+    GlideShape::Linear,
+    GlideShape::Exponential,
+    GlideShape::ConstantTime,
+];
+
+const CUTOFFS: [&str; 5] = ["Off", "2 kHz", "500 Hz", "100 Hz", "20 Hz"];
+const CUTOFF_HZ: [f32; 5] = [0.0, 2000.0, 500.0, 100.0, 20.0];
+
+/// Parameters every channel gets after its mode's own.
+pub const SLEW_PARAMETERS: usize = 3;
+
+/// Glide and smoothing run on a channel's output, whatever its mode.
+#[derive(Clone)]
+pub struct SlewStage {
+    shape: Choice,
+    time: LogTime,
+    smoothing: Choice,
+    glide: Glide,
+    filter: OnePole,
+    // Settings the glide and filter were last set up for
+    configured: (usize, LogTime, usize),
+}
+
+impl Default for SlewStage {
+    fn default() -> Self {
+        let time = LogTime::new(50.0);
+        SlewStage {
+            shape: Choice::new(&SHAPES, 0),
+            time,
+            smoothing: Choice::new(&CUTOFFS, 0),
+            glide: Glide::new(GlideShape::Off, time.millis(), SAMPLE_RATE),
+            filter: OnePole::new(CUTOFF_HZ[1], SAMPLE_RATE),
+            configured: (0, time, 0),
+        }
+    }
+}
+
+impl SlewStage {
+    pub fn parameter(&mut self, param: usize) -> Option<(&'static str, &mut dyn ConfigParameter)> {
+        match param {
+            0 => Some(("Slew", &mut self.shape)),
+            1 => Some(("Slew Time", &mut self.time)),
+            2 => Some(("Smoothing", &mut self.smoothing)),
+            _ => None,
+        }
+    }
+
+    pub fn process(&mut self, volts: f32) -> f32 {
+        let settings = (*self.shape, self.time, *self.smoothing);
+        if settings != self.configured {
+            let (shape, time, smoothing) = settings;
+            self.glide
+                .configure(GLIDE_SHAPES[shape], time.millis(), SAMPLE_RATE);
+            if smoothing != 0 {
+                self.filter.set_cutoff(CUTOFF_HZ[smoothing], SAMPLE_RATE);
+            }
+            self.configured = settings;
+        }
+
+        let glided = self.glide.process(to_q16(volts));
+        if *self.smoothing == 0 {
+            self.filter.reset(glided);
+            from_q16(glided)
+        } else {
+            from_q16(self.filter.process(glided))
+        }
+    }
+}
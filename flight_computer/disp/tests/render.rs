@@ -0,0 +1,125 @@
+//! Golden file tests for the offline renderer, see `disp::render`.
+//!
+//! After an intended change to a mode, rewrite the expected output with
+//! `UPDATE_GOLDEN=1 cargo test --test render` and review the diff.
+
+use std::path::PathBuf;
+
+use disp::render::{ScriptError, Timeline};
+
+/// Rows kept in golden CSVs, 1ms apart.
+const GOLDEN_EVERY: usize = 48;
+
+fn golden(name: &str, extension: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name)
+        .with_extension(extension)
+}
+
+fn check(name: &str) {
+    let script = std::fs::read_to_string(golden(name, "txt")).unwrap();
+    let recording = Timeline::parse(&script).unwrap().render().unwrap();
+    let mut csv = Vec::new();
+    recording.write_csv(&mut csv, GOLDEN_EVERY).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+
+    let path = golden(name, "csv");
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, &csv).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap();
+    for (row, (actual, expected)) in csv.lines().zip(expected.lines()).enumerate() {
+        assert_eq!(actual, expected, "{} differs at row {}", name, row);
+    }
+    assert_eq!(
+        csv.lines().count(),
+        expected.lines().count(),
+        "{} rows",
+        name
+    );
+}
+
+#[test]
+fn clock_out() {
+    check("clock_out");
+}
+
+#[test]
+fn envelope() {
+    check("envelope");
+}
+
+#[test]
+fn midi_cv() {
+    check("midi_cv");
+}
+
+#[test]
+fn mpe() {
+    check("mpe");
+}
+
+#[test]
+fn poly_midi() {
+    check("poly_midi");
+}
+
+#[test]
+fn quad_lfo() {
+    check("quad_lfo");
+}
+
+#[test]
+fn renders_to_end() {
+    let timeline = Timeline::parse("0 mode 1 ClockOut\n10 end\n20 cv 1 1").unwrap();
+    let recording = timeline.render().unwrap();
+    assert_eq!(recording.frames.len(), 480);
+    // 120bpm, high for the first half beat
+    assert!(recording.frames.iter().all(|frame| frame[0] == 5.0));
+}
+
+#[test]
+fn wav_layout() {
+    let recording = Timeline::parse("0 cv 1 1\n1 end")
+        .unwrap()
+        .render()
+        .unwrap();
+    let mut wav = Vec::new();
+    recording.write_wav(&mut wav).unwrap();
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(&wav[8..16], b"WAVEfmt ");
+    // 8 channels of 16 bit samples after a 44 byte header
+    assert_eq!(wav.len(), 44 + 48 * 8 * 2);
+    assert_eq!(u16::from_le_bytes([wav[22], wav[23]]), 8);
+}
+
+#[test]
+fn script_errors() {
+    let error = |script: &str| Timeline::parse(script).and_then(|t| t.render().map(|_| t));
+    let at = |line: usize, message: &str| {
+        Err(ScriptError {
+            line,
+            message: message.into(),
+        })
+    };
+
+    assert_eq!(
+        error("0 mode 9 ClockOut\n1 end").map(|_| ()),
+        at(1, "output 9 out of range 1-8")
+    );
+    assert_eq!(
+        error("# tempo\n0 bpm fast\n1 end").map(|_| ()),
+        at(2, "bad tempo 'fast'")
+    );
+    assert_eq!(error("0 cv 1 1").map(|_| ()), at(1, "no end"));
+    assert_eq!(
+        error("0 mode 1 ClockOut\n0 param 1 +1 Attack\n1 end").map(|_| ()),
+        at(2, "output 1 has no parameter 'Attack'")
+    );
+    assert_eq!(
+        error("0 note_on 1 60 100 7\n1 end").map(|_| ()),
+        at(1, "unexpected '7'")
+    );
+}
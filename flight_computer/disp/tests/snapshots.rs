@@ -0,0 +1,82 @@
+//! Golden image tests of the simulator's screens, run headless.
+//!
+//! Each `tests/snapshots/<name>.txt` script is run with `disp --headless`
+//! and every frame compared against `tests/snapshots/<name>/`. After an
+//! intended change to a screen, rewrite the images with
+//! `UPDATE_GOLDEN=1 cargo test --test snapshots` and review them.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use embedded_graphics::pixelcolor::Bgr565;
+use embedded_graphics_simulator::SimulatorDisplay;
+
+fn frames(dir: &Path) -> Vec<PathBuf> {
+    let mut frames: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map(|entries| entries.map(|entry| entry.unwrap().path()).collect())
+        .unwrap_or_default();
+    frames.retain(|path| path.extension().is_some_and(|ext| ext == "png"));
+    frames.sort();
+    frames
+}
+
+fn check(name: &str) {
+    let snapshots = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+    // A directory of its own, so no stored calibration is picked up
+    let run_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&run_dir);
+    std::fs::create_dir_all(&run_dir).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_disp"))
+        .arg("--headless")
+        .arg(snapshots.join(name).with_extension("txt"))
+        .arg("frames")
+        .current_dir(&run_dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{} exited with {}: {}",
+        name,
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let golden = snapshots.join(name);
+    let actual = frames(&run_dir.join("frames"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        let _ = std::fs::remove_dir_all(&golden);
+        std::fs::create_dir_all(&golden).unwrap();
+        for frame in &actual {
+            std::fs::copy(frame, golden.join(frame.file_name().unwrap())).unwrap();
+        }
+        return;
+    }
+
+    let expected = frames(&golden);
+    assert_eq!(actual.len(), expected.len(), "{} frame count", name);
+    for (actual, expected) in actual.iter().zip(&expected) {
+        let load = |path: &Path| SimulatorDisplay::<Bgr565>::load_png(path).unwrap();
+        assert!(
+            load(actual).diff(&load(expected)).is_none(),
+            "{} differs from {}",
+            actual.display(),
+            expected.display()
+        );
+    }
+}
+
+#[test]
+fn configure() {
+    check("configure");
+}
+
+#[test]
+fn output_state() {
+    check("output_state");
+}
+
+#[test]
+fn settings() {
+    check("settings");
+}
@@ -1,8 +1,9 @@
 MEMORY {
     /*
      * The RP2350 has either external or internal flash.
+     * The last 4K sector holds the output calibration, see flash.rs
      */
-    FLASH : ORIGIN = 0x10000000, LENGTH = 2048K
+    FLASH : ORIGIN = 0x10000000, LENGTH = 2048K - 4K
     /*
      * RAM consists of 8 banks, SRAM0-SRAM7, with a striped mapping.
      * This is usually good for performance, as it distributes load on
@@ -0,0 +1,70 @@
+use common::calibration::{CvCalibration, DacCalibration};
+use rp235x_hal::rom_data;
+
+// The last sector of the internal flash is kept out of `memory.x` for settings
+const FLASH_BASE: u32 = 0x1000_0000;
+const FLASH_SIZE: u32 = 2048 * 1024;
+const SECTOR_SIZE: u32 = 4096;
+const PAGE_SIZE: usize = 256;
+const CALIBRATION_OFFSET: u32 = FLASH_SIZE - SECTOR_SIZE;
+
+// One page each for the output and input tables
+const DAC_PAGE: usize = 0;
+const CV_PAGE: usize = PAGE_SIZE;
+
+const _: () = assert!(DacCalibration::STORED_SIZE <= PAGE_SIZE);
+const _: () = assert!(CvCalibration::STORED_SIZE <= PAGE_SIZE);
+
+fn stored(offset: usize, len: usize) -> &'static [u8] {
+    let addr = FLASH_BASE + CALIBRATION_OFFSET + offset as u32;
+    unsafe { core::slice::from_raw_parts(addr as *const u8, len) }
+}
+
+pub fn read_dac_calibration() -> Option<DacCalibration> {
+    DacCalibration::from_bytes(stored(DAC_PAGE, DacCalibration::STORED_SIZE))
+}
+
+pub fn read_cv_calibration() -> Option<CvCalibration> {
+    CvCalibration::from_bytes(stored(CV_PAGE, CvCalibration::STORED_SIZE))
+}
+
+/// Erase the calibration sector and program both tables into it.
+///
+/// XIP is unavailable while this runs, so core 1 must not be running from flash.
+pub fn write_calibration(dac: &DacCalibration, cv: &CvCalibration) {
+    let mut pages = [0xFFu8; 2 * PAGE_SIZE];
+    pages[DAC_PAGE..][..DacCalibration::STORED_SIZE].copy_from_slice(&dac.to_bytes());
+    pages[CV_PAGE..][..CvCalibration::STORED_SIZE].copy_from_slice(&cv.to_bytes());
+
+    let rom = RomFlash {
+        connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+        flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+        flash_range_erase: rom_data::flash_range_erase::ptr(),
+        flash_range_program: rom_data::flash_range_program::ptr(),
+        flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+        flash_enter_cmd_xip: rom_data::flash_enter_cmd_xip::ptr(),
+    };
+
+    critical_section::with(|_| unsafe { program_sector(&rom, CALIBRATION_OFFSET, &pages) });
+}
+
+// Looked up before leaving XIP, the lookup itself runs from flash
+struct RomFlash {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+    flash_enter_cmd_xip: unsafe extern "C" fn(),
+}
+
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn program_sector(rom: &RomFlash, offset: u32, data: &[u8]) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+    (rom.flash_range_erase)(offset, SECTOR_SIZE as usize, SECTOR_SIZE, 0x20);
+    (rom.flash_range_program)(offset, data.as_ptr(), data.len());
+    (rom.flash_flush_cache)();
+    (rom.flash_enter_cmd_xip)();
+}
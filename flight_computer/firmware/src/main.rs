@@ -12,6 +12,7 @@ use embedded_hal::spi::MODE_0;
 
 use hal::{
     pac,
+    adc::AdcPin,
     gpio,
     gpio::{PullNone, FunctionSpi},
     multicore::{Multicore, Stack},
@@ -27,6 +28,9 @@ use embedded_graphics::pixelcolor::{Rgb565, RgbColor};
 use core::cell::RefCell;
 use critical_section::Mutex;
 
+
+mod flash;
+mod midi;
 mod output_core;
 
 const XOSC_CRYSTAL_FREQ: u32 = 12_000_000u32;
@@ -101,13 +105,47 @@ fn main() -> ! {
 
 
     let system_clk = clocks.system_clock.freq().to_Hz();
+
+    // Core 1 runs from flash, so any first-boot write has to happen before it starts
+    let (dac_calibration, cv_calibration) = match (flash::read_dac_calibration(), flash::read_cv_calibration()) {
+        (Some(dac), Some(cv)) => (dac, cv),
+        (dac, cv) => {
+            let (dac, cv) = (dac.unwrap_or_default(), cv.unwrap_or_default());
+            flash::write_calibration(&dac, &cv);
+            (dac, cv)
+        }
+    };
+
+    let adc = hal::Adc::new(pac.ADC, &mut pac.RESETS);
+    let cv_pins = (
+        AdcPin::new(pins.gpio26.into_floating_input()).unwrap(),
+        AdcPin::new(pins.gpio27.into_floating_input()).unwrap(),
+        AdcPin::new(pins.gpio28.into_floating_input()).unwrap(),
+    );
+
+    // MIDI_TX on GPIO0, MIDI_RX on GPIO1
+    let midi_pins: midi::MidiPins = (pins.gpio0.reconfigure(), pins.gpio1.reconfigure());
+    let midi_uart = hal::uart::UartPeripheral::new(pac.UART0, midi_pins, &mut pac.RESETS)
+        .enable(
+            hal::uart::UartConfig::new(
+                common::midi::BAUD_RATE.Hz(),
+                hal::uart::DataBits::Eight,
+                None,
+                hal::uart::StopBits::One,
+            ),
+            clocks.peripheral_clock.freq(),
+        )
+        .unwrap();
+    let midi_tx = midi::listen(midi_uart, common::midi::ThruFilter::default());
+
     let mut mc = Multicore::new(&mut pac.PSM, &mut pac.PPB, &mut sio.fifo);
     let cores = mc.cores();
     let core1 = &mut cores[1];
-    core1.spawn(unsafe {&mut CORE1_STACK.mem}, output_core::core1_loop);
+    core1.spawn(unsafe {&mut CORE1_STACK.mem}, move || output_core::core1_loop(dac_calibration, cv_calibration, adc, cv_pins));
 
 
     loop {
+        midi::flush(&midi_tx);
         Rectangle::new(Point::new(0,0), Size::new(128, 128)).draw_styled(&style, &mut disp);
     }
 }
\ No newline at end of file
@@ -0,0 +1,95 @@
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use rp235x_hal as hal;
+
+use hal::gpio::{bank0, FunctionUart, Pin, PullNone};
+use hal::pac::{self, interrupt, UART0};
+use hal::uart::{Enabled, Reader, UartPeripheral, Writer};
+
+use common::midi::{MidiOut, MidiParser, MidiState, ThruFilter};
+
+pub type MidiPins = (
+    Pin<bank0::Gpio0, FunctionUart, PullNone>,
+    Pin<bank0::Gpio1, FunctionUart, PullNone>,
+);
+pub type MidiUart = UartPeripheral<Enabled, UART0, MidiPins>;
+pub type MidiWriter = Writer<UART0, MidiPins>;
+
+/// Latest MIDI state, written by the UART interrupt. The output modes that
+/// would read it don't run on the device yet, so for now nothing does.
+pub static MIDI_STATE: Mutex<RefCell<MidiState>> = Mutex::new(RefCell::new(MidiState::new()));
+
+/// Bytes waiting to go out, thru from the UART interrupt, sent by `flush`.
+pub static MIDI_OUT: Mutex<RefCell<MidiOut>> = Mutex::new(RefCell::new(MidiOut::new()));
+
+/// The receive half of the UART and what it feeds, owned by `UART0_IRQ`.
+struct Receiver {
+    reader: Reader<UART0, MidiPins>,
+    parser: MidiParser,
+    thru: ThruFilter,
+}
+
+static RECEIVER: Mutex<RefCell<Option<Receiver>>> = Mutex::new(RefCell::new(None));
+
+/// Hand receiving over to the UART interrupt, so bytes are taken out of
+/// the FIFO as they arrive however long the main loop takes. Returns the
+/// transmit half for `flush`.
+pub fn listen(uart: MidiUart, thru: ThruFilter) -> MidiWriter {
+    let (mut reader, writer) = uart.split();
+    reader.enable_rx_interrupt();
+    critical_section::with(|cs| {
+        RECEIVER.borrow_ref_mut(cs).replace(Receiver {
+            reader,
+            parser: MidiParser::new(),
+            thru,
+        });
+    });
+    unsafe { cortex_m::peripheral::NVIC::unmask(pac::Interrupt::UART0_IRQ) };
+    writer
+}
+
+/// Drain the receive FIFO into the shared MIDI state, queueing whatever
+/// the thru filter passes back out.
+#[interrupt]
+fn UART0_IRQ() {
+    critical_section::with(|cs| {
+        let mut receiver = RECEIVER.borrow_ref_mut(cs);
+        let Some(rx) = receiver.as_mut() else {
+            return;
+        };
+        let mut state = MIDI_STATE.borrow_ref_mut(cs);
+        let mut out = MIDI_OUT.borrow_ref_mut(cs);
+        let mut buf = [0u8; 32];
+        loop {
+            let len = match rx.reader.read_raw(&mut buf) {
+                Ok(len) => len,
+                Err(nb::Error::WouldBlock) => break,
+                // Framing or overrun errors lose the byte, the parser resyncs on the next status
+                Err(nb::Error::Other(_)) => continue,
+            };
+            for byte in &buf[..len] {
+                if let Some(message) = rx.parser.push(*byte) {
+                    state.apply(&message);
+                    if rx.thru.passes(&message) {
+                        out.send(&message);
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Move as much of the queue as the UART's TX FIFO will take, leaving the
+/// rest for the next call.
+pub fn flush(writer: &MidiWriter) {
+    critical_section::with(|cs| {
+        let mut out = MIDI_OUT.borrow_ref_mut(cs);
+        while let Some(byte) = out.peek() {
+            if writer.write_raw(&[byte]).is_err() {
+                break;
+            }
+            out.pop();
+        }
+    });
+}
@@ -7,13 +7,43 @@ use rp235x_hal::pio::{PIOExt, PinDir};
 use rp235x_hal as hal;
 use rp235x_hal::pac;
 
-use critical_section;
+use critical_section::{self, Mutex};
+
+use common::calibration::{CvCalibration, DacCalibration, NUM_INPUTS, NUM_OUTPUTS};
+use common::cv_input::CvInput;
+use common::jacks::Jacks;
+use rp235x_hal::adc::{AdcFifo, AdcPin};
+use rp235x_hal::gpio::{bank0, FunctionSioInput, Pin, PullNone};
 
 use crate::LED;
 
 static mut CYCLE: AtomicU32 = AtomicU32::new(0);
 
-pub fn core1_loop() {
+/// Volts each output is driven to, written by whatever generates them and
+/// turned into DAC codes through that output's calibration every sample.
+/// Nothing writes it until the output modes run on the device, so every
+/// output holds 0V.
+pub static OUTPUT_VOLTS: Mutex<RefCell<[f32; NUM_OUTPUTS]>> =
+    Mutex::new(RefCell::new([0.0; NUM_OUTPUTS]));
+
+pub type CvPins = (
+    AdcPin<Pin<bank0::Gpio26, FunctionSioInput, PullNone>>,
+    AdcPin<Pin<bank0::Gpio27, FunctionSioInput, PullNone>>,
+    AdcPin<Pin<bank0::Gpio28, FunctionSioInput, PullNone>>,
+);
+
+/// Sample the CV inputs in round robin from the first, so the FIFO holds
+/// whole triplets in input order.
+fn start_cv_fifo<'a>(adc: &'a mut hal::Adc, cv_pins: &mut CvPins) -> AdcFifo<'a, u16> {
+    // 1kHz per input, slow enough that the 4 deep FIFO never overflows between loops
+    adc.build_fifo()
+        .clock_divider(15999, 0)
+        .set_channel(&mut cv_pins.0)
+        .round_robin((&cv_pins.0, &cv_pins.1, &cv_pins.2))
+        .start()
+}
+
+pub fn core1_loop(calibration: DacCalibration, cv_calibration: CvCalibration, mut adc: hal::Adc, mut cv_pins: CvPins) {
     let core = unsafe { cortex_m::Peripherals::steal() };
     let mut pac = unsafe { pac::Peripherals::steal() };
     let sys_clk = 150_000_000u32;
@@ -77,17 +107,36 @@ pub fn core1_loop() {
     systimer.enable_interrupt();
     systimer.enable_counter();
 
+    let mut cv_in: [CvInput; NUM_INPUTS] = core::array::from_fn(|i| CvInput::new(*cv_calibration.input(i)));
+    // The TN switches aren't wired to GPIOs on this board, see common::jacks
+    let jacks = Jacks::default();
+    for (i, cv) in cv_in.iter_mut().enumerate() {
+        cv.set_patched(jacks.input(i));
+    }
+    let mut adc_fifo = start_cv_fifo(&mut adc, &mut cv_pins);
+
     loop {
         led.set_high();
-        let cycle = unsafe {CYCLE.load(core::sync::atomic::Ordering::Relaxed)};
-        sm0_tx.write(cycle);
-        sm0_tx.write(cycle+1);
-        sm0_tx.write(cycle+2);
-        sm0_tx.write(cycle+3);
-        sm1_tx.write(0);
-        sm1_tx.write(1);
-        sm1_tx.write(2);
-        sm1_tx.write(3);
+        // Samples carry no channel, so a sample dropped on overflow would
+        // shift every later one onto the wrong input. Start over from the
+        // first input with an empty FIFO instead.
+        if adc_fifo.is_over() {
+            adc_fifo = start_cv_fifo(adc_fifo.stop(), &mut cv_pins);
+        }
+        while adc_fifo.len() as usize >= NUM_INPUTS {
+            for cv in &mut cv_in {
+                cv.set_raw(adc_fifo.read());
+            }
+        }
+
+        let volts = critical_section::with(|cs| *OUTPUT_VOLTS.borrow_ref(cs));
+        let codes: [u16; NUM_OUTPUTS] = core::array::from_fn(|ch| calibration.volts_to_code(ch, volts[ch]));
+        for code in &codes[0..4] {
+            sm0_tx.write(*code as u32);
+        }
+        for code in &codes[4..8] {
+            sm1_tx.write(*code as u32);
+        }
         pio.force_irq(1);
         led.set_low();
         
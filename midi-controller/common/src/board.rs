@@ -0,0 +1,18 @@
+/// What the MIDI to CV logic drives, so it runs the same against the
+/// real peripherals and a mock on the host.
+pub trait Board {
+    /// Set a DAC output to a 12 bit code.
+    fn set_cv(&mut self, output: usize, code: u16);
+    fn set_gate(&mut self, output: usize, high: bool);
+    /// Set a PWM mod output's duty from a 7 bit controller value.
+    fn set_mod(&mut self, output: usize, value: u8);
+    fn set_led(&mut self, led: Led, on: bool);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Led {
+    /// Lit while a note is held
+    Note,
+    /// Toggled on every MIDI clock tick
+    Clock,
+}
@@ -0,0 +1,213 @@
+use crate::board::{Board, Led};
+use crate::notes::NoteStack;
+use crate::voltage::note_to_code;
+
+/// Controllers setting the two PWM mod outputs, in order.
+pub const MOD_CONTROLLERS: [u8; 2] = [0, 1];
+
+/// Codes the CV outputs start at before the first note.
+const STARTUP_CV: [u16; 2] = [2048, 1024];
+
+/// The MIDI messages the module follows, from any channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Message {
+    NoteOn { note: u8, velocity: u8 },
+    NoteOff { note: u8 },
+    ControlChange { control: u8, value: u8 },
+    Clock,
+}
+
+/// Monophonic MIDI to CV: the latest held note's pitch on CV 1 with its
+/// gate on gate 1, mod controllers on the PWM outputs and the clock on an
+/// LED.
+#[derive(Clone, Debug)]
+pub struct MidiCv {
+    notes: NoteStack,
+    clock_led: bool,
+}
+
+impl Default for MidiCv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiCv {
+    pub const fn new() -> Self {
+        MidiCv {
+            notes: NoteStack::new(),
+            clock_led: true,
+        }
+    }
+
+    /// Put the outputs in their power on state.
+    pub fn start(&mut self, board: &mut impl Board) {
+        board.set_led(Led::Clock, self.clock_led);
+        for (output, code) in STARTUP_CV.into_iter().enumerate() {
+            board.set_cv(output, code);
+        }
+    }
+
+    pub fn handle(&mut self, message: Message, board: &mut impl Board) {
+        match message {
+            Message::Clock => {
+                self.clock_led = !self.clock_led;
+                board.set_led(Led::Clock, self.clock_led);
+            }
+            // Velocity 0 is a note off by another name
+            Message::NoteOn { note, velocity: 0 } | Message::NoteOff { note } => {
+                let latest = self.notes.last();
+                self.notes.release(note);
+                match self.notes.last() {
+                    // Back to the key still down, without a new gate
+                    Some(held) if latest == Some(note) => board.set_cv(0, note_to_code(held)),
+                    Some(_) => {}
+                    None => {
+                        board.set_led(Led::Note, false);
+                        board.set_gate(0, false);
+                    }
+                }
+            }
+            Message::NoteOn { note, .. } => {
+                self.notes.press(note);
+                board.set_led(Led::Note, true);
+                board.set_gate(0, true);
+                board.set_cv(0, note_to_code(note));
+            }
+            Message::ControlChange { control, value } => {
+                if let Some(output) = MOD_CONTROLLERS.iter().position(|cc| *cc == control) {
+                    board.set_mod(output, value);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Remembers what was last set on each output.
+    #[derive(Default)]
+    struct MockBoard {
+        cv: [u16; 2],
+        gates: [bool; 2],
+        mods: [u8; 2],
+        note_led: bool,
+        clock_led: bool,
+        /// CV writes, to tell a retrigger from a held note
+        cv_writes: usize,
+    }
+
+    impl Board for MockBoard {
+        fn set_cv(&mut self, output: usize, code: u16) {
+            self.cv[output] = code;
+            self.cv_writes += 1;
+        }
+
+        fn set_gate(&mut self, output: usize, high: bool) {
+            self.gates[output] = high;
+        }
+
+        fn set_mod(&mut self, output: usize, value: u8) {
+            self.mods[output] = value;
+        }
+
+        fn set_led(&mut self, led: Led, on: bool) {
+            match led {
+                Led::Note => self.note_led = on,
+                Led::Clock => self.clock_led = on,
+            }
+        }
+    }
+
+    fn started() -> (MidiCv, MockBoard) {
+        let mut midi_cv = MidiCv::new();
+        let mut board = MockBoard::default();
+        midi_cv.start(&mut board);
+        (midi_cv, board)
+    }
+
+    fn note_on(note: u8) -> Message {
+        Message::NoteOn {
+            note,
+            velocity: 100,
+        }
+    }
+
+    #[test]
+    fn starts_up() {
+        let (_, board) = started();
+        assert_eq!(board.cv, STARTUP_CV);
+        assert!(board.clock_led);
+        assert!(!board.gates[0] && !board.note_led);
+    }
+
+    #[test]
+    fn note_sets_pitch_and_gate() {
+        let (mut midi_cv, mut board) = started();
+        midi_cv.handle(note_on(60), &mut board);
+        assert_eq!(board.cv[0], note_to_code(60));
+        assert!(board.gates[0] && board.note_led);
+        // The second CV is left alone
+        assert_eq!(board.cv[1], STARTUP_CV[1]);
+
+        midi_cv.handle(Message::NoteOff { note: 60 }, &mut board);
+        assert!(!board.gates[0] && !board.note_led);
+        assert_eq!(board.cv[0], note_to_code(60));
+    }
+
+    #[test]
+    fn falls_back_to_held_notes() {
+        let (mut midi_cv, mut board) = started();
+        midi_cv.handle(note_on(60), &mut board);
+        midi_cv.handle(note_on(64), &mut board);
+        midi_cv.handle(note_on(67), &mut board);
+        assert_eq!(board.cv[0], note_to_code(67));
+
+        // Releasing an older note changes nothing
+        let writes = board.cv_writes;
+        midi_cv.handle(Message::NoteOff { note: 64 }, &mut board);
+        assert_eq!(board.cv_writes, writes);
+
+        midi_cv.handle(Message::NoteOff { note: 67 }, &mut board);
+        assert_eq!(board.cv[0], note_to_code(60));
+        assert!(board.gates[0]);
+
+        midi_cv.handle(
+            Message::NoteOn {
+                note: 60,
+                velocity: 0,
+            },
+            &mut board,
+        );
+        assert!(!board.gates[0]);
+    }
+
+    #[test]
+    fn unheld_note_off_is_harmless() {
+        let (mut midi_cv, mut board) = started();
+        midi_cv.handle(note_on(60), &mut board);
+        midi_cv.handle(Message::NoteOff { note: 61 }, &mut board);
+        assert!(board.gates[0]);
+        assert_eq!(board.cv[0], note_to_code(60));
+    }
+
+    #[test]
+    fn mod_controllers() {
+        let (mut midi_cv, mut board) = started();
+        for (control, value) in [(0, 10), (1, 127), (7, 64)] {
+            midi_cv.handle(Message::ControlChange { control, value }, &mut board);
+        }
+        assert_eq!(board.mods, [10, 127]);
+    }
+
+    #[test]
+    fn clock_toggles_led() {
+        let (mut midi_cv, mut board) = started();
+        midi_cv.handle(Message::Clock, &mut board);
+        assert!(!board.clock_led);
+        midi_cv.handle(Message::Clock, &mut board);
+        assert!(board.clock_led);
+    }
+}
@@ -0,0 +1,6 @@
+#![no_std]
+
+pub mod board;
+pub mod controller;
+pub mod notes;
+pub mod voltage;
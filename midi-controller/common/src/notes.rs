@@ -0,0 +1,84 @@
+/// Keys held at once before the oldest are forgotten.
+pub const MAX_HELD: usize = 16;
+
+/// Notes held, oldest first, so releasing the latest falls back to the
+/// one before it.
+#[derive(Clone, Copy, Debug)]
+pub struct NoteStack {
+    notes: [u8; MAX_HELD],
+    len: usize,
+}
+
+impl Default for NoteStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NoteStack {
+    pub const fn new() -> Self {
+        NoteStack {
+            notes: [0; MAX_HELD],
+            len: 0,
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.notes[..self.len]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn last(&self) -> Option<u8> {
+        self.as_slice().last().copied()
+    }
+
+    pub fn press(&mut self, note: u8) {
+        // A repeated note moves to the top rather than being held twice
+        self.release(note);
+        if self.len == MAX_HELD {
+            self.notes.copy_within(1.., 0);
+            self.len -= 1;
+        }
+        self.notes[self.len] = note;
+        self.len += 1;
+    }
+
+    pub fn release(&mut self, note: u8) {
+        if let Some(i) = self.as_slice().iter().position(|held| *held == note) {
+            self.notes.copy_within(i + 1..self.len, i);
+            self.len -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latest_on_top() {
+        let mut stack = NoteStack::new();
+        stack.press(60);
+        stack.press(64);
+        stack.press(60);
+        assert_eq!(stack.as_slice(), [64, 60]);
+        stack.release(60);
+        assert_eq!(stack.last(), Some(64));
+        stack.release(62);
+        stack.release(64);
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn forgets_the_oldest_when_full() {
+        let mut stack = NoteStack::new();
+        for note in 0..MAX_HELD as u8 + 1 {
+            stack.press(note);
+        }
+        assert_eq!(stack.as_slice().first(), Some(&1));
+        assert_eq!(stack.last(), Some(MAX_HELD as u8));
+    }
+}
@@ -0,0 +1,36 @@
+/// Largest code the 12 bit DAC takes.
+pub const DAC_MAX: u16 = 4095;
+
+/// Volts out at the DAC's full scale, after the output amplifier.
+const FULL_SCALE_VOLTS: f32 = 3.3 * 3.0;
+
+/// Codes added to every note, trimming the output stage's offset.
+const BIAS: u16 = 5;
+
+/// DAC code for a MIDI note at 1V/oct, note 0 at 0V. Notes above the
+/// DAC's range stay at its top.
+pub fn note_to_code(note: u8) -> u16 {
+    let codes_per_note = DAC_MAX as f32 / FULL_SCALE_VOLTS / 12.0;
+    let code = (note as f32 * codes_per_note) as u16 + BIAS;
+    code.min(DAC_MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_volt_per_octave() {
+        assert_eq!(note_to_code(0), BIAS);
+        let octave = note_to_code(24) - note_to_code(12);
+        let volts = octave as f32 * FULL_SCALE_VOLTS / DAC_MAX as f32;
+        assert!((volts - 1.0).abs() < 0.01, "{}", volts);
+        assert!(note_to_code(61) > note_to_code(60));
+    }
+
+    #[test]
+    fn clamps_to_dac_range() {
+        assert_eq!(note_to_code(127), DAC_MAX);
+        assert!(note_to_code(118) < DAC_MAX);
+    }
+}
@@ -1,6 +1,12 @@
 #![no_main]
 #![no_std]
 
+use core::convert::Infallible;
+
+use common::board::{Board, Led};
+use common::controller::{Message, MidiCv};
+use embedded_hal::digital::v2::OutputPin;
+use embedded_hal::PwmPin;
 use embedded_midi::MidiMessage;
 use panic_halt as _;
 
@@ -14,23 +20,53 @@ use stm32g4xx_hal::pwr::PwrExt;
 use stm32g4xx_hal::time::{Bps, RateExtU32};
 use stm32g4xx_hal as hal;
 
-use embedded_midi::{MidiIn, Note};
+use embedded_midi::MidiIn;
 
 use cortex_m_rt::entry;
 use rtt_target::{rtt_init_print, rprintln, ChannelMode};
 
-fn note_to_voltage(note: Note) -> u16 {
-    let full_scale: f32 = 3.300_f32 * 3.0 * 1_000_000.0; //uv full-scale output;
-    let codes = 4095.0;
-    let uv_per_volt = 1_000_000_f32;
-    let notes_per_octave = 12_f32;
-    let codes_per_note: f32 = uv_per_volt / (full_scale / codes) / notes_per_octave;
+/// The module's outputs, for the shared MIDI to CV logic to drive.
+struct Outputs<'a> {
+    gates: [&'a mut dyn OutputPin<Error = Infallible>; 2],
+    cvs: [&'a mut dyn DacOut<u16>; 2],
+    mods: [&'a mut dyn PwmPin<Duty = u32>; 2],
+    note_led: &'a mut dyn OutputPin<Error = Infallible>,
+    clock_led: &'a mut dyn OutputPin<Error = Infallible>,
+}
+
+impl Board for Outputs<'_> {
+    fn set_cv(&mut self, output: usize, code: u16) {
+        self.cvs[output].set_value(code);
+    }
 
-    let note_number: u8 = note.into();
-    let code = unsafe { ((note_number as f32) * codes_per_note).to_int_unchecked::<u16>() };
-    let bias = 5;
+    fn set_gate(&mut self, output: usize, high: bool) {
+        self.gates[output].set_state(high.into()).unwrap();
+    }
 
-    code + bias
+    fn set_mod(&mut self, output: usize, value: u8) {
+        let pwm = &mut self.mods[output];
+        let duty = pwm.get_max_duty() / 128 * (value as u32);
+        pwm.set_duty(duty);
+    }
+
+    fn set_led(&mut self, led: Led, on: bool) {
+        let pin = match led {
+            Led::Note => &mut self.note_led,
+            Led::Clock => &mut self.clock_led,
+        };
+        pin.set_state(on.into()).unwrap();
+    }
+}
+
+/// The messages the module follows, or `None` for the rest.
+fn message(msg: &MidiMessage) -> Option<Message> {
+    Some(match *msg {
+        MidiMessage::NoteOn(_, note, velocity) => Message::NoteOn { note: note.into(), velocity: velocity.into() },
+        MidiMessage::NoteOff(_, note, _) => Message::NoteOff { note: note.into() },
+        MidiMessage::ControlChange(_, control, value) => Message::ControlChange { control: control.into(), value: value.into() },
+        MidiMessage::TimingClock => Message::Clock,
+        _ => return None,
+    })
 }
 
 #[entry]
@@ -46,7 +82,7 @@ fn main() -> ! {
 
     let gpioa = dp.GPIOA.split(&mut rcc);
     let mut gate_1 = gpioa.pa7.into_push_pull_output();
-    let mut _gate_2 = gpioa.pa6.into_push_pull_output();
+    let mut gate_2 = gpioa.pa6.into_push_pull_output();
 
     let mod_1_pin = gpioa.pa0.into_alternate();
     let mod_2_pin = gpioa.pa1.into_alternate();
@@ -65,39 +101,21 @@ fn main() -> ! {
     let mut led_1 = gpiob.pb8.into_push_pull_output();
     let mut led_2 = gpiob.pb7.into_push_pull_output();
 
-    led_2.set_high().unwrap();
-    cv_1.set_value(2048);
-    cv_2.set_value(1024);
+    let mut outputs = Outputs {
+        gates: [&mut gate_1, &mut gate_2],
+        cvs: [&mut cv_1, &mut cv_2],
+        mods: [&mut mod_1, &mut mod_2],
+        note_led: &mut led_1,
+        clock_led: &mut led_2,
+    };
+    let mut midi_cv = MidiCv::new();
+    midi_cv.start(&mut outputs);
     loop {
         if let Ok(msg) = midi.read() {
-            match msg {
-                MidiMessage::TimingClock => {
-                    led_2.toggle().unwrap();
-                }
-                MidiMessage::NoteOn(_, note, _) => {
-                    led_1.set_high().unwrap();
-                    gate_1.set_high().unwrap();
-                    let value = note_to_voltage(note);
-                    cv_1.set_value(value);
-                    //rprintln!("NOTE ON: {:?} -> {}", note, value)
-                },
-                MidiMessage::NoteOff(_, _note, _) => {
-                    led_1.set_low().unwrap();
-                    gate_1.set_low().unwrap();
-                    //rprintln!("NOTE OFF: {:?}", note)
-                },
-                MidiMessage::ControlChange(_, ch, value) => {
-                    let v: u8 = value.into();
-                    match ch.into() {
-                        0 => mod_1.set_duty(mod_1.get_max_duty() / 128 * (v as u32)),
-                        1 => mod_2.set_duty(mod_2.get_max_duty() / 128 * (v as u32)),
-                        _ => {}
-                    }
-                    //rprintln!("PARAM CHG: {:?}: {:?}", ch, value),
-                },
-
-                unhandled_msg => rprintln!("OTHER: {:?}", unhandled_msg),
+            match message(&msg) {
+                Some(message) => midi_cv.handle(message, &mut outputs),
+                None => rprintln!("OTHER: {:?}", msg),
             }
         }
     }
-}
\ No newline at end of file
+}